@@ -1,4 +1,5 @@
 use dxgi;
+use super::{CursorState, Window, WindowBounds};
 use std::{io, ops};
 use std::io::ErrorKind::{WouldBlock, TimedOut, NotFound};
 
@@ -11,11 +12,23 @@ pub struct Capturer {
 unsafe impl Send for Capturer {}
 
 impl Capturer {
-    pub fn new(display: Display, width: usize, height: usize) -> io::Result<Capturer> {
+    /// Desktop duplication always hands out frames at the display's current resolution, so
+    /// that's the size reported rather than the one asked for.
+    pub fn new(display: Display, _width: usize, _height: usize) -> io::Result<Capturer> {
+        let (width, height) = (display.width(), display.height());
         let inner = dxgi::Capturer::new(&display.0)?;
         Ok(Capturer { inner, width, height })
     }
 
+    /// Desktop duplication hands out frames without the cursor, so the flag has no effect here.
+    pub fn with_cursor(display: Display, width: usize, height: usize, _cursor: bool) -> io::Result<Capturer> {
+        Capturer::new(display, width, height)
+    }
+
+    pub fn draws_cursor(&self) -> bool {
+        false
+    }
+
     pub fn width(&self) -> usize {
         self.width
     }
@@ -75,4 +88,39 @@ impl Display {
     pub fn height(&self) -> usize {
         self.0.height() as usize
     }
+
+    /// The process isn't aware of per-monitor DPI, so desktop coordinates are already pixels.
+    pub fn scale_factor(&self) -> f64 {
+        1.0
+    }
+
+    pub fn name(&self) -> String {
+        String::from_utf16_lossy(self.0.name())
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.0.is_primary()
+    }
+
+    pub fn cursor_state(&self) -> Option<CursorState> {
+        self.0.cursor().map(|(x, y, pressed)| CursorState { x, y, pressed })
+    }
+
+    pub fn window_bounds(&self, id: u64) -> Option<WindowBounds> {
+        self.0.window_rect(id).map(|(x, y, width, height)| WindowBounds {
+            x,
+            y,
+            width: width.max(0) as u32,
+            height: height.max(0) as u32,
+        })
+    }
+}
+
+pub fn windows() -> io::Result<Vec<Window>> {
+    Ok(
+        dxgi::Window::all()
+            .into_iter()
+            .map(|window| Window { id: window.id, title: window.title, app: window.app })
+            .collect()
+    )
 }
@@ -12,3 +12,30 @@ cfg_if! {
         //TODO: Fallback implementation.
     }
 }
+
+/// Where the pointer is, in pixels relative to a display's top-left corner, and whether the
+/// primary button is held.
+#[derive(Clone, Copy, Debug)]
+pub struct CursorState {
+    pub x: i32,
+    pub y: i32,
+    pub pressed: bool,
+}
+
+/// A top-level application window that can be recorded.
+#[derive(Clone, Debug)]
+pub struct Window {
+    pub id: u64,
+    pub title: String,
+    pub app: String,
+}
+
+/// Where a window is, in pixels relative to a display's top-left corner. Parts of it may lie
+/// outside the display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
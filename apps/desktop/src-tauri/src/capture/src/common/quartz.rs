@@ -1,15 +1,22 @@
 use quartz;
+use super::{CursorState, Window, WindowBounds};
 use std::{io, ops, mem};
 use std::marker::PhantomData;
 use std::sync::{Arc, Mutex, TryLockError};
 
 pub struct Capturer {
     inner: quartz::Capturer,
-    frame: Arc<Mutex<Option<quartz::Frame>>>
+    frame: Arc<Mutex<Option<quartz::Frame>>>,
+    cursor: bool
 }
 
 impl Capturer {
     pub fn new(display: Display, width: usize, height: usize) -> io::Result<Capturer> {
+        Capturer::with_cursor(display, width, height, true)
+    }
+
+    /// Creates a capturer whose frames include the cursor when `cursor` is set, drawn by the display stream.
+    pub fn with_cursor(display: Display, width: usize, height: usize, cursor: bool) -> io::Result<Capturer> {
         let frame = Arc::new(Mutex::new(None));
 
         let f = frame.clone();
@@ -18,7 +25,7 @@ impl Capturer {
             width,
             height,
             quartz::PixelFormat::Argb8888,
-            Default::default(),
+            quartz::Config { cursor, ..Default::default() },
             move |inner| {
                 if let Ok(mut f) = f.lock() {
                     *f = Some(inner);
@@ -26,7 +33,11 @@ impl Capturer {
             }
         ).map_err(|_| io::Error::from(io::ErrorKind::Other))?;
 
-        Ok(Capturer { inner, frame })
+        Ok(Capturer { inner, frame, cursor })
+    }
+
+    pub fn draws_cursor(&self) -> bool {
+        self.cursor
     }
 
     pub fn width(&self) -> usize {
@@ -103,6 +114,11 @@ impl Display {
         self.0.height()
     }
 
+    /// How many pixels make up a point, the unit windows and the cursor are laid out in.
+    pub fn scale_factor(&self) -> f64 {
+        self.0.scale_factor()
+    }
+
     pub fn bytes_per_row(&self) -> usize {
         self.0.bytes_per_row()
     }
@@ -118,5 +134,35 @@ impl Display {
     pub fn samples_per_pixel(&self) -> usize {
         self.0.samples_per_pixel()
     }
+
+    pub fn name(&self) -> String {
+        format!("Display {}", self.0.id())
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.0.is_primary()
+    }
+
+    pub fn cursor_state(&self) -> Option<CursorState> {
+        self.0.cursor().map(|(x, y, pressed)| CursorState { x: x as i32, y: y as i32, pressed })
+    }
+
+    pub fn window_bounds(&self, id: u64) -> Option<WindowBounds> {
+        self.0.window_rect(id as u32).map(|rect| WindowBounds {
+            x: rect.origin.x.round() as i32,
+            y: rect.origin.y.round() as i32,
+            width: rect.size.width.round() as u32,
+            height: rect.size.height.round() as u32,
+        })
+    }
+}
+
+pub fn windows() -> io::Result<Vec<Window>> {
+    Ok(
+        quartz::Window::on_screen()
+            .into_iter()
+            .map(|window| Window { id: window.id as u64, title: window.title, app: window.owner })
+            .collect()
+    )
 }
 
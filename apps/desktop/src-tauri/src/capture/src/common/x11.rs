@@ -1,4 +1,5 @@
 use x11;
+use super::{CursorState, Window, WindowBounds};
 use std::{io, ops};
 use std::rc::Rc;
 
@@ -11,6 +12,15 @@ impl Capturer {
         x11::Capturer::new(display.0).map(Capturer)
     }
 
+    /// The raw X11 grabber never includes the cursor, so the flag has no effect here.
+    pub fn with_cursor(display: Display, width: usize, height: usize, _cursor: bool) -> io::Result<Capturer> {
+        Capturer::new(display, width, height)
+    }
+
+    pub fn draws_cursor(&self) -> bool {
+        false
+    }
+
     pub fn width(&self) -> usize {
         self.0.display().rect().w as usize
     }
@@ -77,4 +87,47 @@ impl Display {
     pub fn height(&self) -> usize {
         self.0.rect().h as usize
     }
+
+    /// X11 lays everything out in pixels, so there is no separate logical size.
+    pub fn scale_factor(&self) -> f64 {
+        1.0
+    }
+
+    pub fn name(&self) -> String {
+        let rect = self.0.rect();
+        format!("Display at {},{}", rect.x, rect.y)
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.0.is_default()
+    }
+
+    pub fn cursor_state(&self) -> Option<CursorState> {
+        let rect = self.0.rect();
+        self.0.cursor().map(|(x, y, pressed)| CursorState {
+            x: x as i32 - rect.x as i32,
+            y: y as i32 - rect.y as i32,
+            pressed,
+        })
+    }
+
+    pub fn window_bounds(&self, id: u64) -> Option<WindowBounds> {
+        let rect = self.0.rect();
+        self.0.window_rect(id as u32).map(|window| WindowBounds {
+            x: window.x as i32 - rect.x as i32,
+            y: window.y as i32 - rect.y as i32,
+            width: window.w as u32,
+            height: window.h as u32,
+        })
+    }
+}
+
+pub fn windows() -> io::Result<Vec<Window>> {
+    let display = Display::primary()?;
+    Ok(
+        display.0.windows()
+            .into_iter()
+            .map(|window| Window { id: window.id as u64, title: window.title, app: window.class })
+            .collect()
+    )
 }
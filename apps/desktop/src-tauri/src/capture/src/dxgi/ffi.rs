@@ -1,3 +1,4 @@
+use libc::c_void;
 use winapi::{
     GUID,
     HRESULT,
@@ -42,6 +43,65 @@ pub const IID_ID3D11TEXTURE2D: GUID = GUID {
     Data4: [154, 180, 72, 149, 53, 211, 79, 156]
 };
 
+pub const VK_LBUTTON: i32 = 0x01;
+
+#[repr(C)]
+pub struct CursorPoint {
+    pub x: i32,
+    pub y: i32
+}
+
+pub type WindowHandle = *mut c_void;
+pub type EnumWindowsProc = unsafe extern "system" fn(WindowHandle, isize) -> i32;
+
+pub const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+pub const DWMWA_EXTENDED_FRAME_BOUNDS: u32 = 9;
+
+#[repr(C)]
+pub struct WindowRect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32
+}
+
+#[link(name="user32")]
+extern "system" {
+    pub fn GetCursorPos(point: *mut CursorPoint) -> i32;
+    pub fn GetAsyncKeyState(key: i32) -> i16;
+
+    pub fn EnumWindows(callback: EnumWindowsProc, lparam: isize) -> i32;
+    pub fn IsWindow(hwnd: WindowHandle) -> i32;
+    pub fn IsWindowVisible(hwnd: WindowHandle) -> i32;
+    pub fn IsIconic(hwnd: WindowHandle) -> i32;
+    pub fn GetWindowTextLengthW(hwnd: WindowHandle) -> i32;
+    pub fn GetWindowTextW(hwnd: WindowHandle, text: *mut u16, max_count: i32) -> i32;
+    pub fn GetWindowRect(hwnd: WindowHandle, rect: *mut WindowRect) -> i32;
+    pub fn GetWindowThreadProcessId(hwnd: WindowHandle, process_id: *mut u32) -> u32;
+}
+
+#[link(name="dwmapi")]
+extern "system" {
+    pub fn DwmGetWindowAttribute(
+        hwnd: WindowHandle,
+        attribute: u32,
+        value: *mut c_void,
+        size: u32
+    ) -> HRESULT;
+}
+
+#[link(name="kernel32")]
+extern "system" {
+    pub fn OpenProcess(access: u32, inherit_handle: i32, process_id: u32) -> *mut c_void;
+    pub fn QueryFullProcessImageNameW(
+        process: *mut c_void,
+        flags: u32,
+        name: *mut u16,
+        size: *mut u32
+    ) -> i32;
+    pub fn CloseHandle(handle: *mut c_void) -> i32;
+}
+
 #[link(name="dxgi")]
 #[link(name="d3d11")]
 extern "system" {
@@ -33,6 +33,9 @@ use winapi::{
 };
 
 mod ffi;
+mod window;
+
+pub use self::window::Window;
 
 //TODO: Split up into files.
 
@@ -395,6 +398,34 @@ impl Display {
         self.desc.DesktopCoordinates.top
     }
 
+    /// Windows always places the primary monitor at the desktop origin.
+    pub fn is_primary(&self) -> bool {
+        self.desc.DesktopCoordinates.left == 0 &&
+        self.desc.DesktopCoordinates.top == 0
+    }
+
+    /// The pointer position relative to this output and whether the left button is held.
+    pub fn cursor(&self) -> Option<(LONG, LONG, bool)> {
+        unsafe {
+            let mut point = CursorPoint { x: 0, y: 0 };
+            if GetCursorPos(&mut point) == 0 {
+                return None;
+            }
+
+            let pressed = GetAsyncKeyState(VK_LBUTTON) as u16 & 0x8000 != 0;
+            Some((
+                point.x - self.desc.DesktopCoordinates.left,
+                point.y - self.desc.DesktopCoordinates.top,
+                pressed
+            ))
+        }
+    }
+
+    /// The output's top-left corner in desktop coordinates.
+    pub fn origin(&self) -> (LONG, LONG) {
+        (self.desc.DesktopCoordinates.left, self.desc.DesktopCoordinates.top)
+    }
+
     pub fn rotation(&self) -> DXGI_MODE_ROTATION {
         self.desc.Rotation
     }
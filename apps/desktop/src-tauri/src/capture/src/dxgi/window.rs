@@ -0,0 +1,105 @@
+use super::ffi::*;
+use super::Display;
+use libc::c_void;
+use std::mem;
+use std::path::Path;
+
+/// A visible top-level window. Ids are the window handles.
+#[derive(Clone, Debug)]
+pub struct Window {
+    pub id: u64,
+    pub title: String,
+    pub app: String,
+}
+
+impl Window {
+    /// Visible, titled, non-minimized top-level windows, front to back.
+    pub fn all() -> Vec<Window> {
+        let mut handles: Vec<WindowHandle> = Vec::new();
+        unsafe {
+            EnumWindows(collect_window, &mut handles as *mut Vec<WindowHandle> as isize);
+        }
+
+        handles.into_iter()
+            .filter(|&hwnd| unsafe { IsWindowVisible(hwnd) != 0 && IsIconic(hwnd) == 0 })
+            .filter_map(|hwnd| {
+                let title = window_title(hwnd);
+                if title.is_empty() {
+                    return None;
+                }
+                Some(Window { id: hwnd as u64, title, app: process_name(hwnd).unwrap_or_default() })
+            })
+            .collect()
+    }
+}
+
+impl Display {
+    /// The window's visible frame relative to this output, as `(x, y, width, height)`, or `None`
+    /// once it has been closed.
+    pub fn window_rect(&self, id: u64) -> Option<(i32, i32, i32, i32)> {
+        let hwnd = id as WindowHandle;
+        unsafe {
+            if IsWindow(hwnd) == 0 {
+                return None;
+            }
+
+            // The extended frame bounds leave out the invisible resize borders and drop shadow
+            // that GetWindowRect includes.
+            let mut rect: WindowRect = mem::zeroed();
+            let hr = DwmGetWindowAttribute(
+                hwnd,
+                DWMWA_EXTENDED_FRAME_BOUNDS,
+                &mut rect as *mut WindowRect as *mut c_void,
+                mem::size_of::<WindowRect>() as u32
+            );
+            if hr != 0 && GetWindowRect(hwnd, &mut rect) == 0 {
+                return None;
+            }
+
+            let (left, top) = self.origin();
+            Some((rect.left - left, rect.top - top, rect.right - rect.left, rect.bottom - rect.top))
+        }
+    }
+}
+
+unsafe extern "system" fn collect_window(hwnd: WindowHandle, lparam: isize) -> i32 {
+    let handles = &mut *(lparam as *mut Vec<WindowHandle>);
+    handles.push(hwnd);
+    1
+}
+
+fn window_title(hwnd: WindowHandle) -> String {
+    unsafe {
+        let len = GetWindowTextLengthW(hwnd);
+        if len <= 0 {
+            return String::new();
+        }
+
+        let mut buffer = vec![0u16; len as usize + 1];
+        let copied = GetWindowTextW(hwnd, buffer.as_mut_ptr(), buffer.len() as i32);
+        String::from_utf16_lossy(&buffer[..copied.max(0) as usize])
+    }
+}
+
+/// The executable name of the process owning the window, e.g. `chrome`.
+fn process_name(hwnd: WindowHandle) -> Option<String> {
+    unsafe {
+        let mut process_id = 0;
+        GetWindowThreadProcessId(hwnd, &mut process_id);
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, process_id);
+        if process.is_null() {
+            return None;
+        }
+
+        let mut buffer = vec![0u16; 1024];
+        let mut size = buffer.len() as u32;
+        let ok = QueryFullProcessImageNameW(process, 0, buffer.as_mut_ptr(), &mut size);
+        CloseHandle(process);
+        if ok == 0 {
+            return None;
+        }
+
+        let path = String::from_utf16_lossy(&buffer[..size as usize]);
+        Path::new(&path).file_stem().map(|stem| stem.to_string_lossy().into_owned())
+    }
+}
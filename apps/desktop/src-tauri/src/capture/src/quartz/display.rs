@@ -1,5 +1,5 @@
 use super::ffi::*;
-use std::mem;
+use std::{mem, ptr};
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 #[repr(C)]
@@ -46,6 +46,18 @@ impl Display {
         }
     }
 
+    /// Pixels per point, 2.0 on a Retina display in its default mode.
+    pub fn scale_factor(self) -> f64 {
+        unsafe {
+            let bounds = CGDisplayBounds(self.0);
+            if bounds.size.width > 0.0 {
+                self.width() as f64 / bounds.size.width
+            } else {
+                1.0
+            }
+        }
+    }
+
     pub fn bytes_per_row(self) -> usize {
         unsafe {
             CGDisplayBytesPerRow(self.0) as usize
@@ -64,6 +76,26 @@ impl Display {
         unsafe { CGDisplaySamplesPerPixel(self.0) as usize }
     }
 
+    /// The pointer position in pixels relative to this display and whether the left button is held.
+    pub fn cursor(self) -> Option<(f64, f64, bool)> {
+        unsafe {
+            let event = CGEventCreate(ptr::null_mut());
+            if event.is_null() {
+                return None;
+            }
+
+            let location = CGEventGetLocation(event);
+            CFRelease(event);
+
+            // Event locations are in points while frames are captured in pixels.
+            let bounds = CGDisplayBounds(self.0);
+            let scale = self.scale_factor();
+            let pressed = CGEventSourceButtonState(CG_EVENT_SOURCE_STATE_COMBINED_SESSION_STATE, CG_MOUSE_BUTTON_LEFT);
+
+            Some(((location.x - bounds.origin.x) * scale, (location.y - bounds.origin.y) * scale, pressed))
+        }
+    }
+
     pub fn is_builtin(self) -> bool {
         unsafe { CGDisplayIsBuiltin(self.0) != 0 }
     }
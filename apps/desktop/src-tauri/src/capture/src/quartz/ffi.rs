@@ -13,6 +13,7 @@ pub type IOSurfaceRef = *mut c_void;
 pub type DispatchQueue = *mut c_void;
 pub type DispatchQueueAttr = *mut c_void;
 pub type CFAllocatorRef = *mut c_void;
+pub type CFArrayRef = *mut c_void;
 
 #[repr(C)]
 pub struct CFDictionaryKeyCallBacks {
@@ -26,6 +27,39 @@ pub struct CFDictionaryValueCallBacks {
     version: i32
 }
 
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CGPoint {
+    pub x: f64,
+    pub y: f64
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CGSize {
+    pub width: f64,
+    pub height: f64
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CGRect {
+    pub origin: CGPoint,
+    pub size: CGSize
+}
+
+pub type CGEventRef = *mut c_void;
+
+pub const CG_EVENT_SOURCE_STATE_COMBINED_SESSION_STATE: i32 = 0;
+pub const CG_MOUSE_BUTTON_LEFT: u32 = 0;
+
+pub const CG_WINDOW_LIST_OPTION_ON_SCREEN_ONLY: u32 = 1 << 0;
+pub const CG_WINDOW_LIST_OPTION_INCLUDING_WINDOW: u32 = 1 << 3;
+pub const CG_WINDOW_LIST_EXCLUDE_DESKTOP_ELEMENTS: u32 = 1 << 4;
+pub const CG_NULL_WINDOW_ID: u32 = 0;
+
+pub const CF_STRING_ENCODING_UTF8: u32 = 0x0800_0100;
+
 pub enum CGDisplayMode {}
 pub type CGDisplayModeRef = *mut CGDisplayMode;
 
@@ -186,6 +220,20 @@ extern {
     pub fn CGDisplayIsMain(display: u32) -> i32;
     pub fn CGDisplayIsActive(display: u32) -> i32;
     pub fn CGDisplayIsOnline(display: u32) -> i32;
+    pub fn CGDisplayBounds(display: u32) -> CGRect;
+
+    pub fn CGEventCreate(source: *mut c_void) -> CGEventRef;
+    pub fn CGEventGetLocation(event: CGEventRef) -> CGPoint;
+    pub fn CGEventSourceButtonState(state_id: i32, button: u32) -> bool;
+
+    pub static kCGWindowNumber: CFStringRef;
+    pub static kCGWindowName: CFStringRef;
+    pub static kCGWindowOwnerName: CFStringRef;
+    pub static kCGWindowBounds: CFStringRef;
+    pub static kCGWindowLayer: CFStringRef;
+
+    pub fn CGWindowListCopyWindowInfo(option: u32, relative_to_window: u32) -> CFArrayRef;
+    pub fn CGRectMakeWithDictionaryRepresentation(dict: CFDictionaryRef, rect: *mut CGRect) -> bool;
 
     // IOSurface
 
@@ -240,6 +288,29 @@ extern {
         valueCallBacks: *const CFDictionaryValueCallBacks
     ) -> CFDictionaryRef;
 
+    pub fn CFNumberGetValue(
+        number: CFNumberRef,
+        theType: CFNumberType,
+        valuePtr: *mut c_void
+    ) -> bool;
+
+    pub fn CFDictionaryGetValue(
+        theDict: CFDictionaryRef,
+        key: *const c_void
+    ) -> *const c_void;
+
+    pub fn CFArrayGetCount(theArray: CFArrayRef) -> i64;
+    pub fn CFArrayGetValueAtIndex(theArray: CFArrayRef, idx: i64) -> *const c_void;
+
+    pub fn CFStringGetLength(theString: CFStringRef) -> i64;
+    pub fn CFStringGetMaximumSizeForEncoding(length: i64, encoding: u32) -> i64;
+    pub fn CFStringGetCString(
+        theString: CFStringRef,
+        buffer: *mut i8,
+        bufferSize: i64,
+        encoding: u32
+    ) -> bool;
+
     pub fn CFRetain(cf: *const c_void);
     pub fn CFRelease(cf: *const c_void);
 }
\ No newline at end of file
@@ -3,9 +3,11 @@ mod config;
 mod display;
 mod ffi;
 mod frame;
+mod window;
 
 pub use self::capturer::Capturer;
 pub use self::config::Config;
 pub use self::display::Display;
 pub use self::ffi::{CGError, PixelFormat};
 pub use self::frame::Frame;
+pub use self::window::Window;
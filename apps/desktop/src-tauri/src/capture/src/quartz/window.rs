@@ -0,0 +1,116 @@
+use super::ffi::*;
+use super::Display;
+use libc::c_void;
+use std::ptr;
+
+/// An on-screen window as reported by the window server. Bounds are in global points.
+#[derive(Clone, Debug)]
+pub struct Window {
+    pub id: u32,
+    pub title: String,
+    pub owner: String,
+    pub bounds: CGRect,
+}
+
+impl Window {
+    /// Normal application windows (layer 0) currently on screen, front to back.
+    pub fn on_screen() -> Vec<Window> {
+        window_list(CG_WINDOW_LIST_OPTION_ON_SCREEN_ONLY | CG_WINDOW_LIST_EXCLUDE_DESKTOP_ELEMENTS, CG_NULL_WINDOW_ID)
+    }
+
+    /// Looks up a single window, or `None` once it has been closed.
+    pub fn with_id(id: u32) -> Option<Window> {
+        window_list(CG_WINDOW_LIST_OPTION_INCLUDING_WINDOW, id)
+            .into_iter()
+            .find(|window| window.id == id)
+    }
+}
+
+impl Display {
+    /// The window's bounds in this display's pixels, relative to its top-left corner.
+    pub fn window_rect(self, id: u32) -> Option<CGRect> {
+        let window = Window::with_id(id)?;
+        let bounds = unsafe { CGDisplayBounds(self.id()) };
+        let scale = self.width() as f64 / bounds.size.width;
+
+        Some(CGRect {
+            origin: CGPoint {
+                x: (window.bounds.origin.x - bounds.origin.x) * scale,
+                y: (window.bounds.origin.y - bounds.origin.y) * scale,
+            },
+            size: CGSize {
+                width: window.bounds.size.width * scale,
+                height: window.bounds.size.height * scale,
+            },
+        })
+    }
+}
+
+fn window_list(option: u32, relative_to: u32) -> Vec<Window> {
+    unsafe {
+        let list = CGWindowListCopyWindowInfo(option, relative_to);
+        if list.is_null() {
+            return Vec::new();
+        }
+
+        let mut windows = Vec::new();
+        for i in 0..CFArrayGetCount(list) {
+            let info = CFArrayGetValueAtIndex(list, i) as CFDictionaryRef;
+            if number(info, kCGWindowLayer) != Some(0) {
+                continue;
+            }
+            let id = match number(info, kCGWindowNumber) {
+                Some(id) => id as u32,
+                None => continue
+            };
+
+            let mut bounds = CGRect { origin: CGPoint { x: 0.0, y: 0.0 }, size: CGSize { width: 0.0, height: 0.0 } };
+            let bounds_dict = CFDictionaryGetValue(info, kCGWindowBounds as *const c_void);
+            if bounds_dict.is_null() || !CGRectMakeWithDictionaryRepresentation(bounds_dict as CFDictionaryRef, &mut bounds) {
+                continue;
+            }
+
+            windows.push(Window {
+                id,
+                // Titles of other apps' windows are only available with screen recording permission.
+                title: string(info, kCGWindowName).unwrap_or_default(),
+                owner: string(info, kCGWindowOwnerName).unwrap_or_default(),
+                bounds,
+            });
+        }
+
+        CFRelease(list);
+        windows
+    }
+}
+
+unsafe fn number(dict: CFDictionaryRef, key: CFStringRef) -> Option<i64> {
+    let value = CFDictionaryGetValue(dict, key as *const c_void);
+    if value.is_null() {
+        return None;
+    }
+
+    let mut result: i64 = 0;
+    if CFNumberGetValue(value as CFNumberRef, CFNumberType::SInt64, &mut result as *mut i64 as *mut c_void) {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+unsafe fn string(dict: CFDictionaryRef, key: CFStringRef) -> Option<String> {
+    let value = CFDictionaryGetValue(dict, key as *const c_void) as CFStringRef;
+    if value.is_null() {
+        return None;
+    }
+
+    let len = CFStringGetMaximumSizeForEncoding(CFStringGetLength(value), CF_STRING_ENCODING_UTF8) + 1;
+    let mut buffer = vec![0u8; len as usize];
+    if !CFStringGetCString(value, buffer.as_mut_ptr() as *mut i8, len, CF_STRING_ENCODING_UTF8) {
+        return None;
+    }
+
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    buffer.truncate(end);
+    Some(String::from_utf8_lossy(&buffer).into_owned())
+}
@@ -1,4 +1,6 @@
+use std::ptr;
 use std::rc::Rc;
+use libc;
 use super::Server;
 use super::ffi::*;
 
@@ -32,4 +34,19 @@ impl Display {
     pub fn is_default(&self) -> bool { self.default }
     pub fn rect(&self) -> Rect { self.rect }
     pub fn root(&self) -> xcb_window_t { self.root }
+
+    /// The pointer position on the root window and whether the left button is held.
+    pub fn cursor(&self) -> Option<(i16, i16, bool)> {
+        unsafe {
+            let cookie = xcb_query_pointer(self.server.raw(), self.root);
+            let reply = xcb_query_pointer_reply(self.server.raw(), cookie, ptr::null_mut());
+            if reply.is_null() {
+                return None;
+            }
+
+            let cursor = ((*reply).root_x, (*reply).root_y, (*reply).mask & XCB_KEY_BUT_MASK_BUTTON_1 != 0);
+            libc::free(reply as *mut _);
+            Some(cursor)
+        }
+    }
 }
@@ -3,8 +3,10 @@ mod display;
 mod ffi;
 mod iter;
 mod server;
+mod window;
 
 pub use self::capturer::*;
 pub use self::display::*;
 pub use self::iter::*;
 pub use self::server::*;
+pub use self::window::*;
@@ -0,0 +1,110 @@
+use std::{ptr, slice};
+use libc;
+use super::{Display, Rect};
+use super::ffi::*;
+
+/// A top-level window as listed by the window manager.
+#[derive(Clone, Debug)]
+pub struct Window {
+    pub id: xcb_window_t,
+    pub title: String,
+    pub class: String,
+}
+
+impl Display {
+    /// The windows in the window manager's `_NET_CLIENT_LIST`, oldest first.
+    pub fn windows(&self) -> Vec<Window> {
+        let c = self.server().raw();
+        unsafe {
+            let client_list = match intern_atom(c, "_NET_CLIENT_LIST") {
+                Some(atom) => atom,
+                None => return Vec::new()
+            };
+            let ids = match property(c, self.root(), client_list, XCB_ATOM_WINDOW) {
+                Some(value) => value,
+                None => return Vec::new()
+            };
+            let net_wm_name = intern_atom(c, "_NET_WM_NAME");
+
+            ids.chunks_exact(4)
+                .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                .map(|id| {
+                    let title = net_wm_name
+                        .and_then(|atom| property(c, id, atom, XCB_GET_PROPERTY_TYPE_ANY))
+                        .or_else(|| property(c, id, XCB_ATOM_WM_NAME, XCB_GET_PROPERTY_TYPE_ANY))
+                        .map(|value| String::from_utf8_lossy(&value).into_owned())
+                        .unwrap_or_default();
+
+                    // WM_CLASS holds the instance and class names, each NUL-terminated.
+                    let class = property(c, id, XCB_ATOM_WM_CLASS, XCB_ATOM_STRING)
+                        .and_then(|value| {
+                            value.split(|&b| b == 0)
+                                .filter(|part| !part.is_empty())
+                                .last()
+                                .map(|part| String::from_utf8_lossy(part).into_owned())
+                        })
+                        .unwrap_or_default();
+
+                    Window { id, title, class }
+                })
+                .collect()
+        }
+    }
+
+    /// Where the window currently is on the root window, or `None` once it has been closed.
+    pub fn window_rect(&self, window: xcb_window_t) -> Option<Rect> {
+        let c = self.server().raw();
+        unsafe {
+            let geometry = xcb_get_geometry_reply(c, xcb_get_geometry(c, window), ptr::null_mut());
+            if geometry.is_null() {
+                return None;
+            }
+            let (w, h) = ((*geometry).width, (*geometry).height);
+            libc::free(geometry as *mut _);
+
+            let cookie = xcb_translate_coordinates(c, window, self.root(), 0, 0);
+            let position = xcb_translate_coordinates_reply(c, cookie, ptr::null_mut());
+            if position.is_null() {
+                return None;
+            }
+            let (x, y) = ((*position).dst_x, (*position).dst_y);
+            libc::free(position as *mut _);
+
+            Some(Rect { x, y, w, h })
+        }
+    }
+}
+
+unsafe fn intern_atom(c: *mut xcb_connection_t, name: &str) -> Option<xcb_atom_t> {
+    let cookie = xcb_intern_atom(c, 1, name.len() as u16, name.as_ptr() as *const i8);
+    let reply = xcb_intern_atom_reply(c, cookie, ptr::null_mut());
+    if reply.is_null() {
+        return None;
+    }
+
+    let atom = (*reply).atom;
+    libc::free(reply as *mut _);
+    if atom == 0 { None } else { Some(atom) }
+}
+
+unsafe fn property(
+    c: *mut xcb_connection_t,
+    window: xcb_window_t,
+    property: xcb_atom_t,
+    type_: xcb_atom_t
+) -> Option<Vec<u8>> {
+    let cookie = xcb_get_property(c, 0, window, property, type_, 0, u32::max_value());
+    let reply = xcb_get_property_reply(c, cookie, ptr::null_mut());
+    if reply.is_null() {
+        return None;
+    }
+
+    let len = xcb_get_property_value_length(reply);
+    let value = if len > 0 {
+        Some(slice::from_raw_parts(xcb_get_property_value(reply) as *const u8, len as usize).to_vec())
+    } else {
+        None
+    };
+    libc::free(reply as *mut _);
+    value
+}
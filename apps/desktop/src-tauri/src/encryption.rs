@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use aes_gcm::{Aes256Gcm, Key, KeyInit};
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use aes_gcm::aead::OsRng;
+
+/// Files are encrypted in blocks of this much plaintext, so neither direction holds more than one
+/// block in memory.
+const BLOCK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+/// The 12-byte AES-GCM nonce minus the block counter and last-block flag the STREAM construction
+/// appends.
+const NONCE_PREFIX_SIZE: usize = 7;
+
+/// Encrypts `input` into `output` with AES-256-GCM: a random nonce prefix followed by the blocks,
+/// each carrying its own tag. The last block is marked as such, so a truncated file fails to
+/// decrypt instead of coming out short.
+pub fn encrypt_file(key: &[u8; 32], input: &Path, output: &Path) -> Result<(), String> {
+    let mut reader = BufReader::new(File::open(input).map_err(|e| format!("Failed to open {:?}: {}", input, e))?);
+    let mut writer = BufWriter::new(File::create(output).map_err(|e| format!("Failed to create {:?}: {}", output, e))?);
+
+    let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+    OsRng.fill_bytes(&mut nonce_prefix);
+    writer.write_all(&nonce_prefix).map_err(|e| format!("Failed to write {:?}: {}", output, e))?;
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let mut encryptor = EncryptorBE32::from_aead(cipher, GenericArray::from_slice(&nonce_prefix));
+
+    let mut block = read_block(&mut reader, BLOCK_SIZE)?;
+    loop {
+        let next_block = read_block(&mut reader, BLOCK_SIZE)?;
+        if next_block.is_empty() {
+            let ciphertext = encryptor.encrypt_last(block.as_slice()).map_err(|_| "Failed to encrypt the last block".to_string())?;
+            writer.write_all(&ciphertext).map_err(|e| format!("Failed to write {:?}: {}", output, e))?;
+            break;
+        }
+
+        let ciphertext = encryptor.encrypt_next(block.as_slice()).map_err(|_| "Failed to encrypt a block".to_string())?;
+        writer.write_all(&ciphertext).map_err(|e| format!("Failed to write {:?}: {}", output, e))?;
+        block = next_block;
+    }
+
+    writer.flush().map_err(|e| format!("Failed to write {:?}: {}", output, e))
+}
+
+/// Reverses `encrypt_file`, failing if any block was tampered with or the file was truncated.
+pub fn decrypt_file(key: &[u8; 32], input: &Path, output: &Path) -> Result<(), String> {
+    let mut reader = BufReader::new(File::open(input).map_err(|e| format!("Failed to open {:?}: {}", input, e))?);
+    let mut writer = BufWriter::new(File::create(output).map_err(|e| format!("Failed to create {:?}: {}", output, e))?);
+
+    let nonce_prefix = read_block(&mut reader, NONCE_PREFIX_SIZE)?;
+    if nonce_prefix.len() != NONCE_PREFIX_SIZE {
+        return Err(format!("{:?} is too short to be an encrypted chunk", input));
+    }
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let mut decryptor = DecryptorBE32::from_aead(cipher, GenericArray::from_slice(&nonce_prefix));
+
+    let mut block = read_block(&mut reader, BLOCK_SIZE + TAG_SIZE)?;
+    loop {
+        let next_block = read_block(&mut reader, BLOCK_SIZE + TAG_SIZE)?;
+        if next_block.is_empty() {
+            let plaintext = decryptor.decrypt_last(block.as_slice()).map_err(|_| format!("Failed to decrypt {:?}, the key is wrong or the file is corrupt", input))?;
+            writer.write_all(&plaintext).map_err(|e| format!("Failed to write {:?}: {}", output, e))?;
+            break;
+        }
+
+        let plaintext = decryptor.decrypt_next(block.as_slice()).map_err(|_| format!("Failed to decrypt {:?}, the key is wrong or the file is corrupt", input))?;
+        writer.write_all(&plaintext).map_err(|e| format!("Failed to write {:?}: {}", output, e))?;
+        block = next_block;
+    }
+
+    writer.flush().map_err(|e| format!("Failed to write {:?}: {}", output, e))
+}
+
+/// Decrypts a chunk downloaded from an encrypted recording into `output_path`.
+#[tauri::command]
+pub async fn decrypt_chunk(key: [u8; 32], input_path: String, output_path: String) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || decrypt_file(&key, Path::new(&input_path), Path::new(&output_path)))
+        .await
+        .map_err(|e| format!("Decryption task failed: {}", e))?
+}
+
+/// Reads up to `size` bytes, fewer only at the end of the file.
+fn read_block(reader: &mut impl Read, size: usize) -> Result<Vec<u8>, String> {
+    let mut block = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut block).map_err(|e| format!("Failed to read: {}", e))?;
+    Ok(block)
+}
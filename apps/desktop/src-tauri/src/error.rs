@@ -0,0 +1,126 @@
+use std::fmt;
+use serde::{Serialize, Serializer, ser::SerializeStruct};
+
+/// Why a recording command failed. Serialized as `{ "code": ..., "message": ... }`, so the
+/// frontend can branch on the code and still show the message.
+#[derive(Debug, Clone)]
+pub enum RecordingError {
+    /// No microphone or system audio device could be opened.
+    NoInputDevice(String),
+    /// The ffmpeg binary is missing or could not be started.
+    FfmpegNotFound(String),
+    /// The display or window to capture is gone or can't be captured.
+    DisplayUnavailable(String),
+    /// Segments or other recording files could not be uploaded, even after retries.
+    UploadFailed(String),
+    /// An ffmpeg process or the capture failed while recording.
+    PipelineFailed(String),
+    /// The recording options are invalid.
+    InvalidOptions(String),
+    /// Not enough disk space to start recording.
+    InsufficientDiskSpace { available_mb: u64, required_mb: u64 },
+    /// The recording ended before a single segment was written.
+    RecordingTooShort { segment_duration_secs: u64 },
+    AlreadyRecording,
+    /// The command can't run while a recording or its uploads are in progress.
+    RecordingInProgress(String),
+    Other(String),
+}
+
+impl RecordingError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RecordingError::NoInputDevice(_) => "NoInputDevice",
+            RecordingError::FfmpegNotFound(_) => "FfmpegNotFound",
+            RecordingError::DisplayUnavailable(_) => "DisplayUnavailable",
+            RecordingError::UploadFailed(_) => "UploadFailed",
+            RecordingError::PipelineFailed(_) => "PipelineFailed",
+            RecordingError::InvalidOptions(_) => "InvalidOptions",
+            RecordingError::InsufficientDiskSpace { .. } => "InsufficientDiskSpace",
+            RecordingError::RecordingTooShort { .. } => "RecordingTooShort",
+            RecordingError::AlreadyRecording => "AlreadyRecording",
+            RecordingError::RecordingInProgress(_) => "RecordingInProgress",
+            RecordingError::Other(_) => "Other",
+        }
+    }
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::NoInputDevice(message)
+            | RecordingError::FfmpegNotFound(message)
+            | RecordingError::DisplayUnavailable(message)
+            | RecordingError::UploadFailed(message)
+            | RecordingError::PipelineFailed(message)
+            | RecordingError::InvalidOptions(message)
+            | RecordingError::RecordingInProgress(message)
+            | RecordingError::Other(message) => write!(f, "{}", message),
+            RecordingError::InsufficientDiskSpace { available_mb, required_mb } => {
+                write!(f, "Not enough disk space to start recording: {} MB available, {} MB required", available_mb, required_mb)
+            },
+            RecordingError::RecordingTooShort { segment_duration_secs } => {
+                write!(f, "Recording too short, no data was captured. Recordings need to run for at least one {}s segment", segment_duration_secs)
+            },
+            RecordingError::AlreadyRecording => write!(f, "A recording is already in progress"),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl Serialize for RecordingError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("RecordingError", 2)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+/// Why a file could not be uploaded.
+#[derive(Debug, Clone)]
+pub enum UploadError {
+    /// The upload target is misconfigured, so retrying won't help.
+    InvalidTarget(String),
+    /// The file could not be read or encrypted.
+    File(String),
+    /// The request failed or the server rejected the file.
+    Failed(String),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::InvalidTarget(message) | UploadError::File(message) | UploadError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+impl From<UploadError> for RecordingError {
+    fn from(error: UploadError) -> Self {
+        RecordingError::UploadFailed(error.to_string())
+    }
+}
+
+/// Most helpers still return plain strings.
+impl From<UploadError> for String {
+    fn from(error: UploadError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Errors that haven't been classified yet, which most helpers still return as plain strings.
+impl From<String> for RecordingError {
+    fn from(message: String) -> Self {
+        RecordingError::Other(message)
+    }
+}
+
+impl From<&str> for RecordingError {
+    fn from(message: &str) -> Self {
+        RecordingError::Other(message.to_string())
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+use tauri::AppHandle;
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
+
+/// Number of log lines kept in memory for `dump_logs`.
+const RECENT_LOG_LINES: usize = 5000;
+
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+static RECENT_LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Keeps the most recent formatted log lines so they can be written out for a bug report.
+struct RecentLogsWriter;
+
+impl Write for RecentLogsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut recent_logs = RECENT_LOGS.lock().unwrap_or_else(|e| e.into_inner());
+        for line in String::from_utf8_lossy(buf).lines() {
+            if recent_logs.len() >= RECENT_LOG_LINES {
+                recent_logs.pop_front();
+            }
+            recent_logs.push_back(line.to_string());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Sets up logging to stdout and to the in-memory buffer. `RUST_LOG` overrides the default level.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL));
+    let (filter, filter_handle) = reload::Layer::new(filter);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .with(fmt::layer().with_ansi(false).with_writer(|| RecentLogsWriter))
+        .init();
+
+    let _ = LOG_FILTER.set(filter_handle);
+}
+
+/// Changes which logs are recorded, e.g. `debug` or `cap=trace,info`.
+#[tauri::command]
+pub fn set_log_level(level: String) -> Result<(), String> {
+    let filter = EnvFilter::try_new(&level).map_err(|e| format!("Invalid log level '{}': {}", level, e))?;
+    LOG_FILTER.get()
+        .ok_or("Logging has not been initialized".to_string())?
+        .reload(filter)
+        .map_err(|e| format!("Failed to set log level: {}", e))
+}
+
+/// Writes the recent logs to a file in the app log directory and returns its path, so it can be
+/// attached to an issue.
+#[tauri::command]
+pub fn dump_logs(app_handle: AppHandle) -> Result<String, String> {
+    let log_dir = app_handle.path_resolver().app_log_dir()
+        .ok_or("Failed to resolve the app log directory".to_string())?;
+    std::fs::create_dir_all(&log_dir).map_err(|e| format!("Failed to create {:?}: {}", log_dir, e))?;
+
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let log_path = log_dir.join(format!("cap-{}.log", timestamp));
+
+    let recent_logs = RECENT_LOGS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut contents = recent_logs.iter().cloned().collect::<Vec<_>>().join("\n");
+    contents.push('\n');
+    drop(recent_logs);
+
+    std::fs::write(&log_path, contents).map_err(|e| format!("Failed to write {:?}: {}", log_path, e))?;
+
+    Ok(log_path.to_string_lossy().to_string())
+}
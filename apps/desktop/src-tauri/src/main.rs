@@ -2,7 +2,6 @@
 
 use std::collections::LinkedList;
 use std::sync::{Arc};
-use std::path::PathBuf;
 use cpal::Devices;
 use regex::Regex;
 use tokio::sync::Mutex;
@@ -14,14 +13,20 @@ use window_shadows::set_shadow;
 use tauri_plugin_positioner::{WindowExt, Position};
 use tauri_plugin_oauth::start;
 
+mod encryption;
+mod error;
 mod recording;
 mod upload;
 mod utils;
 mod media;
+mod logging;
 
-use recording::{RecordingState, start_dual_recording, stop_all_recordings};
-use media::{enumerate_audio_devices};
-use utils::{has_screen_capture_access};
+use recording::{RecordingState, start_dual_recording, stop_all_recordings, cancel_recording, clear_recording_data, recover_or_clean_sessions, set_data_dir, finalize_recording, finalize_to_path, verify_recording, save_replay, render_preview, validate_recording_options, get_drop_stats, get_channel_stats, get_active_recording_config, get_upload_progress, get_upload_status, save_recording_options, load_recording_options};
+use media::{AudioLevelMonitor, start_audio_level_monitor, stop_audio_level_monitor, enumerate_audio_devices, enumerate_displays, enumerate_system_audio_devices, enumerate_video_devices, get_audio_device_info, get_device_capabilities, ffmpeg_info, check_ffmpeg, benchmark_encoding, capture_screenshot, enumerate_windows};
+use upload::{test_upload_connection};
+use encryption::decrypt_chunk;
+use logging::{set_log_level, dump_logs};
+use utils::{has_screen_capture_access, check_permissions, request_permissions};
 
 use ffmpeg_sidecar::{
     command::ffmpeg_is_installed,
@@ -36,6 +41,8 @@ use winit::monitor::{MonitorHandle, VideoMode};
 
 fn main() {    
     let _ = fix_path_env::fix();
+
+    logging::init();
     
     std::panic::set_hook(Box::new(|info| {
         eprintln!("Thread panicked: {:?}", info);
@@ -208,19 +215,31 @@ fn main() {
               set_shadow(&options_window, true).expect("Unsupported platform!");
             }
 
-            let data_directory = handle.path_resolver().app_data_dir().unwrap_or_else(|| PathBuf::new());
             let recording_state = RecordingState {
+                is_recording: false,
                 media_process: None,
                 recording_options: None,
                 shutdown_flag: Arc::new(AtomicBool::new(false)),
-                video_uploading_finished: Arc::new(AtomicBool::new(false)),
-                audio_uploading_finished: Arc::new(AtomicBool::new(false)),
-                data_dir: Some(data_directory),
+                upload_cancelled: Arc::new(AtomicBool::new(false)),
+                failed_uploads: Arc::new(Mutex::new(Vec::new())),
+                segment_durations: Default::default(),
+                upload_progress: Default::default(),
+                upload_handles: Vec::new(),
+                upload_loop_errors: Vec::new(),
+                uploader: None,
+                cancellation_token: Default::default(),
+                span: tracing::Span::none(),
+                drop_counters: Default::default(),
+                av_offset_seconds: 0.0,
+                session: None,
+                data_dir: handle.path_resolver().app_data_dir(),
+                output_dir: None,
                 max_screen_width: max_width as usize,
                 max_screen_height: max_height as usize,
             };
 
             app.manage(Arc::new(Mutex::new(recording_state)));
+            app.manage(Arc::new(Mutex::new(AudioLevelMonitor::default())));
 
             let tray_handle = app.tray_handle();
             app.listen_global("toggle-recording", move |event| {
@@ -303,12 +322,47 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             start_dual_recording,
             stop_all_recordings,
+            cancel_recording,
+            clear_recording_data,
+            recover_or_clean_sessions,
+            set_data_dir,
+            finalize_recording,
+            finalize_to_path,
+            verify_recording,
+            save_replay,
+            render_preview,
+            validate_recording_options,
+            save_recording_options,
+            load_recording_options,
+            get_drop_stats,
+            get_channel_stats,
+            get_active_recording_config,
+            get_upload_progress,
+            get_upload_status,
+            test_upload_connection,
+            decrypt_chunk,
+            set_log_level,
+            dump_logs,
             enumerate_audio_devices,
+            enumerate_displays,
+            enumerate_windows,
+            enumerate_system_audio_devices,
+            enumerate_video_devices,
+            get_audio_device_info,
+            get_device_capabilities,
+            ffmpeg_info,
+            check_ffmpeg,
+            benchmark_encoding,
+            capture_screenshot,
+            start_audio_level_monitor,
+            stop_audio_level_monitor,
             start_server,
             open_screen_capture_preferences,
             open_mic_preferences,
             open_camera_preferences,
             has_screen_capture_access,
+            check_permissions,
+            request_permissions,
             reset_screen_permissions,
             reset_microphone_permissions,
             reset_camera_permissions,
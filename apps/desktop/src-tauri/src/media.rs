@@ -2,38 +2,427 @@ use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::SampleFormat;
 use std::process::{Stdio};
 use byteorder::{ByteOrder, LittleEndian};
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
-use std::io::{ErrorKind::WouldBlock, Error};
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}};
+use std::io::{BufWriter, ErrorKind::WouldBlock, Error, Write};
+use std::fs::File;
 use std::time::{Instant, Duration};
+use chrono::{DateTime, Utc};
 use std::path::Path;
-use image::{ImageBuffer, Rgba, ImageFormat};
+use std::collections::VecDeque;
+use image::{ColorType, DynamicImage, ImageBuffer, ImageEncoder, ImageError, Rgba, ImageFormat};
 use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use serde::Serialize;
+use tauri::{AppHandle, State};
 
-use tokio::io::{AsyncWriteExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::process::{Command, Child, ChildStdin};
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, oneshot, Mutex, Semaphore};
+use tokio::task::JoinHandle;
 use tokio::try_join;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn, Instrument};
 
-use crate::recording::RecordingOptions;
-use crate::utils::{ffmpeg_path_as_str};
-use crate::upload::upload_file;
-use capture::{Capturer, Display};
+use crate::error::RecordingError;
+use crate::recording::{RecordingOptions, RecordingState, RecordingErrorPayload, audio_track_video_type, emit_event};
+use crate::utils::{ffmpeg_path_as_str, locate_ffmpeg, run_command};
+#[cfg(unix)]
+use crate::utils::create_named_pipe;
+use crate::upload::{create_uploader, Uploader};
+use capture::{Capturer, Display, WindowBounds};
 
-const FRAME_RATE: u64 = 30;
+pub const DEFAULT_FRAME_RATE: u32 = 30;
+pub const SEGMENT_DURATION_SECS: u64 = 3;
+const MIN_FRAME_RATE: u32 = 5;
+const MAX_FRAME_RATE: u32 = 60;
+const MIN_OUTPUT_SAMPLE_RATE: u32 = 8000;
+const MAX_OUTPUT_SAMPLE_RATE: u32 = 192000;
+const AUDIO_RESAMPLE_FILTER: &str = "aresample=async=1:min_hard_comp=0.100000:first_pts=0";
+
+const STDERR_TAIL_LINES: usize = 20;
+const PROCESS_SPAWN_RETRY_DELAY: Duration = Duration::from_millis(100);
+const PROCESS_STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// ffmpeg errors that mean a process won't record anything, even when it hasn't exited yet.
+const FFMPEG_FATAL_ERRORS: [&str; 7] = [
+    "Unrecognized option",
+    "Error splitting the argument list",
+    "Unknown encoder",
+    "Error initializing output stream",
+    "Error opening output",
+    "Could not write header",
+    "Invalid argument",
+];
+const CURSOR_RADIUS: i64 = 5;
+const CURSOR_OUTLINE_RADIUS: i64 = 7;
+const CLICK_HIGHLIGHT_RADIUS: i64 = 24;
+/// JPEG quality of the thumbnail taken during a recording, which only needs to be small.
+const RECORDING_SCREENSHOT_QUALITY: u8 = 20;
+const SCREENSHOT_QUALITY: u8 = 90;
+const SCREENSHOT_FRAME_TIMEOUT: Duration = Duration::from_secs(3);
+/// How often `capture-stats` is emitted, and the window its FPS is measured over.
+const CAPTURE_STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// The smallest `-probesize` ffmpeg accepts.
+pub const MIN_INPUT_PROBESIZE: u64 = 32;
+/// Part of the warning ffmpeg logs when an input's thread queue is full.
+const THREAD_QUEUE_BLOCKING_WARNING: &str = "consider raising the thread_queue_size option";
+/// How long the capture has to stay black, having shown something before, to be reported as
+/// protected content.
+const PROTECTED_CONTENT_THRESHOLD: Duration = Duration::from_secs(1);
+const PROTECTED_CONTENT_STRIPE_WIDTH: usize = 24;
+/// How often a captured window's position is looked up again.
+const WINDOW_TRACK_INTERVAL: Duration = Duration::from_millis(200);
+/// How long the capture thread waits on a full video channel under the `block` policy before
+/// dropping the frame.
+const CHANNEL_SEND_TIMEOUT: Duration = Duration::from_millis(100);
+/// Distance of the overlay from the edges of the video.
+const OVERLAY_MARGIN: u32 = 16;
+/// Fonts the overlay falls back to when no `overlay_font_path` is set, in order of preference.
+#[cfg(target_os = "macos")]
+const OVERLAY_FONT_PATHS: &[&str] = &["/System/Library/Fonts/Helvetica.ttc", "/System/Library/Fonts/Supplemental/Arial.ttf", "/Library/Fonts/Arial.ttf"];
+#[cfg(target_os = "windows")]
+const OVERLAY_FONT_PATHS: &[&str] = &["C:/Windows/Fonts/arial.ttf", "C:/Windows/Fonts/segoeui.ttf"];
+#[cfg(target_os = "linux")]
+const OVERLAY_FONT_PATHS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+];
+/// How often the microphone's stream is checked for a disconnect.
+const DEVICE_DISCONNECT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// How often a disconnected microphone is looked for a replacement.
+const DEVICE_FALLBACK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How much silence is sent at once in place of a disconnected microphone.
+const SILENCE_BUFFER: Duration = Duration::from_millis(10);
+/// Display size simulated by `test_source` recordings.
+const TEST_SOURCE_SIZE: (usize, usize) = (1280, 720);
+pub const TIMING_LOG_FILE: &str = "timing_log.txt";
+/// The header every `fmp4` video segment of a recording is played back after.
+pub const INIT_SEGMENT_FILE: &str = "init.mp4";
+/// How often the audio sample position is written to the timing log.
+const TIMING_LOG_AUDIO_INTERVAL: Duration = Duration::from_millis(100);
+
+const TEST_TONE_FREQUENCY: f64 = 440.0;
+const TEST_TONE_AMPLITUDE: f64 = 0.2;
+/// How much of the test tone is sent at once, like a device buffer.
+const TEST_TONE_BUFFER: Duration = Duration::from_millis(10);
+
+/// Sample formats ffmpeg reads as they are, in order of preference.
+const NATIVE_SAMPLE_FORMATS: [SampleFormat; 4] = [SampleFormat::F32, SampleFormat::I16, SampleFormat::I8, SampleFormat::I32];
+/// Sample formats converted to a signed or `f32` equivalent before they are passed to ffmpeg, for
+/// devices (mostly USB interfaces) that offer nothing else.
+const CONVERTED_SAMPLE_FORMATS: [SampleFormat; 4] = [SampleFormat::U16, SampleFormat::U8, SampleFormat::U32, SampleFormat::F64];
+
+type SharedChild = Arc<Mutex<Child>>;
+type StderrTail = Arc<Mutex<VecDeque<String>>>;
+
+struct AudioSource {
+    /// `None` for the generated tone of `test_source` recordings.
+    device: Option<cpal::Device>,
+    name: String,
+    config: cpal::SupportedStreamConfig,
+}
+
+impl AudioSource {
+    fn test_tone() -> Self {
+        AudioSource {
+            device: None,
+            name: "Test tone".to_string(),
+            config: cpal::SupportedStreamConfig::new(2, cpal::SampleRate(48000), cpal::SupportedBufferSize::Unknown, SampleFormat::F32),
+        }
+    }
+
+    fn input_device(&self) -> Result<&cpal::Device, String> {
+        self.device.as_ref().ok_or_else(|| format!("'{}' is not an input device", self.name))
+    }
+
+    fn info(&self) -> AudioDeviceInfo {
+        AudioDeviceInfo {
+            device_name: self.name.clone(),
+            sample_rate: self.config.sample_rate().0,
+            channels: self.config.channels(),
+            sample_format: format!("{:?}", self.config.sample_format()).to_lowercase(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AudioDeviceInfo {
+    pub device_name: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample_format: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AudioDevice {
+    pub index: usize,
+    pub name: String,
+}
+
+/// One of the input config ranges a device supports.
+#[derive(Debug, Serialize, Clone)]
+pub struct AudioDeviceCapability {
+    pub sample_format: String,
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+}
+
+/// Counts buffers dropped because a channel to an ffmpeg writer was full.
+#[derive(Debug, Default)]
+pub struct DropCounters {
+    pub audio: AtomicU64,
+    pub video: AtomicU64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DropStats {
+    pub audio_frames_dropped: u64,
+    pub video_frames_dropped: u64,
+}
+
+impl DropCounters {
+    pub fn snapshot(&self) -> DropStats {
+        DropStats {
+            audio_frames_dropped: self.audio.load(Ordering::Relaxed),
+            video_frames_dropped: self.video.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Whether the capture is paused, and for how long it has been paused in total.
+#[derive(Debug, Default)]
+pub struct PauseState {
+    paused: AtomicBool,
+    /// When the current pause started, and the length of the pauses before it.
+    pauses: std::sync::Mutex<(Option<Instant>, Duration)>,
+}
+
+impl PauseState {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Pauses or resumes, returning whether that changed anything.
+    pub fn set_paused(&self, paused: bool) -> bool {
+        let Ok(mut pauses) = self.pauses.lock() else { return false };
+        if self.paused.swap(paused, Ordering::SeqCst) == paused {
+            return false;
+        }
+        match (paused, pauses.0.take()) {
+            (true, _) => pauses.0 = Some(Instant::now()),
+            (false, Some(paused_at)) => pauses.1 += paused_at.elapsed(),
+            (false, None) => {},
+        }
+        true
+    }
+
+    /// How long the capture has been paused for, including the current pause.
+    pub fn total_paused(&self) -> Duration {
+        self.pauses.lock().map_or(Duration::ZERO, |pauses| pauses.1 + pauses.0.map_or(Duration::ZERO, |paused_at| paused_at.elapsed()))
+    }
+}
+
+/// The capture times of a recording's video frames and audio samples, one `video <frame_index>
+/// <ns>` or `audio <sample_position> <ns>` line each, in nanoseconds on the monotonic clock since
+/// the log was created. Frame indexes are the output frame slots, so a repeated frame shows up as
+/// a gap in them.
+///
+/// Lines are written on a thread of their own, so the audio callback never takes a lock or waits
+/// on the file to mark a position.
+pub struct TimingLog {
+    epoch: Instant,
+    sender: mpsc::UnboundedSender<TimingLogMessage>,
+}
+
+enum TimingLogMessage {
+    Line { kind: &'static str, position: u64, nanos: u128 },
+    Flush(oneshot::Sender<()>),
+}
+
+impl TimingLog {
+    pub fn create(path: &Path) -> Result<Arc<Self>, String> {
+        let file = File::create(path).map_err(|e| format!("Failed to create the timing log {:?}: {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "# started {}", Utc::now().to_rfc3339()).map_err(|e| format!("Failed to write the timing log: {}", e))?;
+        info!("Writing the timing log to {:?}", path);
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            while let Some(message) = receiver.blocking_recv() {
+                let result = match message {
+                    TimingLogMessage::Line { kind, position, nanos } => writeln!(writer, "{} {} {}", kind, position, nanos),
+                    TimingLogMessage::Flush(done) => {
+                        let result = writer.flush();
+                        let _ = done.send(());
+                        result
+                    },
+                };
+                if let Err(e) = result {
+                    warn!("Failed to write the timing log: {}", e);
+                }
+            }
+        });
+
+        Ok(Arc::new(TimingLog { epoch: Instant::now(), sender }))
+    }
+
+    fn video_frame(&self, frame_index: u32, captured_at: Instant) {
+        self.write_line("video", frame_index as u64, captured_at);
+    }
+
+    fn audio_position(&self, sample_position: u64, captured_at: Instant) {
+        self.write_line("audio", sample_position, captured_at);
+    }
+
+    fn write_line(&self, kind: &'static str, position: u64, captured_at: Instant) {
+        let nanos = captured_at.saturating_duration_since(self.epoch).as_nanos();
+        let _ = self.sender.send(TimingLogMessage::Line { kind, position, nanos });
+    }
+
+    /// Waits for the lines sent so far to reach the file.
+    async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.sender.send(TimingLogMessage::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+/// Marks an audio stream's sample position in the timing log once per `TIMING_LOG_AUDIO_INTERVAL`
+/// of samples. Positions count frames of all channels, from the first buffer the stream delivered.
+struct AudioTimingMarks {
+    log: Arc<TimingLog>,
+    channels: usize,
+    interval_frames: u64,
+    position: u64,
+    next_mark: u64,
+}
+
+impl AudioTimingMarks {
+    fn new(log: Arc<TimingLog>, config: &cpal::SupportedStreamConfig) -> Self {
+        let interval_frames = (config.sample_rate().0 as f64 * TIMING_LOG_AUDIO_INTERVAL.as_secs_f64()) as u64;
+        AudioTimingMarks { log, channels: (config.channels() as usize).max(1), interval_frames: interval_frames.max(1), position: 0, next_mark: 0 }
+    }
+
+    /// Counts a buffer of `sample_count` interleaved samples captured at `captured_at`.
+    fn advance(&mut self, sample_count: usize, captured_at: Instant) {
+        if self.position >= self.next_mark {
+            self.log.audio_position(self.position, captured_at);
+            self.next_mark = self.position + self.interval_frames;
+        }
+        self.position += (sample_count / self.channels) as u64;
+    }
+}
+
+/// What the capture path does when the channel to an ffmpeg writer is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelFullPolicy {
+    /// Drop the buffer straight away, keeping capture timing at the cost of lost frames.
+    Drop,
+    /// Wait up to `CHANNEL_SEND_TIMEOUT` for room, so the writer catching up throttles capture
+    /// instead of frames being lost. Only applies to video, as the audio callback must not block.
+    Block,
+}
+
+impl ChannelFullPolicy {
+    pub fn parse(policy: &str) -> Result<Self, String> {
+        match policy {
+            "drop" => Ok(ChannelFullPolicy::Drop),
+            "block" => Ok(ChannelFullPolicy::Block),
+            other => Err(format!("Unknown channel full policy '{}', expected drop or block", other)),
+        }
+    }
+}
+
+/// How many buffers are waiting in the channels to the ffmpeg writers.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ChannelStats {
+    pub audio_queued: usize,
+    pub audio_capacity: usize,
+    pub video_queued: usize,
+    pub video_capacity: usize,
+}
+
+fn channel_occupancy(sender: &Option<mpsc::Sender<Vec<u8>>>) -> (usize, usize) {
+    match sender {
+        Some(sender) => (sender.max_capacity() - sender.capacity(), sender.max_capacity()),
+        None => (0, 0),
+    }
+}
+
+/// What the video and primary audio segments were encoded with.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct EncodingInfo {
+    pub width: usize,
+    pub height: usize,
+    pub framerate: u32,
+    pub video_codec: String,
+    pub audio_codec: Option<String>,
+    pub audio_device: Option<String>,
+}
+
+/// What the display and primary audio are actually captured with, after clamping and fallbacks.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct CaptureInfo {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Whether ffmpeg's screen grabber is recording instead of the capturer.
+    pub native_capture: bool,
+    pub audio_sample_rate: Option<u32>,
+    pub audio_channels: Option<u16>,
+    pub audio_sample_format: Option<String>,
+}
+
+/// A microphone recorded as its own track next to the primary audio.
+struct AudioTrack {
+    name: String,
+    stream: cpal::Stream,
+    process: SharedChild,
+    stdin: Arc<Mutex<Option<ChildStdin>>>,
+}
 
 pub struct MediaRecorder {
     pub options: Option<RecordingOptions>,
-    ffmpeg_audio_process: Option<tokio::process::Child>,
-    ffmpeg_video_process: Option<tokio::process::Child>,
+    ffmpeg_audio_process: Option<SharedChild>,
+    ffmpeg_video_process: Option<SharedChild>,
+    ffmpeg_webcam_process: Option<SharedChild>,
     ffmpeg_audio_stdin: Option<Arc<Mutex<Option<tokio::process::ChildStdin>>>>,
     ffmpeg_video_stdin: Option<Arc<Mutex<Option<tokio::process::ChildStdin>>>>,
     device_name: Option<String>,
     stream: Option<cpal::Stream>,
+    system_audio_stream: Option<cpal::Stream>,
+    audio_tracks: Vec<AudioTrack>,
     audio_channel_sender: Option<mpsc::Sender<Vec<u8>>>,
     audio_channel_receiver: Option<mpsc::Receiver<Vec<u8>>>,
     video_channel_sender: Option<mpsc::Sender<Vec<u8>>>,
     video_channel_receiver: Option<mpsc::Receiver<Vec<u8>>>,
     should_stop: Arc<AtomicBool>,
+    is_stopping: Arc<AtomicBool>,
+    failure: Arc<Mutex<Option<String>>>,
+    /// Cancelled when the recording is torn down, which ends every task the recorder spawned.
+    pub cancellation_token: CancellationToken,
+    pub drop_counters: Arc<DropCounters>,
+    /// Seconds the video track was offset against the audio track; negative when audio was offset.
+    pub av_offset_seconds: f64,
+    pub encoding: EncodingInfo,
+    pub capture: CaptureInfo,
+    /// System clock time of the first captured video frame.
+    pub video_started_at: Arc<Mutex<Option<DateTime<Utc>>>>,
+    /// Set before the recording starts when `emit_timing_log` is on.
+    pub timing_log: Option<Arc<TimingLog>>,
+    /// While set, neither frames nor samples are passed to ffmpeg, so the recording picks up where
+    /// it left off once it is cleared. The webcam and ffmpeg's own screen grabber keep recording.
+    pub paused: Arc<PauseState>,
+    /// Uploads the thumbnail, set before the recording starts when it is uploaded.
+    pub uploader: Option<Arc<dyn Uploader>>,
+    /// Shared with the segment uploads, so the thumbnail counts towards `max_concurrent_uploads`.
+    pub upload_semaphore: Arc<Semaphore>,
     start_time: Option<Instant>,
     audio_file_path: Option<String>,
     video_file_path: Option<String>,
@@ -46,45 +435,119 @@ impl MediaRecorder {
             options: None,
             ffmpeg_audio_process: None,
             ffmpeg_video_process: None,
+            ffmpeg_webcam_process: None,
             ffmpeg_audio_stdin: None,
             ffmpeg_video_stdin: None,
             device_name: None,
             stream: None,
+            system_audio_stream: None,
+            audio_tracks: Vec::new(),
             audio_channel_sender: None,
             audio_channel_receiver: None,
             video_channel_sender: None,
             video_channel_receiver: None,
             should_stop: Arc::new(AtomicBool::new(false)),
+            is_stopping: Arc::new(AtomicBool::new(false)),
+            failure: Arc::new(Mutex::new(None)),
+            cancellation_token: CancellationToken::new(),
+            drop_counters: Arc::new(DropCounters::default()),
+            av_offset_seconds: 0.0,
+            encoding: EncodingInfo::default(),
+            capture: CaptureInfo::default(),
+            video_started_at: Arc::new(Mutex::new(None)),
+            timing_log: None,
+            paused: Arc::new(PauseState::default()),
+            uploader: None,
+            upload_semaphore: Arc::new(Semaphore::new(1)),
             start_time: None,
             audio_file_path: None,
             video_file_path: None,
         }
     }
 
-    pub async fn start_media_recording(&mut self, options: RecordingOptions, audio_file_path: &str, video_file_path: &str, screenshot_file_path: &str, custom_device: Option<&str>, max_screen_width: usize, max_screen_height: usize) -> Result<(), String> {
+    /// The options the recording runs with, with the values that were clamped or filled in on start
+    /// replaced by the ones in use.
+    pub fn effective_options(&self) -> Option<RecordingOptions> {
+        let mut options = self.options.clone()?;
+        options.framerate = self.encoding.framerate;
+        options.channel_buffer_size = options.channel_buffer_size.max(1);
+        options.thread_queue_size = options.thread_queue_size.max(1);
+        if let Some(device_name) = &self.device_name {
+            options.audio_name = device_name.clone();
+        }
+        Some(options)
+    }
+
+    pub fn channel_stats(&self) -> ChannelStats {
+        let (audio_queued, audio_capacity) = channel_occupancy(&self.audio_channel_sender);
+        let (video_queued, video_capacity) = channel_occupancy(&self.video_channel_sender);
+        ChannelStats { audio_queued, audio_capacity, video_queued, video_capacity }
+    }
+
+    pub async fn start_media_recording(&mut self, options: RecordingOptions, audio_file_path: &str, video_file_path: &str, screenshot_file_path: &str, webcam_file_path: &str, custom_device: Option<&str>, max_screen_width: usize, max_screen_height: usize, app_handle: AppHandle) -> Result<(), RecordingError> {
+        let result = self.start_media_pipeline(options, audio_file_path, video_file_path, screenshot_file_path, webcam_file_path, custom_device, max_screen_width, max_screen_height, app_handle).await;
+        if result.is_err() {
+            // Stop whatever was started before the failure, the capture thread, the audio streams
+            // and any ffmpeg process, instead of leaving it running.
+            self.cancel_media_recording().await;
+            self.cancellation_token.cancel();
+        }
+        result
+    }
+
+    async fn start_media_pipeline(&mut self, options: RecordingOptions, audio_file_path: &str, video_file_path: &str, screenshot_file_path: &str, webcam_file_path: &str, custom_device: Option<&str>, max_screen_width: usize, max_screen_height: usize, app_handle: AppHandle) -> Result<(), RecordingError> {
         self.options = Some(options.clone());
 
-        println!("Custom device: {:?}", custom_device);
+        validate_container_format(&options).map_err(RecordingError::InvalidOptions)?;
+        validate_extra_ffmpeg_args("extra_audio_ffmpeg_args", &options.extra_audio_ffmpeg_args).map_err(RecordingError::InvalidOptions)?;
+        validate_extra_ffmpeg_args("extra_video_ffmpeg_args", &options.extra_video_ffmpeg_args).map_err(RecordingError::InvalidOptions)?;
+        validate_output_scale(&options).map_err(RecordingError::InvalidOptions)?;
+        validate_aac_profile(&options, None).map_err(RecordingError::InvalidOptions)?;
+        validate_audio_output_format(&options).map_err(RecordingError::InvalidOptions)?;
+
+        info!("Custom device: {:?}", custom_device);
         
         let host = cpal::default_host();
-        let devices = host.devices().expect("Failed to get devices");
-        let _display = Display::primary().expect("Failed to find primary display");
-        let w = max_screen_width;
-        let h = max_screen_height;
+        let display_id = options.display_id;
+        let capture_window_id = options.capture_window_id;
+        let (w, h, display_scale, backing_scale, window_bounds) = if options.test_source {
+            (TEST_SOURCE_SIZE.0, TEST_SOURCE_SIZE.1, 1.0, 1.0, None)
+        } else {
+            let display = select_display(display_id).map_err(|e| RecordingError::DisplayUnavailable(format!("Failed to find display: {}", e)))?;
+            let (w, h) = if display.is_primary() {
+                (max_screen_width, max_screen_height)
+            } else {
+                (display.width(), display.height())
+            };
+            // Display coordinates (cursor, window bounds) are in the display's pixels, which can differ from the frame size.
+            (w, h, w as f64 / display.width() as f64, display.scale_factor(), capture_window_id.and_then(|id| display.window_bounds(id)))
+        };
         
-        let adjusted_width = w & !2;
-        let adjusted_height = h & !2;
-        let capture_size = adjusted_width * adjusted_height * 4;
-        let (audio_tx, audio_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(2048);
-        let (video_tx, video_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(2048);
+        let adjusted_width = w & !1;
+        let adjusted_height = h & !1;
+        let (crop_x, crop_y, frame_width, frame_height) = match capture_window_id {
+            Some(window_id) => window_region(&options, window_id, window_bounds, display_scale, adjusted_width, adjusted_height).map_err(RecordingError::DisplayUnavailable)?,
+            None => crop_region(&options, adjusted_width, adjusted_height).map_err(RecordingError::InvalidOptions)?
+                .unwrap_or((0, 0, adjusted_width, adjusted_height)),
+        };
+        let capture_size = frame_width * frame_height * 4;
+        let channel_buffer_size = options.channel_buffer_size.max(1);
+        let (audio_tx, audio_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(channel_buffer_size);
+        let (video_tx, video_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(channel_buffer_size);
         let calculated_stride = (adjusted_width * 4) as usize;
+        let framerate = options.framerate.clamp(MIN_FRAME_RATE, MAX_FRAME_RATE);
+
+        if framerate != options.framerate {
+            warn!("Framerate {} is out of range, using {} instead", options.framerate, framerate);
+        }
         
-        println!("Display width: {}", w);
-        println!("Display height: {}", h);
-        println!("Adjusted width: {}", adjusted_width);
-        println!("Adjusted height: {}", adjusted_height);
-        println!("Capture size: {}", capture_size);
-        println!("Calculated stride: {}", calculated_stride);
+        debug!("Display width: {}", w);
+        debug!("Display height: {}", h);
+        debug!("Adjusted width: {}", adjusted_width);
+        debug!("Adjusted height: {}", adjusted_height);
+        info!("Capture region: {}x{} at {},{}", frame_width, frame_height, crop_x, crop_y);
+        debug!("Capture size: {}", capture_size);
+        debug!("Calculated stride: {}", calculated_stride);
 
         let audio_start_time = Arc::new(Mutex::new(None));
         let video_start_time = Arc::new(Mutex::new(None));
@@ -104,271 +567,453 @@ impl MediaRecorder {
 
         let should_stop = Arc::clone(&self.should_stop);
         
-        let mut input_devices = devices.filter_map(|device| {
-            let supported_input_configs = device.supported_input_configs();
-            if supported_input_configs.is_ok() && supported_input_configs.unwrap().count() > 0 {
-                Some(device)
-            } else {
-                None
-            }
-        });
+        if options.test_source && options.capture_system_audio {
+            warn!("System audio is not recorded with the test source");
+        }
 
-        let device = if let Some(custom_device_name) = custom_device {
-            input_devices
-                .find(|d| d.name().map(|name| name == custom_device_name).unwrap_or(false))
-                .unwrap_or_else(|| host.default_input_device().expect("No default input device available"))
+        let microphone = if options.record_audio && options.test_source {
+            Some(AudioSource::test_tone())
+        } else if options.record_audio && custom_device != Some("None") {
+            Some(select_input_device(&host, custom_device, options.audio_device_index).map_err(RecordingError::NoInputDevice)?)
         } else {
-            host.default_input_device().expect("No default input device available")
+            None
         };
 
-        println!("Using audio device: {}", device.name().expect("Failed to get device name"));
-
-        let config = device.supported_input_configs()
-            .expect("Failed to get supported input configs")
-            .find(|c| c.sample_format() == SampleFormat::F32 || c.sample_format() == SampleFormat::I16 || c.sample_format() == SampleFormat::I8 || c.sample_format() == SampleFormat::I32)
-            .unwrap_or_else(||
-                device.supported_input_configs().expect("Failed to get supported input configs").next().expect("No supported input config")
-            )
-            .with_max_sample_rate();
-
-        let sample_rate = config.sample_rate().0;
-        let channels = config.channels();
-        let sample_format = match config.sample_format() {
-            SampleFormat::I8 => "s8",
-            SampleFormat::I16 => "s16le",
-            SampleFormat::I32 => "s32le",
-            SampleFormat::F32 => "f32le",
-            _ => panic!("Unsupported sample format."),
+        let system_audio = if options.record_audio && options.capture_system_audio && !options.test_source {
+            Some(select_system_audio_device(&host, options.system_audio_name.as_deref()).map_err(RecordingError::NoInputDevice)?)
+        } else {
+            None
         };
 
-        println!("Sample rate: {}", sample_rate);
-        println!("Channels: {}", channels);
-        println!("Sample format: {}", sample_format);
-        
-        let ffmpeg_binary_path_str = ffmpeg_path_as_str().unwrap().to_owned();
+        // The primary source feeds ffmpeg's stdin; when both are recorded, system audio is mixed in
+        // through a second input.
+        let (primary_audio, mixed_audio) = match (microphone, system_audio) {
+            (Some(microphone), system_audio) => (Some(microphone), system_audio),
+            (None, system_audio) => (system_audio, None),
+        };
+        let record_audio = primary_audio.is_some();
 
-        println!("FFmpeg binary path: {}", ffmpeg_binary_path_str);
+        let ffmpeg_binary_path_str = ffmpeg_path_as_str().map_err(RecordingError::FfmpegNotFound)?;
+
+        info!("FFmpeg binary path: {}", ffmpeg_binary_path_str);
+
+        let encoder = audio_encoder(&options);
+        if record_audio && encoder == "libfdk_aac" && !is_encoder_available(&ffmpeg_binary_path_str, encoder) {
+            return Err(RecordingError::InvalidOptions("HE-AAC needs an FFmpeg built with libfdk_aac, which this one isn't".to_string()));
+        }
         
         let audio_file_path_owned = audio_file_path.to_owned();
         let video_file_path_owned = video_file_path.to_owned();
-        let sample_rate_str = sample_rate.to_string();
-        let channels_str = channels.to_string();
         
         let ffmpeg_audio_stdin = self.ffmpeg_audio_stdin.clone();
         let ffmpeg_video_stdin = self.ffmpeg_video_stdin.clone();
-
-        let err_fn = move |err| {
-            eprintln!("an error occurred on stream: {}", err);
-        };
         
-        if custom_device != Some("None") {
-            println!("Building input stream...");
-
-            let stream_result: Result<cpal::Stream, cpal::BuildStreamError> = match config.sample_format() {
-              SampleFormat::I8 => device.build_input_stream(
-                  &config.into(),
-                  {
-                      let audio_start_time = Arc::clone(&audio_start_time);
-                      move |data: &[i8], _: &_| {
-                          let mut first_frame_time_guard = audio_start_time.try_lock();
-                          
-                          let bytes = data.iter().map(|&sample| sample as u8).collect::<Vec<u8>>();
-                          if let Some(sender) = &audio_channel_sender {
-                            if sender.try_send(bytes).is_err() {
-                              eprintln!("Channel send error. Dropping data.");
-                            }
-                          }
-                          
-                          if let Ok(ref mut start_time_option) = first_frame_time_guard {
-                              if start_time_option.is_none() {
-                                  **start_time_option = Some(Instant::now()); 
-
-                                  println!("Audio start time captured");
-                              }
-                          }
-                      }
-                  },
-                  err_fn,
-                  None,
-              ),
-              SampleFormat::I16 => device.build_input_stream(
-                  &config.into(),
-                  {
-                      let audio_start_time = Arc::clone(&audio_start_time); 
-                      move |data: &[i16], _: &_| {
-                          let mut first_frame_time_guard = audio_start_time.try_lock();
-
-                          let mut bytes = vec![0; data.len() * 2];
-                          LittleEndian::write_i16_into(data, &mut bytes);
-                          if let Some(sender) = &audio_channel_sender {
-                              if sender.try_send(bytes).is_err() {
-                                  eprintln!("Channel send error. Dropping data.");
-                              }
-                          }
-
-                          if let Ok(ref mut start_time_option) = first_frame_time_guard {
-                              if start_time_option.is_none() {
-                                  **start_time_option = Some(Instant::now()); 
-
-                                  println!("Audio start time captured");
-                              }
-                          }
-                      }
-                  },
-                  err_fn,
-                  None,
-              ),
-              SampleFormat::I32 => device.build_input_stream(
-                  &config.into(),
-                  {
-                      let audio_start_time = Arc::clone(&audio_start_time);
-                      move |data: &[i32], _: &_| {
-                          let mut first_frame_time_guard = audio_start_time.try_lock();
-
-                          let mut bytes = vec![0; data.len() * 2];
-                          LittleEndian::write_i32_into(data, &mut bytes);
-                          if let Some(sender) = &audio_channel_sender {
-                              if sender.try_send(bytes).is_err() {
-                                  eprintln!("Channel send error. Dropping data.");
-                              }
-                          }
-
-                          if let Ok(ref mut start_time_option) = first_frame_time_guard {
-                              if start_time_option.is_none() {
-                                  **start_time_option = Some(Instant::now()); 
-
-                                  println!("Audio start time captured");
-                              }
-                          }
-                      }
-                  },
-                  err_fn,
-                  None,
-              ),
-              SampleFormat::F32 => device.build_input_stream(
-                  &config.into(),
-                  {
-                      let audio_start_time = Arc::clone(&audio_start_time);
-                      move |data: &[f32], _: &_| {
-                          let mut first_frame_time_guard = audio_start_time.try_lock();
-
-                          let mut bytes = vec![0; data.len() * 4];
-                          LittleEndian::write_f32_into(data, &mut bytes);
-                          if let Some(sender) = &audio_channel_sender {
-                              if sender.try_send(bytes).is_err() {
-                                  eprintln!("Channel send error. Dropping data.");
-                              }
-                          }
-
-                          if let Ok(ref mut start_time_option) = first_frame_time_guard {
-                              if start_time_option.is_none() {
-                                  **start_time_option = Some(Instant::now()); 
-
-                                  println!("Audio start time captured");
-                              }
-                          }
-                      }
-                  },
-                  err_fn,
-                  None,
-              ),
-              _sample_format => Err(cpal::BuildStreamError::DeviceNotAvailable),
-            };
-            
-            let stream = stream_result.map_err(|_| "Failed to build input stream")?;
-            self.stream = Some(stream);
-            self.trigger_play()?;
+        if let Some(source) = &primary_audio {
+            info!("Using audio device: {}", source.name);
+            debug!("Sample rate: {}", source.config.sample_rate().0);
+            debug!("Channels: {}", source.config.channels());
+            debug!("Sample format: {}", ffmpeg_sample_format(source.config.sample_format())?);
+            debug!("Building input stream...");
+
+            let sender = audio_channel_sender.clone().ok_or("Audio channel is not set")?;
+            if source.device.is_some() {
+                let disconnected = Arc::new(AtomicBool::new(false));
+                let timing_marks = self.timing_log.clone().map(|log| AudioTimingMarks::new(log, &source.config));
+                let stream = build_audio_input_stream(source, sender.clone(), Arc::clone(&audio_start_time), options.compensate_input_latency, Arc::clone(&self.drop_counters), Some(app_handle.clone()), Arc::clone(&disconnected), timing_marks, Arc::clone(&self.paused))?;
+                self.stream = Some(stream);
+                self.trigger_play()?;
+                spawn_audio_disconnect_handler(source, sender, disconnected, options.audio_device_fallback, Arc::clone(&self.drop_counters), app_handle.clone(), Arc::clone(&should_stop), self.cancellation_token.clone(), Arc::clone(&self.paused));
+            } else {
+                let timing_marks = self.timing_log.clone().map(|log| AudioTimingMarks::new(log, &source.config));
+                spawn_test_tone(source, sender, Arc::clone(&audio_start_time), Arc::clone(&self.drop_counters), app_handle.clone(), Arc::clone(&should_stop), self.cancellation_token.clone(), timing_marks, Arc::clone(&self.paused));
+            }
+        }
+
+        let mut system_audio_pipe_path = None;
+
+        if let Some(source) = &mixed_audio {
+            info!("Mixing in system audio from: {}", source.name);
+
+            let (system_audio_tx, mut system_audio_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(channel_buffer_size);
+            let (pipe_path, pipe_writer) = open_audio_pipe(&audio_file_path_owned, "system_audio")?;
+
+            let stream = build_audio_input_stream(source, system_audio_tx, Arc::new(Mutex::new(None)), false, Arc::clone(&self.drop_counters), None, Arc::new(AtomicBool::new(false)), None, Arc::clone(&self.paused))?;
+            stream.play().map_err(|_| "Failed to play system audio stream")?;
+            self.system_audio_stream = Some(stream);
+
+            let cancellation_token = self.cancellation_token.clone();
+            tokio::spawn(async move {
+                let mut writer = match pipe_writer.await {
+                    Ok(Ok(writer)) => writer,
+                    Ok(Err(e)) => {
+                        error!("Failed to open system audio pipe: {}", e);
+                        return;
+                    },
+                    Err(e) => {
+                        error!("Failed to open system audio pipe: {}", e);
+                        return;
+                    },
+                };
+
+                while let Some(bytes) = recv_or_cancelled(&mut system_audio_rx, &cancellation_token).await {
+                    if let Err(e) = write_or_cancelled(&mut writer, &bytes, &cancellation_token).await {
+                        error!("Failed to write system audio data to FFmpeg: {}", e);
+                        break;
+                    }
+                }
+            }.in_current_span());
+
+            system_audio_pipe_path = Some(pipe_path);
+        }
+
+        // Everything the options can make fail is worked out before the capture thread starts.
+        let (audio_segment_extension, audio_segment_format_args) = audio_segment_format_args(&options)?;
+        let (video_segment_extension, video_segment_format_args) = video_segment_format_args(&options, &video_file_path_owned);
+        let channels = primary_audio.as_ref().map_or(0, |source| source.config.channels());
+        let channel_filter = audio_channel_filter(&options.audio_channel_mode, channels)?;
+        let output_filter = audio_output_filter(&options)?;
+
+        let mut audio_output_args: Vec<String> = vec!["-async".to_string(), "1".to_string()];
+        if let Some(source) = &primary_audio {
+            audio_output_args.extend(audio_encoder_args(&options, output_channels(&options.audio_channel_mode, source.config.channels()))?);
+            audio_output_args.extend(output_sample_rate_args(&options, source.config.sample_rate().0)?);
         }
 
+        let requested_resolution = parse_resolution(&options.resolution)?;
+        let video_encoder_args = match options.container_format.as_str() {
+            "webm" => vp9_encoder_args(&options)?,
+            _ => video_encoder_args(&options, &ffmpeg_binary_path_str)?,
+        };
+        let overlay_filter = overlay_filter(&options).map_err(RecordingError::InvalidOptions)?;
+        let keyframe_args = keyframe_args(&options, framerate)?;
+        let ffmpeg_webcam_command = if options.webcam_index.is_empty() {
+            None
+        } else {
+            Some(ffmpeg_webcam_command(&options.webcam_index, webcam_file_path)?)
+        };
+
+        // A single muxed output reads the video as input 0 and the audio inputs after it, with the
+        // microphone coming through a named pipe since stdin carries the video.
+        let single_muxed_output = options.single_muxed_output && record_audio;
+        let first_audio_input = if single_muxed_output { 1 } else { 0 };
+        let mut microphone_pipe_writer = None;
+        let primary_audio_input = if single_muxed_output {
+            let (pipe_path, pipe_writer) = open_audio_pipe(&audio_file_path_owned, "microphone")?;
+            microphone_pipe_writer = Some(pipe_writer);
+            pipe_path
+        } else {
+            "pipe:0".to_string()
+        };
+
         let video_start_time_clone = Arc::clone(&video_start_time); 
-        let screenshot_file_path_owned = format!("{}/screen-capture.jpg", screenshot_file_path);
-        let capture_frame_at = Duration::from_secs(3);
+        let video_started_at = Arc::clone(&self.video_started_at);
+        let screenshot_encoding = ScreenshotEncoding::from_options(&options).map_err(RecordingError::InvalidOptions)?;
+        let screenshot_file_path_owned = format!("{}/screen-capture.{}", screenshot_file_path, screenshot_encoding.extension());
+        let capture_frame_at = Duration::from_millis(options.screenshot_delay_ms);
+        let (capture_init_tx, capture_init_rx) = tokio::sync::oneshot::channel::<Result<(usize, usize, usize, usize, f64), String>>();
+        let capture_app_handle = app_handle.clone();
+        let video_drop_counters = Arc::clone(&self.drop_counters);
+        let channel_full_policy = ChannelFullPolicy::parse(&options.channel_full_policy).map_err(RecordingError::InvalidOptions)?;
+        let capture_failure = Arc::clone(&self.failure);
+        let capture_cursor = options.capture_cursor;
+        let highlight_clicks = options.highlight_clicks;
+        let mask_protected_content = options.mask_protected_content;
+        let stall_threshold = Duration::from_secs(options.capture_stall_threshold_secs.max(1));
+        let test_source = options.test_source;
+        let timing_log = self.timing_log.clone();
+        let capture_paused = Arc::clone(&self.paused);
+        let thumbnail_uploader = self.uploader.clone().map(|uploader| (uploader, Arc::clone(&self.upload_semaphore)));
         
+        let capture_cancellation_token = self.cancellation_token.clone();
+        // The capture thread logs under the span of the recording that started it.
+        let capture_span = tracing::Span::current();
+
         std::thread::spawn(move || {
-            println!("Starting video recording capture thread...");
+            let _capture_span = capture_span.entered();
+            info!("Starting video recording capture thread...");
+
+            // A second handle on the display to query the pointer and windows with, since the capturer takes ownership of its own.
+            let cursor_display = if test_source {
+                None
+            } else if capture_cursor || highlight_clicks || capture_window_id.is_some() { select_display(display_id).ok() } else { None };
+            let (mut crop_x, mut crop_y) = (crop_x, crop_y);
+            let mut next_window_check_at = Instant::now() + WINDOW_TRACK_INTERVAL;
+            let mut window_size: Option<(u32, u32)> = None;
+            let mut window_lost = false;
+
+            let capturer = if test_source {
+                Ok(FrameSource::TestPattern(TestPattern::new(adjusted_width, adjusted_height)))
+            } else {
+                select_display(display_id).and_then(|display| Capturer::with_cursor(display, w, h, capture_cursor)).map(FrameSource::Display)
+            };
+            let mut capturer = match capturer {
+                Ok(capturer) => capturer,
+                Err(e) => {
+                    let _ = capture_init_tx.send(Err(format!("Failed to start capture: {}", e)));
+                    return;
+                }
+            };
 
-            let is_local_mode = match dotenv_codegen::dotenv!("NEXT_PUBLIC_LOCAL_MODE") {
-                "true" => true,
-                _ => false,
+            // Frames can come at another size than the one requested, such as a display's current mode
+            // rather than its largest one, in which case the capture region is scaled to the frames.
+            let (source_width, source_height) = capturer.size();
+            let (adjusted_width, adjusted_height, frame_width, frame_height, display_scale) = if source_width < 2 || source_height < 2 || (source_width, source_height) == (w, h) {
+                (adjusted_width, adjusted_height, frame_width, frame_height, display_scale)
+            } else {
+                let scale_x = source_width as f64 / w as f64;
+                let scale_y = source_height as f64 / h as f64;
+                let (adjusted_width, adjusted_height) = (source_width & !1, source_height & !1);
+                crop_x = ((crop_x as f64 * scale_x) as usize).min(adjusted_width.saturating_sub(2));
+                crop_y = ((crop_y as f64 * scale_y) as usize).min(adjusted_height.saturating_sub(2));
+                let scaled_width = ((((frame_width as f64 * scale_x) as usize).min(adjusted_width - crop_x)) & !1).max(2);
+                let scaled_height = ((((frame_height as f64 * scale_y) as usize).min(adjusted_height - crop_y)) & !1).max(2);
+                warn!(
+                    "Capture delivers {}x{} frames instead of {}x{}, capturing {}x{} at {},{}",
+                    source_width, source_height, w, h, scaled_width, scaled_height, crop_x, crop_y,
+                );
+                (adjusted_width, adjusted_height, scaled_width, scaled_height, display_scale * scale_x)
             };
+            let capture_size = frame_width * frame_height * 4;
+            // Unpadded rows are as wide as the delivered frames, which can be an odd width. A capturer
+            // that doesn't report its size is taken to deliver the requested one.
+            let calculated_stride = source_width.max(adjusted_width) * 4;
+            let cropped_stride = frame_width * 4;
+            let _ = capture_init_tx.send(Ok((crop_x, crop_y, frame_width, frame_height, display_scale)));
 
-            let mut capturer = Capturer::new(Display::primary().expect("Failed to find primary display"), w.try_into().unwrap(), h.try_into().unwrap()).expect("Failed to start capture");
+            let draw_cursor = capture_cursor && !capturer.draws_cursor();
 
-            let fps = FRAME_RATE;
+            let fps = framerate as u64;
             let spf = Duration::from_nanos(1_000_000_000 / fps);
 
+            let send_frame = |frame_data: Vec<u8>| {
+                if let Some(sender) = &video_channel_sender {
+                    let sent = match channel_full_policy {
+                        ChannelFullPolicy::Drop => sender.try_send(frame_data).is_ok(),
+                        ChannelFullPolicy::Block => matches!(
+                            tauri::async_runtime::block_on(tokio::time::timeout(CHANNEL_SEND_TIMEOUT, sender.send(frame_data))),
+                            Ok(Ok(()))
+                        ),
+                    };
+                    if !sent {
+                        video_drop_counters.video.fetch_add(1, Ordering::Relaxed);
+                        error!("Channel send error. Dropping data.");
+                    }
+                }
+            };
+
             let mut frame_count = 0u32;
+            let mut duplicated_frame_count = 0u32;
             let start_time = Instant::now();
-            let mut time_next = Instant::now() + spf;
+            // Frame N is due at `schedule_start + N * spf`, counted from the first captured frame so
+            // that the schedule matches the video start time. Every slot gets exactly one frame: a new
+            // one when the capturer has it in time, the previous one otherwise.
+            let mut schedule_start: Option<Instant> = None;
+            let mut frame_index = 0u32;
+            let mut last_frame_data: Option<Vec<u8>> = None;
             let mut screenshot_captured: bool = false;
+            // Since when the capturer has been failing frames. `WouldBlock` isn't a failure, it only
+            // means the screen hasn't changed, so a static screen never counts as stalled.
+            let mut failing_since: Option<Instant> = None;
+            // Capture times of the frames in the last stats window, for the rolling FPS.
+            let mut recent_frame_times: VecDeque<Instant> = VecDeque::new();
+            let mut next_stats_at = Instant::now() + CAPTURE_STATS_INTERVAL;
+            let mut stats_dropped_count = video_drop_counters.video.load(Ordering::Relaxed);
+            let mut stats_repeated_count = 0u32;
+            // Protected content, such as a DRM video player, is captured as black or fails the frames.
+            let mut saw_content = false;
+            let mut black_since: Option<Instant> = None;
+            let mut protected_content = false;
             
-            while !should_stop.load(Ordering::SeqCst) {
-                let options_clone = options.clone();
+            while !should_stop.load(Ordering::SeqCst) && !capture_cancellation_token.is_cancelled() {
+                // ffmpeg times the frames by their count, so the frames held back while paused leave
+                // no gap. The schedule moves on by the pause, so no slots are made up for after it.
+                if capture_paused.is_paused() {
+                    let paused_at = Instant::now();
+                    while capture_paused.is_paused() && !should_stop.load(Ordering::SeqCst) && !capture_cancellation_token.is_cancelled() {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    let paused_for = paused_at.elapsed();
+                    schedule_start = schedule_start.map(|schedule_start| schedule_start + paused_for);
+                    failing_since = failing_since.map(|failing_since| failing_since + paused_for);
+                    black_since = None;
+                    continue;
+                }
+
                 let now = Instant::now();
 
-                if now >= time_next {
+                if now >= next_stats_at {
+                    while recent_frame_times.front().map_or(false, |frame_time| now.duration_since(*frame_time) > CAPTURE_STATS_INTERVAL) {
+                        recent_frame_times.pop_front();
+                    }
+
+                    let dropped_count = video_drop_counters.video.load(Ordering::Relaxed);
+                    emit_event(&capture_app_handle, "capture-stats", CaptureStatsPayload {
+                        fps: recent_frame_times.len() as f64 / CAPTURE_STATS_INTERVAL.as_secs_f64(),
+                        target_fps: framerate,
+                        dropped: dropped_count - stats_dropped_count,
+                        repeated: duplicated_frame_count - stats_repeated_count,
+                    });
+                    stats_dropped_count = dropped_count;
+                    stats_repeated_count = duplicated_frame_count;
+                    next_stats_at = now + CAPTURE_STATS_INTERVAL;
+                }
+
+                // The output size is fixed once ffmpeg has started, so a captured window is followed
+                // by moving the region and a resized window is cut to the size it started with.
+                if let (Some(window_id), Some(display)) = (capture_window_id, cursor_display.as_ref()) {
+                    if now >= next_window_check_at {
+                        match display.window_bounds(window_id) {
+                            Some(bounds) => {
+                                if window_lost {
+                                    info!("Window {} is available again", window_id);
+                                    window_lost = false;
+                                }
+                                if window_size.map_or(false, |size| size != (bounds.width, bounds.height)) {
+                                    info!("Window {} resized to {}x{}, recording stays at {}x{}", window_id, bounds.width, bounds.height, frame_width, frame_height);
+                                }
+                                window_size = Some((bounds.width, bounds.height));
+                                (crop_x, crop_y) = follow_window(bounds, display_scale, frame_width, frame_height, adjusted_width, adjusted_height);
+                            },
+                            None if !window_lost => {
+                                warn!("Window {} is no longer available, keeping its last position", window_id);
+                                window_lost = true;
+                            },
+                            None => {},
+                        }
+                        next_window_check_at = now + WINDOW_TRACK_INTERVAL;
+                    }
+                }
+                let frame_due = schedule_start.map_or(now, |schedule_start| schedule_start + spf * frame_index);
+
+                if now >= frame_due {
+                    // A GPU reset or a lost display can leave the capturer failing every frame for
+                    // good, so try starting over with a new one.
+                    let stalled_for = failing_since.map_or(Duration::ZERO, |failing_since| failing_since.elapsed());
+                    if stalled_for >= stall_threshold {
+                        warn!("Capture failing for {:?}, re-creating the capturer", stalled_for);
+                        let recovered = match select_display(display_id).and_then(|display| Capturer::with_cursor(display, w, h, capture_cursor)) {
+                            Ok(new_capturer) => {
+                                capturer = FrameSource::Display(new_capturer);
+                                true
+                            },
+                            Err(e) => {
+                                error!("Failed to re-create the capturer: {}", e);
+                                false
+                            },
+                        };
+                        emit_event(&capture_app_handle, "capture-stalled", CaptureStalledPayload {
+                            stalled_ms: stalled_for.as_millis() as u64,
+                            recovered,
+                        });
+                        failing_since = None;
+                    }
+
                     match capturer.frame() {
                         Ok(frame) => {
                             let mut frame_data = Vec::with_capacity(capture_size.try_into().unwrap());
+                            let padded_stride = frame.stride_override().unwrap_or(calculated_stride);
+                            let required_len = (crop_y + frame_height - 1) * padded_stride + crop_x * 4 + cropped_stride;
 
-                            for row in 0..adjusted_height {
-                                let padded_stride = frame.stride_override().unwrap_or(calculated_stride);
-                                assert!(padded_stride >= calculated_stride, "Image stride with padding should not be smaller than calculated bytes per row");
-                                // Each row should skip the padding of the previous row
-                                let start = row * padded_stride;
-                                // Each row should stop before/trim off its padding, for compatibility with software that doesn't follow arbitrary padding.
-                                let end = start + calculated_stride;
+                            // A smaller frame means the display resolution changed or the display went away.
+                            if padded_stride < calculated_stride || frame.len() < required_len {
+                                tauri::async_runtime::block_on(report_pipeline_failure(
+                                    &capture_failure,
+                                    &capture_app_handle,
+                                    format!(
+                                        "Display resolution changed during recording (got {} bytes with stride {}, expected at least {} bytes with stride {}), stopping video capture",
+                                        frame.len(), padded_stride, required_len, calculated_stride,
+                                    ),
+                                ));
+                                break;
+                            }
+
+                            for row in 0..frame_height {
+                                // Each row should skip the padding of the previous row and the pixels left of the capture region
+                                let start = (row + crop_y) * padded_stride + crop_x * 4;
+                                // Each row should stop at the right edge of the capture region, which also trims off its padding.
+                                let end = start + cropped_stride;
                                 frame_data.extend_from_slice(&frame[start..end]);
                             }
 
-                            if now - start_time >= capture_frame_at && !screenshot_captured {
+                            // A display is black before anything is rendered, so only a region that
+                            // goes black after showing something counts as protected.
+                            if is_blank_frame(&frame_data) {
+                                if saw_content {
+                                    let black_since = *black_since.get_or_insert(now);
+                                    if !protected_content && now.duration_since(black_since) >= PROTECTED_CONTENT_THRESHOLD {
+                                        protected_content = true;
+                                        warn!("Capture has been black for {:?}, the region likely shows protected content", now.duration_since(black_since));
+                                        emit_event(&capture_app_handle, "protected-content", ProtectedContentPayload {
+                                            active: true,
+                                            reason: "black_frames".to_string(),
+                                            masked: mask_protected_content,
+                                        });
+                                    }
+                                }
+                            } else {
+                                saw_content = true;
+                                black_since = None;
+                                if protected_content {
+                                    protected_content = false;
+                                    info!("Capture shows content again");
+                                    emit_event(&capture_app_handle, "protected-content", ProtectedContentPayload {
+                                        active: false,
+                                        reason: "content_visible".to_string(),
+                                        masked: mask_protected_content,
+                                    });
+                                }
+                            }
+                            if protected_content && mask_protected_content {
+                                draw_protected_content_mask(&mut frame_data, frame_width);
+                            }
+
+                            if let Some(cursor) = cursor_display.as_ref().filter(|_| capture_cursor || highlight_clicks).and_then(|display| display.cursor_state()) {
+                                let cursor_x = (cursor.x as f64 * display_scale) as i64 - crop_x as i64;
+                                let cursor_y = (cursor.y as f64 * display_scale) as i64 - crop_y as i64;
+
+                                if highlight_clicks && cursor.pressed {
+                                    draw_circle(&mut frame_data, frame_width, frame_height, cursor_x, cursor_y, CLICK_HIGHLIGHT_RADIUS, [0, 215, 255], 0.4);
+                                }
+
+                                if draw_cursor {
+                                    draw_circle(&mut frame_data, frame_width, frame_height, cursor_x, cursor_y, CURSOR_OUTLINE_RADIUS, [0, 0, 0], 1.0);
+                                    draw_circle(&mut frame_data, frame_width, frame_height, cursor_x, cursor_y, CURSOR_RADIUS, [255, 255, 255], 1.0);
+                                }
+                            }
+
+                            // Use the first frame after the delay that has been rendered, rather than a blank one.
+                            if !screenshot_captured && now - start_time >= capture_frame_at && !is_blank_frame(&frame_data) {
                                 screenshot_captured = true;
                                 let screenshot_file_path_owned_cloned = screenshot_file_path_owned.clone();
-                                let mut frame_data_clone = frame_data.clone();
+                                let frame_data_clone = frame_data.clone();
+                                let screenshot_encoding = screenshot_encoding.clone();
+                                let thumbnail_uploader = thumbnail_uploader.clone();
 
                                 std::thread::spawn(move || {
-                                    for chunk in frame_data_clone.chunks_mut(4) {
-                                        chunk.swap(0, 2);
+                                    let path = Path::new(&screenshot_file_path_owned_cloned);
+
+                                    if let Err(e) = save_screenshot(frame_data_clone, frame_width, frame_height, path, &screenshot_encoding) {
+                                        error!("{}", e);
+                                        return;
                                     }
+                                    info!("Screenshot captured and saved to {:?}", path);
 
-                                    let path = Path::new(&screenshot_file_path_owned_cloned);
-                                    let image: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_raw(
-                                        adjusted_width.try_into().unwrap(),
-                                        adjusted_height.try_into().unwrap(),
-                                        frame_data_clone
-                                    ).expect("Failed to create image buffer");
-
-                                    let mut output_file = std::fs::File::create(&path).expect("Failed to create output file");
-                                    let mut encoder = JpegEncoder::new_with_quality(&mut output_file, 20);
-
-                                    if let Err(e) = encoder.encode_image(&image) {
-                                        eprintln!("Failed to save screenshot: {}", e);
-                                    } else {
-                                        if !is_local_mode {
-                                            let rt = tokio::runtime::Runtime::new().unwrap();
-                                            let screenshot_file_path_owned_cloned_copy = screenshot_file_path_owned_cloned.clone();
-                                            rt.block_on(async {
-                                                let upload_task = tokio::spawn(upload_file(Some(options_clone), screenshot_file_path_owned_cloned_copy.clone(), "screenshot".to_string()));
-                                                match upload_task.await {
-                                                    Ok(result) => {
-                                                        match result {
-                                                            Ok(_) => println!("Screenshot captured and saved to {:?}", path),
-                                                            Err(e) => eprintln!("Failed to upload file: {}", e),
-                                                        }
-                                                    },
-                                                    Err(e) => eprintln!("Failed to join task: {}", e),
-                                                }
-                                            });
-                                        }
-                                        println!("Screenshot captured and saved to {:?}", path);
+                                    if let Some((uploader, upload_semaphore)) = thumbnail_uploader {
+                                        tauri::async_runtime::spawn(
+                                            upload_screenshot(uploader, upload_semaphore, screenshot_file_path_owned_cloned).in_current_span()
+                                        );
                                     }
                                 });
                             }
 
-                            if let Some(sender) = &video_channel_sender {
-                                if sender.try_send(frame_data).is_err() {
-                                    eprintln!("Channel send error. Dropping data.");
-                                }
+                            last_frame_data = Some(frame_data.clone());
+                            send_frame(frame_data);
+                            if let Some(timing_log) = &timing_log {
+                                timing_log.video_frame(frame_index, now);
+                            }
+
+                            if schedule_start.is_none() {
+                                schedule_start = Some(Instant::now());
                             }
 
                             let mut first_frame_time_guard = video_start_time_clone.try_lock();
@@ -376,180 +1021,518 @@ impl MediaRecorder {
                             if let Ok(ref mut start_time_option) = first_frame_time_guard {
                                 if start_time_option.is_none() {
                                     **start_time_option = Some(Instant::now()); 
+                                    if let Ok(mut video_started_at) = video_started_at.try_lock() {
+                                        *video_started_at = Some(Utc::now());
+                                    }
 
-                                    println!("Video start time captured");
+                                    debug!("Video start time captured");
+                                    emit_event(&capture_app_handle, "capture-live", CaptureLivePayload { source: "video".to_string() });
                                 }
                             }
 
                             frame_count += 1;
+                            failing_since = None;
+                            recent_frame_times.push_back(Instant::now());
                         },
                         Err(error) if error.kind() == WouldBlock => {
-                            std::thread::sleep(Duration::from_millis(1));
-                            continue;
+                            failing_since = None;
+                            // Keep waiting for a new frame until the next slot is due, then repeat the
+                            // previous one for this slot.
+                            match &last_frame_data {
+                                Some(frame_data) if now >= frame_due + spf => {
+                                    send_frame(frame_data.clone());
+                                    duplicated_frame_count += 1;
+                                },
+                                _ => {
+                                    std::thread::sleep(Duration::from_millis(1));
+                                    continue;
+                                },
+                            }
                         },
                         Err(error) => {
-                            eprintln!("Capture error: {}", error);
-                            break;
+                            // Some capturers fail frames instead of blacking out protected content, so
+                            // the previous frame is repeated and a capturer that keeps failing is
+                            // re-created once it counts as stalled.
+                            failing_since.get_or_insert(now);
+                            if !protected_content {
+                                protected_content = true;
+                                warn!("Capture error, the screen may show protected content: {}", error);
+                                emit_event(&capture_app_handle, "protected-content", ProtectedContentPayload {
+                                    active: true,
+                                    reason: "capture_error".to_string(),
+                                    masked: mask_protected_content,
+                                });
+                                if let Some(frame_data) = last_frame_data.as_mut().filter(|_| mask_protected_content) {
+                                    draw_protected_content_mask(frame_data, frame_width);
+                                }
+                            }
+                            match &last_frame_data {
+                                Some(frame_data) if now >= frame_due + spf => {
+                                    send_frame(frame_data.clone());
+                                    duplicated_frame_count += 1;
+                                },
+                                _ => {
+                                    std::thread::sleep(Duration::from_millis(1));
+                                    continue;
+                                },
+                            }
                         },
                     }
 
-                    time_next += spf;
+                    frame_index += 1;
+
+                    // Slots that passed while this one was handled get the same frame, so the encoded
+                    // stream keeps one frame per slot instead of running short.
+                    if let (Some(schedule_start), Some(frame_data)) = (schedule_start, &last_frame_data) {
+                        let slots_due = (schedule_start.elapsed().as_nanos() / spf.as_nanos()) as u32;
+                        while frame_index < slots_due {
+                            send_frame(frame_data.clone());
+                            duplicated_frame_count += 1;
+                            frame_index += 1;
+                        }
+                    }
                 }
 
-                // Sleep until the next frame time
-                let now = Instant::now();
-                if time_next > now {
-                    std::thread::sleep(time_next - now);
+                // Sleep until the next frame is due
+                if let Some(schedule_start) = schedule_start {
+                    let frame_due = schedule_start + spf * frame_index;
+                    let now = Instant::now();
+                    if frame_due > now {
+                        std::thread::sleep(frame_due - now);
+                    }
                 }
             }
 
             let elapsed_total_time = start_time.elapsed();
             let fps = frame_count as f64 / elapsed_total_time.as_secs_f64();
-            println!("Current FPS: {}", fps);
+            debug!("Current FPS: {} ({} frames captured, {} repeated)", fps, frame_count, duplicated_frame_count);
+            emit_event(&capture_app_handle, "ffmpeg-log", FfmpegLogPayload {
+                process: "capture".to_string(),
+                line: format!("Current FPS: {} ({} frames captured, {} repeated)", fps, frame_count, duplicated_frame_count),
+            });
         });
 
-        println!("Starting audio recording and processing...");
-        let audio_output_chunk_pattern = format!("{}/audio_recording_%03d.aac", audio_file_path_owned);
+        let (native_capture, (crop_x, crop_y, frame_width, frame_height, display_scale)) = match capture_init_rx.await.map_err(|_| "Capture thread exited before starting".to_string())? {
+            Ok(region) => (false, region),
+            Err(e) if options.native_capture_fallback => {
+                warn!("{}, falling back to ffmpeg's screen grabber", e);
+                emit_event(&app_handle, "capture-fallback", CaptureFallbackPayload { reason: e });
+                // ffmpeg grabs the frames itself, so the best estimate of the video start is now.
+                *video_start_time.lock().await = Some(Instant::now());
+                if let Ok(mut video_started_at) = self.video_started_at.try_lock() {
+                    *video_started_at = Some(Utc::now());
+                }
+                emit_event(&app_handle, "capture-live", CaptureLivePayload { source: "video".to_string() });
+                (true, (crop_x, crop_y, frame_width, frame_height, display_scale))
+            },
+            Err(e) => return Err(RecordingError::DisplayUnavailable(e)),
+        };
+
+        info!("Starting audio recording and processing...");
+        let audio_output_chunk_pattern = format!("{}/audio_recording_%03d.{}", audio_file_path_owned, audio_segment_extension);
         let audio_segment_list_filename = format!("{}/segment_list.txt", audio_file_path_owned);
-        let video_output_chunk_pattern = format!("{}/video_recording_%03d.mp4", video_file_path_owned);
+        let video_output_chunk_pattern = format!("{}/video_recording_%03d.{}", video_file_path_owned, video_segment_extension);
         let video_segment_list_filename = format!("{}/segment_list.txt", video_file_path_owned);
-      
-        let mut audio_filters = Vec::new();
-
-        if channels > 2 {
-            audio_filters.push("pan=stereo|FL=FL+0.5*FC|FR=FR+0.5*FC");
-        }
-
-        audio_filters.push("loudnorm");
-
-        let mut ffmpeg_audio_command: Vec<String> = vec![
-            "-f", sample_format,
-            "-ar", &sample_rate_str,
-            "-ac", &channels_str,
-            "-thread_queue_size", "4096",
-            "-i", "pipe:0",
-            "-af", "aresample=async=1:min_hard_comp=0.100000:first_pts=0",
-            "-c:a", "aac",
-            "-b:a", "128k",
-            "-async", "1",
-            "-f", "segment",
-            "-segment_time", "3",
-            "-segment_time_delta", "0.01",
-            "-segment_list", &audio_segment_list_filename,
-            "-reset_timestamps", "1",
-            &audio_output_chunk_pattern,
-        ].into_iter().map(|s| s.to_string()).collect();
-
-        let mut ffmpeg_video_command: Vec<String> = vec![
-            "-f", "rawvideo",
-            "-pix_fmt", "bgra",
-            "-s", &format!("{}x{}", adjusted_width, adjusted_height),
-            "-r", "30",
-            "-thread_queue_size", "4096",
-            "-i", "pipe:0",
-            "-vf", "fps=30,scale=in_range=full:out_range=limited",
-            "-c:v", "libx264",
-            "-preset", "ultrafast",
+
+        let mut audio_input_args: Vec<String> = Vec::new();
+
+        if let Some(source) = &primary_audio {
+            audio_input_args.extend(ffmpeg_audio_input_args(&options, &source.config, &primary_audio_input)?);
+        }
+
+        let audio_filter_args = match (&mixed_audio, &system_audio_pipe_path) {
+            (Some(source), Some(pipe_path)) => {
+                audio_input_args.extend(ffmpeg_audio_input_args(&options, &source.config, pipe_path)?);
+                let system_audio_input = first_audio_input + 1;
+                vec![
+                    "-filter_complex".to_string(),
+                    match &channel_filter {
+                        Some(channel_filter) => format!("[{}:a]{}[mic];[mic][{}:a]amix=inputs=2:duration=longest,{}[aout]", first_audio_input, channel_filter, system_audio_input, output_filter),
+                        None => format!("[{}:a][{}:a]amix=inputs=2:duration=longest,{}[aout]", first_audio_input, system_audio_input, output_filter),
+                    },
+                    "-map".to_string(),
+                    "[aout]".to_string(),
+                ]
+            },
+            _ => {
+                let audio_filter = match &channel_filter {
+                    Some(channel_filter) => format!("{},{}", channel_filter, output_filter),
+                    None => output_filter.clone(),
+                };
+                let mut audio_filter_args = vec!["-af".to_string(), audio_filter];
+                if single_muxed_output {
+                    audio_filter_args.extend(["-map".to_string(), format!("{}:a", first_audio_input)]);
+                }
+                audio_filter_args
+            },
+        };
+
+        // Frame pixels per point, the size windows and text are laid out in.
+        let points_scale = display_scale * backing_scale;
+        let output_resolution = match requested_resolution {
+            None if options.output_scale == "logical" && points_scale > 1.0 => Some((
+                Some(((frame_width as f64 / points_scale).round() as usize) & !1),
+                Some(((frame_height as f64 / points_scale).round() as usize) & !1),
+            )),
+            resolution => resolution,
+        };
+        let scale_filter = match output_resolution {
+            Some((width, height)) => format!(
+                "scale=w={}:h={}:in_range=full:out_range=limited",
+                width.map_or("-2".to_string(), |width| width.min(frame_width).to_string()),
+                height.map_or("-2".to_string(), |height| height.min(frame_height).to_string()),
+            ),
+            None => "scale=in_range=full:out_range=limited".to_string(),
+        };
+
+        let (output_width, output_height) = output_size(output_resolution, frame_width, frame_height);
+        self.encoding = EncodingInfo {
+            width: output_width,
+            height: output_height,
+            framerate,
+            video_codec: codec_arg(&video_encoder_args, "-c:v").unwrap_or_default(),
+            audio_codec: if record_audio { codec_arg(&audio_codec_args(&options), "-c:a") } else { None },
+            audio_device: None,
+        };
+        self.capture = CaptureInfo {
+            x: crop_x,
+            y: crop_y,
+            width: frame_width,
+            height: frame_height,
+            native_capture,
+            audio_sample_rate: primary_audio.as_ref().map(|source| source.config.sample_rate().0),
+            audio_channels: primary_audio.as_ref().map(|source| source.config.channels()),
+            audio_sample_format: primary_audio.as_ref().map(|source| source.info().sample_format),
+        };
+
+        let mut video_input_args: Vec<String>;
+        let mut video_filter = format!("fps={},{}", framerate, scale_filter);
+        if native_capture {
+            let (input_args, crop_filter) = native_capture_input_args(&options, framerate, (crop_x, crop_y, frame_width, frame_height), (adjusted_width, adjusted_height));
+            video_input_args = input_args;
+            if let Some(crop_filter) = crop_filter {
+                video_filter = format!("{},{}", crop_filter, video_filter);
+            }
+        } else {
+            video_input_args = vec![
+                "-f", "rawvideo",
+                "-pix_fmt", "bgra",
+                "-s", &format!("{}x{}", frame_width, frame_height),
+                "-r", &framerate.to_string(),
+            ].into_iter().map(|s| s.to_string()).collect();
+            video_input_args.extend(pipe_input_args(&options));
+            video_input_args.extend(["-i".to_string(), "pipe:0".to_string()]);
+        }
+        // Drawn after scaling, so the font size is in output pixels.
+        if let Some(overlay_filter) = overlay_filter {
+            video_filter = format!("{},{}", video_filter, overlay_filter);
+        }
+
+        let mut video_output_args: Vec<String> = vec!["-vf".to_string(), video_filter];
+        video_output_args.extend(video_encoder_args);
+        video_output_args.extend(vec![
             "-pix_fmt", "yuv420p",
-            "-tune", "zerolatency",
             "-vsync", "1",
-            "-force_key_frames", "expr:gte(t,n_forced*3)",
-            "-f", "segment",
-            "-segment_time", "3",
-            "-segment_time_delta", "0.01",
-            "-segment_list", &video_segment_list_filename,
-            "-segment_format", "mp4",
-            "-movflags", "frag_keyframe+empty_moov",
-            "-reset_timestamps", "1",
-            &video_output_chunk_pattern,
-        ].into_iter().map(|s| s.to_string()).collect();
+        ].into_iter().map(|s| s.to_string()));
+        video_output_args.extend(keyframe_args);
 
-        if custom_device != Some("None") {
-            println!("Adjusting FFmpeg commands based on start times...");
-            adjust_ffmpeg_commands_based_on_start_times(
+        if record_audio {
+            info!("Adjusting FFmpeg commands based on start times...");
+            let av_offset = adjust_ffmpeg_commands_based_on_start_times(
                 Arc::clone(&audio_start_time),
                 Arc::clone(&video_start_time),
-                &mut ffmpeg_audio_command,
-                &mut ffmpeg_video_command,
+                &mut audio_input_args,
+                &mut video_input_args,
+                Duration::from_millis(options.start_timeout_ms),
+                Duration::from_millis(options.av_offset_warning_ms),
+                &app_handle,
             ).await;
+
+            self.av_offset_seconds = av_offset?;
+        }
+
+        let mut ffmpeg_audio_command: Vec<String> = Vec::new();
+        let mut ffmpeg_video_command: Vec<String> = video_input_args;
+
+        if single_muxed_output {
+            ffmpeg_video_command.extend(audio_input_args);
+            ffmpeg_video_command.extend(["-map".to_string(), "0:v".to_string()]);
+            ffmpeg_video_command.extend(audio_filter_args);
+            ffmpeg_video_command.extend(video_output_args);
+            ffmpeg_video_command.extend(audio_output_args);
+            ffmpeg_video_command.extend(audio_codec_args(&options));
+        } else {
+            ffmpeg_audio_command.extend(audio_input_args);
+            ffmpeg_audio_command.extend(audio_filter_args);
+            ffmpeg_audio_command.extend(audio_output_args);
+            ffmpeg_audio_command.extend(segment_muxer_args(&audio_segment_list_filename, true));
+            ffmpeg_audio_command.extend(audio_segment_format_args);
+            ffmpeg_audio_command.extend(options.extra_audio_ffmpeg_args.iter().cloned());
+            ffmpeg_audio_command.push(audio_output_chunk_pattern);
+
+            ffmpeg_video_command.extend(video_output_args);
+        }
+
+        ffmpeg_video_command.extend(segment_muxer_args(&video_segment_list_filename, video_segment_extension != "m4s"));
+        ffmpeg_video_command.extend(video_segment_format_args);
+        if single_muxed_output {
+            ffmpeg_video_command.extend(options.extra_audio_ffmpeg_args.iter().cloned());
         }
+        ffmpeg_video_command.extend(options.extra_video_ffmpeg_args.iter().cloned());
+        ffmpeg_video_command.push(video_output_chunk_pattern);
 
-        println!("Starting FFmpeg audio and video processes...");
+        info!("Starting FFmpeg audio and video processes...");
 
         let mut audio_stdin: Option<ChildStdin> = None;
-        let mut audio_child: Option<Child> = None;
+        let mut audio_child: Option<SharedChild> = None;
+        // Checked once the recording is running, see `wait_for_process_startup`.
+        let mut started_processes: Vec<(&str, SharedChild, StderrTail)> = Vec::new();
 
-        if custom_device != Some("None") {
-            let (child, stdin) = self.start_audio_ffmpeg_processes(&ffmpeg_binary_path_str, &ffmpeg_audio_command).await.map_err(|e| e.to_string())?;
+        if record_audio && !single_muxed_output {
+            let stderr_tail = StderrTail::default();
+            let (child, stdin) = self.start_audio_ffmpeg_processes(&ffmpeg_binary_path_str, &ffmpeg_audio_command, stderr_tail.clone(), app_handle.clone()).await.map_err(ffmpeg_spawn_error)?;
+            let child = Arc::new(Mutex::new(child));
+            started_processes.push(("audio", Arc::clone(&child), stderr_tail.clone()));
+            self.spawn_process_supervisor("audio", Arc::clone(&child), stderr_tail, app_handle.clone());
             audio_child = Some(child);
             audio_stdin = Some(stdin);
-            println!("Audio process started");
+            info!("Audio process started");
         }
 
-        let (video_child, video_stdin) = self.start_video_ffmpeg_processes(&ffmpeg_binary_path_str, &ffmpeg_video_command).await.map_err(|e| e.to_string())?;
-        println!("Video process started");
+        let video_stderr_tail = StderrTail::default();
+        let (video_child, video_stdin) = self.start_video_ffmpeg_processes(&ffmpeg_binary_path_str, &ffmpeg_video_command, video_stderr_tail.clone(), app_handle.clone()).await.map_err(ffmpeg_spawn_error)?;
+        let video_child = Arc::new(Mutex::new(video_child));
+        started_processes.push(("video", Arc::clone(&video_child), video_stderr_tail.clone()));
+        self.spawn_process_supervisor("video", Arc::clone(&video_child), video_stderr_tail, app_handle.clone());
+        info!("Video process started");
         
         if let Some(ffmpeg_audio_stdin) = &self.ffmpeg_audio_stdin {
             let mut audio_stdin_lock = ffmpeg_audio_stdin.lock().await;
             *audio_stdin_lock = audio_stdin;
             drop(audio_stdin_lock);
-            println!("Audio stdin set");
+            debug!("Audio stdin set");
         }
 
         if let Some(ffmpeg_video_stdin) = &self.ffmpeg_video_stdin {
             let mut video_stdin_lock = ffmpeg_video_stdin.lock().await;
             *video_stdin_lock = Some(video_stdin);
             drop(video_stdin_lock);
-            println!("Video stdin set");
+            debug!("Video stdin set");
         }
 
-        if custom_device != Some("None") {
-            println!("Starting audio channel senders...");
+        if let Some(pipe_writer) = microphone_pipe_writer {
+            info!("Starting audio pipe writer...");
+            let is_stopping = Arc::clone(&self.is_stopping);
+            let failure = Arc::clone(&self.failure);
+            let app_handle = app_handle.clone();
+            let cancellation_token = self.cancellation_token.clone();
+            tokio::spawn(async move {
+                let mut writer = match pipe_writer.await {
+                    Ok(Ok(writer)) => writer,
+                    Ok(Err(e)) => {
+                        report_pipeline_failure(&failure, &app_handle, format!("Failed to open the microphone pipe: {}", e)).await;
+                        return;
+                    },
+                    Err(e) => {
+                        report_pipeline_failure(&failure, &app_handle, format!("Failed to open the microphone pipe: {}", e)).await;
+                        return;
+                    },
+                };
+
+                while let Some(bytes) = recv_or_cancelled(audio_channel_receiver.lock().await.as_mut().unwrap(), &cancellation_token).await {
+                    if let Err(e) = write_or_cancelled(&mut writer, &bytes, &cancellation_token).await {
+                        if !is_stopping.load(Ordering::SeqCst) {
+                            report_pipeline_failure(&failure, &app_handle, format!("Failed to write audio data to the FFmpeg pipe: {}", e)).await;
+                        }
+                        break;
+                    }
+                }
+            }.in_current_span());
+        } else if record_audio {
+            info!("Starting audio channel senders...");
+            let is_stopping = Arc::clone(&self.is_stopping);
+            let failure = Arc::clone(&self.failure);
+            let app_handle = app_handle.clone();
+            let cancellation_token = self.cancellation_token.clone();
             tokio::spawn(async move {
-                while let Some(bytes) = &audio_channel_receiver.lock().await.as_mut().unwrap().recv().await {
+                while let Some(bytes) = recv_or_cancelled(audio_channel_receiver.lock().await.as_mut().unwrap(), &cancellation_token).await {
                     if let Some(audio_stdin_arc) = &ffmpeg_audio_stdin{
                         let mut audio_stdin_guard = audio_stdin_arc.lock().await;
                         if let Some(ref mut stdin) = *audio_stdin_guard {
-                            stdin.write_all(&bytes).await.expect("Failed to write audio data to FFmpeg stdin");
+                            if let Err(e) = write_or_cancelled(stdin, &bytes, &cancellation_token).await {
+                                if !is_stopping.load(Ordering::SeqCst) {
+                                    report_pipeline_failure(&failure, &app_handle, format!("Failed to write audio data to FFmpeg stdin: {}", e)).await;
+                                }
+                                break;
+                            }
                         }
                         drop(audio_stdin_guard);
                     }
                 }
-            });
+            }.in_current_span());
         }
 
-        println!("Starting video channel senders...");
+        info!("Starting video channel senders...");
+        let is_stopping = Arc::clone(&self.is_stopping);
+        let failure = Arc::clone(&self.failure);
+        let video_app_handle = app_handle.clone();
+        let cancellation_token = self.cancellation_token.clone();
         tokio::spawn(async move {
-            while let Some(bytes) = &video_channel_receiver.lock().await.as_mut().unwrap().recv().await {
+            while let Some(bytes) = recv_or_cancelled(video_channel_receiver.lock().await.as_mut().unwrap(), &cancellation_token).await {
                 if let Some(video_stdin_arc) = &ffmpeg_video_stdin {
                     let mut video_stdin_guard = video_stdin_arc.lock().await;
                     if let Some(ref mut stdin) = *video_stdin_guard {
-                        stdin.write_all(&bytes).await.expect("Failed to write video data to FFmpeg stdin");
+                        if let Err(e) = write_or_cancelled(stdin, &bytes, &cancellation_token).await {
+                            if !is_stopping.load(Ordering::SeqCst) {
+                                report_pipeline_failure(&failure, &video_app_handle, format!("Failed to write video data to FFmpeg stdin: {}", e)).await;
+                            }
+                            break;
+                        }
                     }
                     drop(video_stdin_guard);
                 }
             }
-        });
+        }.in_current_span());
         
-        if custom_device != Some("None") {
+        if record_audio {
             self.ffmpeg_audio_process = audio_child;
         }
 
+        if let Some(ffmpeg_webcam_command) = ffmpeg_webcam_command {
+            let stderr_tail = StderrTail::default();
+            let webcam_child = start_recording_process(&ffmpeg_binary_path_str, &ffmpeg_webcam_command, "webcam", stderr_tail.clone(), options.ffmpeg_spawn_retries, app_handle.clone()).await
+                .map_err(|e| format!("Failed to start webcam recording process: {}", e))?;
+            let webcam_child = Arc::new(Mutex::new(webcam_child));
+            started_processes.push(("webcam", Arc::clone(&webcam_child), stderr_tail.clone()));
+            self.spawn_process_supervisor("webcam", Arc::clone(&webcam_child), stderr_tail, app_handle.clone());
+            self.ffmpeg_webcam_process = Some(webcam_child);
+            info!("Webcam process started");
+        }
+
+        if options.record_audio && !options.test_source {
+            for (track_index, device_name) in options.audio_track_names.iter().enumerate() {
+                let track_dir = Path::new(audio_file_path).with_file_name(audio_track_video_type(track_index));
+                match self.start_audio_track(device_name, &track_dir, &options, Arc::clone(&video_start_time), &ffmpeg_binary_path_str, app_handle.clone()).await {
+                    Ok(track) => {
+                        info!("Recording audio track {} from {}", track_index, track.name);
+                        self.audio_tracks.push(track);
+                    },
+                    Err(e) => warn!("Skipping audio track {} ({}): {}", track_index, device_name, e),
+                }
+            }
+        }
+
         self.start_time = Some(Instant::now());
         self.audio_file_path = Some(audio_file_path_owned);
         self.video_file_path = Some(video_file_path_owned);
         self.ffmpeg_video_process = Some(video_child);
-        self.device_name = Some(device.name().expect("Failed to get device name"));
+        self.device_name = primary_audio.map(|source| source.name);
+        self.encoding.audio_device = self.device_name.clone();
+
+        let startup_timeout = Duration::from_millis(options.ffmpeg_startup_timeout_ms);
+        let startup_checks = started_processes.iter().map(|(process_name, child, stderr_tail)| wait_for_process_startup(child, process_name, stderr_tail, startup_timeout));
+        futures::future::try_join_all(startup_checks).await?;
         
-        println!("End of the start_audio_recording function");
+        info!("End of the start_audio_recording function");
         
         Ok(())
     }
 
-    pub fn trigger_play (&mut self) -> Result<(), &'static str> {
+    /// Records `device_name` into its own segments under `chunks_dir`, offset so it lines up with
+    /// the video the same way the primary audio does.
+    async fn start_audio_track(
+        &self,
+        device_name: &str,
+        chunks_dir: &Path,
+        options: &RecordingOptions,
+        video_start_time: Arc<Mutex<Option<Instant>>>,
+        ffmpeg_binary_path: &str,
+        app_handle: AppHandle,
+    ) -> Result<AudioTrack, String> {
+        let host = cpal::default_host();
+        let device = input_devices(&host)?
+            .into_iter()
+            .find(|d| d.name().map(|name| name == device_name).unwrap_or(false))
+            .ok_or(format!("Audio device '{}' not found", device_name))?;
+        let source = input_audio_source(device)?;
+
+        let (sender, mut receiver) = mpsc::channel::<Vec<u8>>(options.channel_buffer_size.max(1));
+        let start_time = Arc::new(Mutex::new(None));
+        let stream = build_audio_input_stream(&source, sender, Arc::clone(&start_time), options.compensate_input_latency, Arc::clone(&self.drop_counters), None, Arc::new(AtomicBool::new(false)), None, Arc::clone(&self.paused))?;
+        stream.play().map_err(|e| format!("Failed to play audio track stream: {}", e))?;
+
+        let (track_start, video_start) = wait_for_start_times(start_time, video_start_time, Duration::from_millis(options.start_timeout_ms)).await?;
+
+        // The video itself is delayed when the primary audio started after it, so the track has to be
+        // delayed by the same amount on top of its own start difference.
+        let track_offset = if track_start > video_start {
+            track_start.duration_since(video_start).as_secs_f64()
+        } else {
+            -video_start.duration_since(track_start).as_secs_f64()
+        } + self.av_offset_seconds.max(0.0);
+
+        let channel_filter = audio_channel_filter(&options.audio_channel_mode, source.config.channels())?;
+        let output_filter = audio_output_filter(options)?;
+        let (segment_extension, segment_format_args) = audio_segment_format_args(options)?;
+        let chunks_dir = chunks_dir.to_string_lossy();
+
+        let mut ffmpeg_command: Vec<String> = Vec::new();
+        if track_offset > 0.0 {
+            ffmpeg_command.extend(["-itsoffset".to_string(), format!("{:.3}", track_offset)]);
+        }
+        ffmpeg_command.extend(ffmpeg_audio_input_args(options, &source.config, "pipe:0")?);
+        if track_offset < 0.0 {
+            // The track started before the video, so drop what it recorded before the first frame.
+            ffmpeg_command.extend(["-ss".to_string(), format!("{:.3}", -track_offset)]);
+        }
+        ffmpeg_command.extend([
+            "-af".to_string(),
+            match channel_filter {
+                Some(channel_filter) => format!("{},{}", channel_filter, output_filter),
+                None => output_filter,
+            },
+        ]);
+        ffmpeg_command.extend(["-async".to_string(), "1".to_string()]);
+        ffmpeg_command.extend(audio_encoder_args(options, output_channels(&options.audio_channel_mode, source.config.channels()))?);
+        ffmpeg_command.extend(segment_muxer_args(&format!("{}/segment_list.txt", chunks_dir), true));
+        ffmpeg_command.extend(output_sample_rate_args(options, source.config.sample_rate().0)?);
+        ffmpeg_command.extend(segment_format_args);
+        ffmpeg_command.push(format!("{}/audio_recording_%03d.{}", chunks_dir, segment_extension));
+
+        let process_name = format!("audio track {}", source.name);
+        let stderr_tail = StderrTail::default();
+        let (process, stdin) = self.start_audio_ffmpeg_processes(ffmpeg_binary_path, &ffmpeg_command, stderr_tail.clone(), app_handle.clone()).await
+            .map_err(|e| e.to_string())?;
+        let process = Arc::new(Mutex::new(process));
+        self.spawn_process_supervisor(&process_name, Arc::clone(&process), stderr_tail, app_handle.clone());
+
+        let stdin = Arc::new(Mutex::new(Some(stdin)));
+        let writer_stdin = Arc::clone(&stdin);
+        let is_stopping = Arc::clone(&self.is_stopping);
+        let cancellation_token = self.cancellation_token.clone();
+        tokio::spawn(async move {
+            while let Some(bytes) = recv_or_cancelled(&mut receiver, &cancellation_token).await {
+                let mut stdin_guard = writer_stdin.lock().await;
+                let stdin = match stdin_guard.as_mut() {
+                    Some(stdin) => stdin,
+                    None => break,
+                };
+                if let Err(e) = write_or_cancelled(stdin, &bytes, &cancellation_token).await {
+                    if !is_stopping.load(Ordering::SeqCst) {
+                        error!("Failed to write data to FFmpeg {} stdin: {}", process_name, e);
+                    }
+                    break;
+                }
+            }
+        }.in_current_span());
+
+        Ok(AudioTrack { name: source.name, stream, process, stdin })
+    }
+
+    pub fn trigger_play (&mut self) -> Result<(), &'static str> {
         if let Some(ref mut stream) = self.stream {
             stream.play().map_err(|_| "Failed to play stream")?;
-            println!("Audio recording playing.");
+            info!("Audio recording playing.");
         } else {
             return Err("Starting the recording did not work");
         }
@@ -558,12 +1541,17 @@ impl MediaRecorder {
     }
 
     pub async fn stop_media_recording(&mut self) -> Result<(), String> {
-        if let Some(start_time) = self.start_time {
+        self.is_stopping.store(true, Ordering::SeqCst);
+
+        // Every step below runs even if an earlier one failed, so the processes are always shut
+        // down and their last segments finalized.
+        let mut errors = Vec::new();
+
+        if let (Some(start_time), Some(audio_file_path), Some(video_file_path)) = (self.start_time, self.audio_file_path.clone(), self.video_file_path.clone()) {
             let segment_duration = Duration::from_secs(3);
-            let recording_duration = start_time.elapsed();
+            // Nothing is recorded while paused, so fewer segments are written.
+            let recording_duration = start_time.elapsed().saturating_sub(self.paused.total_paused());
             let expected_segments = recording_duration.as_secs() / segment_duration.as_secs();
-            let audio_file_path = self.audio_file_path.as_ref().ok_or("Audio file path not set")?;
-            let video_file_path = self.video_file_path.as_ref().ok_or("Video file path not set")?;
             let audio_segment_list_filename = format!("{}/segment_list.txt", audio_file_path);
             let video_segment_list_filename = format!("{}/segment_list.txt", video_file_path);
 
@@ -574,8 +1562,16 @@ impl MediaRecorder {
                 let audio_segment_count = audio_segments.lines().count();
                 let video_segment_count = video_segments.lines().count();
 
-                if audio_segment_count >= expected_segments as usize && video_segment_count >= expected_segments as usize {
-                    println!("All segments generated");
+                // Video-only recordings never produce audio segments.
+                let audio_done = self.ffmpeg_audio_process.is_none() || audio_segment_count >= expected_segments as usize;
+
+                if audio_done && video_segment_count >= expected_segments as usize {
+                    info!("All segments generated");
+                    break;
+                }
+
+                if self.failure.lock().await.is_some() {
+                    error!("Recording pipeline failed, not waiting for remaining segments");
                     break;
                 }
 
@@ -587,7 +1583,7 @@ impl MediaRecorder {
             let mut audio_stdin_guard = ffmpeg_audio_stdin.lock().await;
             if let Some(mut audio_stdin) = audio_stdin_guard.take() {
                 if let Err(e) = audio_stdin.write_all(b"q\n").await {
-                    eprintln!("Failed to send 'q' to audio FFmpeg process: {}", e);
+                    error!("Failed to send 'q' to audio FFmpeg process: {}", e);
                 }
                 let _ = audio_stdin.shutdown().await.map_err(|e| e.to_string());
             }
@@ -597,12 +1593,19 @@ impl MediaRecorder {
             let mut video_stdin_guard = ffmpeg_video_stdin.lock().await;
             if let Some(mut video_stdin) = video_stdin_guard.take() {
                 if let Err(e) = video_stdin.write_all(b"q\n").await {
-                    eprintln!("Failed to send 'q' to video FFmpeg process: {}", e);
+                    error!("Failed to send 'q' to video FFmpeg process: {}", e);
                 }
                 let _ = video_stdin.shutdown().await.map_err(|e| e.to_string());
             }
         }
 
+        for track in &self.audio_tracks {
+            let _ = track.stream.pause();
+            if let Some(mut stdin) = track.stdin.lock().await.take() {
+                let _ = stdin.shutdown().await.map_err(|e| e.to_string());
+            }
+        }
+
         self.should_stop.store(true, Ordering::SeqCst);
 
         if let Some(sender) = self.audio_channel_sender.take() {
@@ -613,37 +1616,157 @@ impl MediaRecorder {
             drop(sender);
         }
 
+        if let Some(process) = self.ffmpeg_webcam_process.take() {
+            let mut process = process.lock().await;
+            // The webcam is captured by ffmpeg itself, so ask it to quit to finalize the last segment.
+            if let Some(stdin) = process.stdin.as_mut() {
+                if let Err(e) = stdin.write_all(b"q\n").await {
+                    error!("Failed to send 'q' to webcam FFmpeg process: {}", e);
+                }
+            }
+            if tokio::time::timeout(Duration::from_secs(5), process.wait()).await.is_err() {
+                let _ = process.kill().await.map_err(|e| e.to_string());
+            }
+        }
+
+        // Dropping the stream drops its channel sender, which closes the system audio pipe.
+        if let Some(stream) = self.system_audio_stream.take() {
+            let _ = stream.pause();
+        }
+
         if let Some(ref mut stream) = self.stream {
-            stream.pause().map_err(|_| "Failed to pause stream")?;
-            println!("Audio recording paused.");
+            match stream.pause() {
+                Ok(()) => info!("Audio recording paused."),
+                Err(e) => {
+                    error!("Failed to pause stream: {}", e);
+                    errors.push(format!("Failed to pause stream: {}", e));
+                },
+            }
         } else {
-            return Err("Original recording was not started".to_string());
+            info!("No audio stream to stop, video-only recording.");
         }
 
-        if let Some(process) = &mut self.ffmpeg_audio_process {
-            let _ = process.kill().await.map_err(|e| e.to_string());
+        // Their stdin has been closed, so they finalize the last segment and exit on their own.
+        if let Some(process) = &self.ffmpeg_audio_process {
+            wait_for_process_exit(process, "audio").await;
         }
 
-        if let Some(process) = &mut self.ffmpeg_video_process {
-            let _ = process.kill().await.map_err(|e| e.to_string());
+        if let Some(process) = &self.ffmpeg_video_process {
+            wait_for_process_exit(process, "video").await;
+        }
+
+        for track in self.audio_tracks.drain(..) {
+            wait_for_process_exit(&track.process, &format!("audio track {}", track.name)).await;
+        }
+
+        info!("Audio recording stopped.");
+
+        if let Some(timing_log) = &self.timing_log {
+            timing_log.flush().await;
+        }
+
+        if let Some(failure) = self.failure.lock().await.take() {
+            errors.push(failure);
+        }
+
+        if !errors.is_empty() {
+            return Err(errors.join("; "));
         }
 
-        println!("Audio recording stopped.");
         Ok(())
     }
 
+    /// Tears the recording down immediately, killing every ffmpeg process instead of letting it
+    /// finish its current segment.
+    pub async fn cancel_media_recording(&mut self) {
+        self.is_stopping.store(true, Ordering::SeqCst);
+        self.should_stop.store(true, Ordering::SeqCst);
+
+        self.audio_channel_sender.take();
+        self.video_channel_sender.take();
+
+        if let Some(stream) = self.system_audio_stream.take() {
+            let _ = stream.pause();
+        }
+
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.pause();
+        }
+
+        let track_processes = self.audio_tracks.drain(..).map(|track| track.process).collect::<Vec<_>>();
+
+        for process in [self.ffmpeg_audio_process.take(), self.ffmpeg_video_process.take(), self.ffmpeg_webcam_process.take()].into_iter().flatten().chain(track_processes) {
+            if let Err(e) = process.lock().await.kill().await {
+                error!("Failed to kill FFmpeg process: {}", e);
+            }
+        }
+
+        info!("Media recording cancelled.");
+    }
+
+    /// Watches an ffmpeg process and, if it exits before the recording is stopped, records the
+    /// failure and emits `ffmpeg-exited` with the exit code and the tail of its stderr.
+    fn spawn_process_supervisor(&self, process_name: &str, child: SharedChild, stderr_tail: StderrTail, app_handle: AppHandle) {
+        let process_name = process_name.to_string();
+        let is_stopping = Arc::clone(&self.is_stopping);
+        let failure = Arc::clone(&self.failure);
+        let cancellation_token = self.cancellation_token.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = cancellation_token.cancelled() => break,
+                    _ = tokio::time::sleep(Duration::from_millis(500)) => {},
+                }
+
+                if is_stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let status = match child.lock().await.try_wait() {
+                    Ok(Some(status)) => status,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!("Failed to poll FFmpeg {} process: {}", process_name, e);
+                        break;
+                    }
+                };
+
+                if is_stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let stderr_tail = stderr_tail.lock().await.iter().cloned().collect::<Vec<_>>().join("\n");
+                emit_event(&app_handle, "ffmpeg-exited", FfmpegExitedPayload {
+                    process: process_name.clone(),
+                    exit_code: status.code(),
+                    stderr_tail: stderr_tail.clone(),
+                });
+                report_pipeline_failure(
+                    &failure,
+                    &app_handle,
+                    format!("FFmpeg {} process exited unexpectedly ({}):\n{}", process_name, status, stderr_tail),
+                ).await;
+                break;
+            }
+        }.in_current_span());
+    }
+
     async fn start_audio_ffmpeg_processes(
         &self,
         ffmpeg_binary_path: &str,
         audio_ffmpeg_command: &[String],
+        stderr_tail: StderrTail,
+        app_handle: AppHandle,
     ) -> Result<(Child, ChildStdin), Error> {
-        let mut audio_process = start_recording_process(ffmpeg_binary_path, audio_ffmpeg_command).await.map_err(|e| {
-            eprintln!("Failed to start audio recording process: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+        let spawn_retries = self.options.as_ref().map_or(0, |options| options.ffmpeg_spawn_retries);
+        let mut audio_process = start_recording_process(ffmpeg_binary_path, audio_ffmpeg_command, "audio", stderr_tail, spawn_retries, app_handle).await.map_err(|e| {
+            error!("Failed to start audio recording process: {}", e);
+            std::io::Error::new(e.kind(), e.to_string())
         })?;
 
         let audio_stdin = audio_process.stdin.take().ok_or_else(|| {
-            eprintln!("Failed to take audio stdin");
+            error!("Failed to take audio stdin");
             std::io::Error::new(std::io::ErrorKind::Other, "Failed to take audio stdin")
         })?;
 
@@ -654,14 +1777,17 @@ impl MediaRecorder {
         &self,
         ffmpeg_binary_path: &str,
         video_ffmpeg_command: &[String],
+        stderr_tail: StderrTail,
+        app_handle: AppHandle,
     ) -> Result<(Child, ChildStdin), Error> {
-        let mut video_process = start_recording_process(ffmpeg_binary_path, video_ffmpeg_command).await.map_err(|e| {
-            eprintln!("Failed to start video recording process: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+        let spawn_retries = self.options.as_ref().map_or(0, |options| options.ffmpeg_spawn_retries);
+        let mut video_process = start_recording_process(ffmpeg_binary_path, video_ffmpeg_command, "video", stderr_tail, spawn_retries, app_handle).await.map_err(|e| {
+            error!("Failed to start video recording process: {}", e);
+            std::io::Error::new(e.kind(), e.to_string())
         })?;
 
         let video_stdin = video_process.stdin.take().ok_or_else(|| {
-            eprintln!("Failed to take video stdin");
+            error!("Failed to take video stdin");
             std::io::Error::new(std::io::ErrorKind::Other, "Failed to take video stdin")
         })?;
 
@@ -670,107 +1796,2513 @@ impl MediaRecorder {
 
 }
 
+/// Lists the input devices with their position in the list, which identifies a device even when
+/// several share a name. The default input device comes first.
 #[tauri::command]
-pub fn enumerate_audio_devices() -> Vec<String> {
+pub fn enumerate_audio_devices() -> Result<Vec<AudioDevice>, String> {
     let host = cpal::default_host();
-    let default_device = host.default_input_device().expect("No default input device available");
-    let default_device_name = default_device.name().expect("Failed to get default device name");
 
-    let devices = host.devices().expect("Failed to get devices");
-    let mut input_device_names: Vec<String> = devices
-        .filter_map(|device| {
+    Ok(input_devices(&host)?
+        .iter()
+        .enumerate()
+        .map(|(index, device)| AudioDevice { index, name: device.name().unwrap_or_default() })
+        .collect())
+}
+
+/// Input devices in the order `enumerate_audio_devices` reports them.
+fn input_devices(host: &cpal::Host) -> Result<Vec<cpal::Device>, String> {
+    let default_device_name = host.default_input_device().and_then(|device| device.name().ok());
+
+    let devices = host.devices().map_err(|e| format!("Failed to get devices: {}", e))?;
+    let mut input_devices: Vec<cpal::Device> = devices
+        .filter(|device| {
             let supported_input_configs = device.supported_input_configs();
-            if supported_input_configs.is_ok() && supported_input_configs.unwrap().count() > 0 {
-                device.name().ok()
-            } else {
-                None
-            }
+            supported_input_configs.is_ok() && supported_input_configs.unwrap().count() > 0
         })
         .collect();
 
-    input_device_names.retain(|name| name != &default_device_name);
-    input_device_names.insert(0, default_device_name);
+    if let Some(default_device_name) = default_device_name {
+        if let Some(position) = input_devices.iter().position(|device| device.name().map(|name| name == default_device_name).unwrap_or(false)) {
+            let default_device = input_devices.remove(position);
+            input_devices.insert(0, default_device);
+        }
+    }
 
-    input_device_names
+    Ok(input_devices)
 }
 
-use tokio::io::{BufReader, AsyncBufReadExt};
+/// Reports the config recording would use for `device_name`. If the device can't be found this
+/// describes the default input device, which is what recording falls back to.
+#[tauri::command]
+pub fn get_audio_device_info(device_name: Option<String>, device_index: Option<usize>) -> Result<AudioDeviceInfo, String> {
+    let host = cpal::default_host();
+    let source = select_input_device(&host, device_name.as_deref(), device_index)?;
+    Ok(source.info())
+}
 
-async fn start_recording_process(
-    ffmpeg_binary_path_str: &str, 
-    args: &[String], 
-) -> Result<tokio::process::Child, std::io::Error> {
-    let mut process = Command::new(ffmpeg_binary_path_str)
-        .args(args)
-        .stdin(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
+/// Lists every input config range `device_name` supports, so a specific format and sample rate
+/// can be picked for recording.
+#[tauri::command]
+pub fn get_device_capabilities(device_name: String) -> Result<Vec<AudioDeviceCapability>, String> {
+    let host = cpal::default_host();
+    let device = host.input_devices()
+        .map_err(|e| format!("Failed to get input devices: {}", e))?
+        .find(|d| d.name().map(|name| name == device_name).unwrap_or(false))
+        .ok_or(format!("Audio device '{}' not found", device_name))?;
 
-    if let Some(process_stderr) = process.stderr.take() {
-      tokio::spawn(async move {
-            let mut process_reader = BufReader::new(process_stderr).lines();
-            while let Ok(Some(line)) = process_reader.next_line().await {
-                eprintln!("FFmpeg process STDERR: {}", line);
+    let capabilities = device.supported_input_configs()
+        .map_err(|e| format!("Failed to get supported input configs: {}", e))?
+        .map(|config| AudioDeviceCapability {
+            sample_format: format!("{:?}", config.sample_format()).to_lowercase(),
+            channels: config.channels(),
+            min_sample_rate: config.min_sample_rate().0,
+            max_sample_rate: config.max_sample_rate().0,
+        })
+        .collect();
+
+    Ok(capabilities)
+}
+
+/// Lists devices that can be used for `capture_system_audio`. On Windows these are output devices
+/// captured through WASAPI loopback. macOS has no loopback API, so system audio has to be routed
+/// through an aggregate or virtual loopback input device (e.g. BlackHole), which is listed here
+/// alongside the other input devices.
+#[tauri::command]
+pub fn enumerate_system_audio_devices() -> Result<Vec<String>, String> {
+    let host = cpal::default_host();
+
+    #[cfg(target_os = "windows")]
+    let devices = host.output_devices().map_err(|e| format!("Failed to get output devices: {}", e))?;
+
+    #[cfg(not(target_os = "windows"))]
+    let devices = host.input_devices().map_err(|e| format!("Failed to get input devices: {}", e))?;
+
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+/// Picks the input device at `device_index` in the `enumerate_audio_devices` list, otherwise the
+/// one named `custom_device`, otherwise the default input device.
+fn select_input_device(host: &cpal::Host, custom_device: Option<&str>, device_index: Option<usize>) -> Result<AudioSource, String> {
+    let mut input_devices = input_devices(host)?;
+
+    let indexed_input_device = device_index
+        .filter(|index| *index < input_devices.len())
+        .map(|index| input_devices.swap_remove(index));
+
+    let custom_input_device = indexed_input_device.or_else(|| custom_device.and_then(|custom_device_name| {
+        input_devices.into_iter().find(|d| d.name().map(|name| name == custom_device_name).unwrap_or(false))
+    }));
+
+    let device = match custom_input_device {
+        Some(device) => device,
+        None => host.default_input_device().ok_or("No default input device available".to_string())?,
+    };
+
+    input_audio_source(device)
+}
+
+/// Picks the config an input device is recorded with.
+fn input_audio_source(device: cpal::Device) -> Result<AudioSource, String> {
+    let name = device.name().map_err(|e| format!("Failed to get device name: {}", e))?;
+
+    let supported_configs: Vec<_> = device.supported_input_configs()
+        .map_err(|e| format!("Failed to get supported input configs: {}", e))?
+        .collect();
+
+    let config = supported_configs.iter()
+        .find(|c| NATIVE_SAMPLE_FORMATS.contains(&c.sample_format()))
+        .or_else(|| supported_configs.iter().find(|c| CONVERTED_SAMPLE_FORMATS.contains(&c.sample_format())))
+        .cloned()
+        .ok_or_else(|| format!(
+            "Audio device '{}' has no input config with a supported sample format (offers {:?})",
+            name,
+            supported_configs.iter().map(|c| c.sample_format()).collect::<Vec<_>>(),
+        ))?
+        .with_max_sample_rate();
+
+    Ok(AudioSource { device: Some(device), name, config })
+}
+
+fn select_system_audio_device(host: &cpal::Host, device_name: Option<&str>) -> Result<AudioSource, String> {
+    #[cfg(target_os = "windows")]
+    let (device, config) = {
+        // WASAPI records an output device in loopback mode when an input stream is built on it.
+        let device = match device_name {
+            Some(device_name) => host.output_devices()
+                .map_err(|e| format!("Failed to get output devices: {}", e))?
+                .find(|d| d.name().map(|name| name == device_name).unwrap_or(false))
+                .ok_or(format!("System audio device '{}' not found", device_name))?,
+            None => host.default_output_device().ok_or("No default output device available".to_string())?,
+        };
+        let config = device.default_output_config()
+            .map_err(|e| format!("Failed to get system audio config: {}", e))?;
+        (device, config)
+    };
+
+    #[cfg(not(target_os = "windows"))]
+    let (device, config) = {
+        let device_name = device_name
+            .ok_or("System audio capture requires a loopback input device, such as an aggregate device".to_string())?;
+        let device = host.input_devices()
+            .map_err(|e| format!("Failed to get input devices: {}", e))?
+            .find(|d| d.name().map(|name| name == device_name).unwrap_or(false))
+            .ok_or(format!("System audio device '{}' not found", device_name))?;
+        let config = device.default_input_config()
+            .map_err(|e| format!("Failed to get system audio config: {}", e))?;
+        (device, config)
+    };
+
+    let name = device.name().map_err(|e| format!("Failed to get device name: {}", e))?;
+
+    Ok(AudioSource { device: Some(device), name, config })
+}
+
+/// The raw format ffmpeg reads a device's samples in, after `build_audio_input_stream` has
+/// converted the unsigned and `f64` ones.
+fn ffmpeg_sample_format(sample_format: SampleFormat) -> Result<&'static str, String> {
+    match sample_format {
+        SampleFormat::I8 | SampleFormat::U8 => Ok("s8"),
+        SampleFormat::I16 | SampleFormat::U16 => Ok("s16le"),
+        SampleFormat::I32 | SampleFormat::U32 => Ok("s32le"),
+        SampleFormat::F32 | SampleFormat::F64 => Ok("f32le"),
+        unsupported => Err(format!("Unsupported sample format: {:?}", unsupported)),
+    }
+}
+
+/// Rejects `container_format` values and codec combinations that can't be muxed together, such
+/// as AAC or H.264 in WebM.
+fn validate_container_format(options: &RecordingOptions) -> Result<(), String> {
+    match options.container_format.as_str() {
+        "mpegts" => Ok(()),
+        "webm" if options.hardware_encoder.is_some() => Err("Hardware encoders produce H.264, which can't be stored in WebM".to_string()),
+        "webm" if options.audio_segment_format == "fmp4" => Err("fmp4 audio segments hold AAC, which can't be stored in WebM".to_string()),
+        "webm" if options.video_segment_format == "fmp4" => Err("fmp4 video segments hold H.264, which can't be stored in WebM".to_string()),
+        "webm" => Ok(()),
+        format => Err(format!("Unknown container format '{}', expected mpegts or webm", format)),
+    }?;
+
+    match options.video_segment_format.as_str() {
+        "mpegts" | "fmp4" => Ok(()),
+        format => Err(format!("Unknown video segment format '{}', expected mpegts or fmp4", format)),
+    }
+}
+
+/// Maps the container and `audio_segment_format` to the audio segment file extension and the
+/// codec and segment muxer args. `adts` writes raw AAC files, `fmp4` writes fragmented MP4 files
+/// like the video segments, and WebM recordings use Opus.
+fn audio_segment_format_args(options: &RecordingOptions) -> Result<(&'static str, Vec<String>), String> {
+    let (extension, segment_args) = match (options.container_format.as_str(), options.audio_segment_format.as_str()) {
+        ("webm", _) => ("webm", vec!["-segment_format", "webm"]),
+        (_, "adts") => ("aac", vec![]),
+        (_, "fmp4") => ("m4a", vec![
+            "-segment_format", "mp4",
+            "-movflags", "frag_keyframe+empty_moov",
+        ]),
+        (_, format) => return Err(format!("Unknown audio segment format '{}', expected adts or fmp4", format)),
+    };
+
+    let mut args = audio_codec_args(options);
+    args.extend(segment_args.into_iter().map(|s| s.to_string()));
+    Ok((extension, args))
+}
+
+/// Flags `extra_*_ffmpeg_args` may not contain, as they would add an input or change the output
+/// the segment muxer writes. `-segment*` flags are rejected as well.
+const RESERVED_FFMPEG_ARGS: [&str; 5] = ["-i", "-f", "-y", "-n", "-reset_timestamps"];
+
+/// Checks that user supplied ffmpeg args only add output options. A value that doesn't follow a
+/// flag would be taken by ffmpeg as another output file.
+pub fn validate_extra_ffmpeg_args(option_name: &str, args: &[String]) -> Result<(), String> {
+    let mut follows_flag = false;
+    for arg in args {
+        // Negative numbers are values, not flags.
+        let is_flag = arg.len() > 1 && arg.starts_with('-') && !arg[1..].starts_with(|c: char| c.is_ascii_digit() || c == '.');
+        if is_flag {
+            if RESERVED_FFMPEG_ARGS.contains(&arg.as_str()) || arg.starts_with("-segment") {
+                return Err(format!("{} must not contain '{}', which would break segmentation", option_name, arg));
             }
-        });
+            follows_flag = true;
+        } else if follows_flag {
+            follows_flag = false;
+        } else {
+            return Err(format!("{} must not contain '{}', which FFmpeg would treat as an extra output", option_name, arg));
+        }
     }
+    Ok(())
+}
 
-    Ok(process)
+/// Checks `output_scale` is `physical` or `logical`.
+pub fn validate_output_scale(options: &RecordingOptions) -> Result<(), String> {
+    match options.output_scale.as_str() {
+        "physical" | "logical" => Ok(()),
+        scale => Err(format!("Unknown output scale '{}', expected physical or logical", scale)),
+    }
 }
 
-async fn wait_for_start_times(
-    audio_start_time: Arc<Mutex<Option<Instant>>>,
-    video_start_time: Arc<Mutex<Option<Instant>>>,
-) -> (Instant, Instant) {
-    loop {
-        let audio_start_locked = audio_start_time.lock().await;
-        let video_start_locked = video_start_time.lock().await;
-        
-        if audio_start_locked.is_some() && video_start_locked.is_some() {
-            let audio_start = *audio_start_locked.as_ref().unwrap();
-            let video_start = *video_start_locked.as_ref().unwrap();
-            return (audio_start, video_start);
+/// Checks `output_sample_rate`: WebM audio is Opus, which is always recorded at 48kHz.
+pub fn validate_output_sample_rate(options: &RecordingOptions) -> Result<(), String> {
+    match options.output_sample_rate {
+        Some(rate) if !(MIN_OUTPUT_SAMPLE_RATE..=MAX_OUTPUT_SAMPLE_RATE).contains(&rate) => {
+            Err(format!("Output sample rate {} is out of range, expected {} to {}", rate, MIN_OUTPUT_SAMPLE_RATE, MAX_OUTPUT_SAMPLE_RATE))
+        },
+        Some(rate) if rate != 48000 && options.container_format == "webm" => {
+            Err(format!("Output sample rate {} is not supported for WebM, which is always 48000", rate))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Resamples the audio to `output_sample_rate`. Without one, the device rate is kept, which has
+/// to be set explicitly after loudnorm as it upsamples to 192kHz internally.
+fn output_sample_rate_args(options: &RecordingOptions, device_sample_rate: u32) -> Result<Vec<String>, String> {
+    validate_output_sample_rate(options)?;
+
+    let rate = match options.output_sample_rate {
+        Some(rate) => rate,
+        None if options.audio_normalize => device_sample_rate,
+        None => return Ok(Vec::new()),
+    };
+
+    Ok(vec!["-ar".to_string(), rate.to_string()])
+}
+
+/// The audio encoder for `container_format` and `aac_profile`. FFmpeg's own AAC encoder can't
+/// produce HE-AAC, so those profiles use libfdk_aac.
+fn audio_encoder(options: &RecordingOptions) -> &'static str {
+    match (options.container_format.as_str(), options.aac_profile.as_deref()) {
+        ("webm", _) => "libopus",
+        (_, Some("he" | "hev2")) => "libfdk_aac",
+        _ => "aac",
+    }
+}
+
+/// The audio encoder used in `container_format`.
+fn audio_codec_args(options: &RecordingOptions) -> Vec<String> {
+    let encoder = audio_encoder(options);
+    let mut args = vec!["-c:a".to_string(), encoder.to_string()];
+    if encoder == "libopus" {
+        // Opus only supports a few sample rates, 48kHz being the one every device can be resampled to.
+        args.extend(["-ar".to_string(), "48000".to_string()]);
+    }
+    args
+}
+
+/// How many channels `audio_channel_mode` leaves a source with.
+fn output_channels(mode: &str, channels: u16) -> u16 {
+    match mode {
+        "mono" => 1,
+        "stereo" => 2,
+        _ => channels,
+    }
+}
+
+/// Checks `aac_profile` against the container and channel count. The channel count is only known
+/// once the devices are open, so without one a HE-AACv2 recording is only rejected if
+/// `audio_channel_mode` makes it mono.
+pub fn validate_aac_profile(options: &RecordingOptions, output_channels: Option<u16>) -> Result<(), String> {
+    match (options.aac_profile.as_deref(), options.container_format.as_str()) {
+        (None, _) => Ok(()),
+        (Some(_), "webm") => Err("WebM audio is Opus, so aac_profile can't be set".to_string()),
+        (Some("lc" | "he"), _) => Ok(()),
+        (Some("hev2"), _) if options.audio_channel_mode == "mono" || output_channels.map_or(false, |channels| channels != 2) => {
+            Err("HE-AACv2 requires stereo audio".to_string())
+        },
+        (Some("hev2"), _) => Ok(()),
+        (Some(profile), _) => Err(format!("Unknown AAC profile '{}', expected lc, he or hev2", profile)),
+    }
+}
+
+/// Checks `audio_output_format` is a sample format the audio encoder accepts.
+pub fn validate_audio_output_format(options: &RecordingOptions) -> Result<(), String> {
+    let encoder = audio_encoder(options);
+    let supported_formats: &[&str] = match encoder {
+        "libopus" => &["s16", "flt"],
+        "libfdk_aac" => &["s16"],
+        _ => &["fltp"],
+    };
+    match options.audio_output_format.as_deref() {
+        Some(format) if !supported_formats.contains(&format) => {
+            Err(format!("Sample format '{}' is not supported by {}, expected {}", format, encoder, supported_formats.join(" or ")))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Bitrate, profile and sample format args of the audio encoder. HE-AAC gets a lower bitrate, as
+/// that's what it's meant for.
+fn audio_encoder_args(options: &RecordingOptions, output_channels: u16) -> Result<Vec<String>, String> {
+    validate_aac_profile(options, Some(output_channels))?;
+    validate_audio_output_format(options)?;
+
+    let (bitrate, profile) = match audio_encoder(options) {
+        "libopus" => ("128k", None),
+        _ => match options.aac_profile.as_deref() {
+            Some("he") => ("64k", Some("aac_he")),
+            Some("hev2") => ("32k", Some("aac_he_v2")),
+            Some(_) => ("128k", Some("aac_low")),
+            None => ("128k", None),
+        },
+    };
+
+    let mut args = vec!["-b:a".to_string(), bitrate.to_string()];
+    if let Some(profile) = profile {
+        args.extend(["-profile:a".to_string(), profile.to_string()]);
+    }
+    if let Some(format) = &options.audio_output_format {
+        args.extend(["-sample_fmt".to_string(), format.clone()]);
+    }
+    Ok(args)
+}
+
+/// Args of the segment muxer that splits a recording into the 3 second chunks the upload loops pick up.
+/// Segments that share one header have to keep counting on from the previous one instead of
+/// having their timestamps reset.
+fn segment_muxer_args(segment_list_filename: &str, reset_timestamps: bool) -> Vec<String> {
+    let mut args = vec![
+        "-f", "segment",
+        "-segment_time", &SEGMENT_DURATION_SECS.to_string(),
+        "-segment_time_delta", "0.01",
+        "-segment_list", segment_list_filename,
+    ];
+    if reset_timestamps {
+        args.extend(["-reset_timestamps", "1"]);
+    }
+    args.into_iter().map(|s| s.to_string()).collect()
+}
+
+/// The configured keyframe interval, or the segment duration. Only intervals that fit a whole
+/// number of times into a segment are accepted, so segment boundaries always fall on a keyframe.
+pub fn keyframe_interval_secs(options: &RecordingOptions) -> Result<f64, String> {
+    let segment_secs = SEGMENT_DURATION_SECS as f64;
+    let interval = match options.keyframe_interval_secs {
+        Some(interval) if interval > 0.0 && interval <= segment_secs => interval,
+        Some(interval) => return Err(format!("Keyframe interval of {}s is out of range, expected more than 0 and at most {}s", interval, segment_secs)),
+        None => return Ok(segment_secs),
+    };
+
+    let keyframes_per_segment = segment_secs / interval;
+    if (keyframes_per_segment - keyframes_per_segment.round()).abs() > 1e-6 {
+        return Err(format!("Keyframe interval of {}s doesn't divide the {}s segment duration", interval, segment_secs));
+    }
+
+    Ok(interval)
+}
+
+/// The `drawtext` filter for `timestamp_overlay` and `watermark_text`, if either is set.
+pub fn overlay_filter(options: &RecordingOptions) -> Result<Option<String>, String> {
+    let watermark_text = options.watermark_text.as_deref().filter(|text| !text.trim().is_empty());
+    if !options.timestamp_overlay && watermark_text.is_none() {
+        return Ok(None);
+    }
+
+    let margin = OVERLAY_MARGIN;
+    let (x, y) = match options.overlay_position.as_str() {
+        "top-left" => (margin.to_string(), margin.to_string()),
+        "top-right" => (format!("w-tw-{}", margin), margin.to_string()),
+        "bottom-left" => (margin.to_string(), format!("h-th-{}", margin)),
+        "bottom-right" => (format!("w-tw-{}", margin), format!("h-th-{}", margin)),
+        position => return Err(format!("Unknown overlay position '{}', expected top-left, top-right, bottom-left or bottom-right", position)),
+    };
+
+    if options.overlay_font_size == 0 {
+        return Err("Overlay font size must be at least 1".to_string());
+    }
+
+    let font_path = match &options.overlay_font_path {
+        Some(font_path) if Path::new(font_path).is_file() => font_path.clone(),
+        Some(font_path) => return Err(format!("Overlay font {} does not exist", font_path)),
+        None => OVERLAY_FONT_PATHS.iter()
+            .find(|font_path| Path::new(font_path).is_file())
+            .map(|font_path| font_path.to_string())
+            .ok_or("No system font found for the overlay, set overlay_font_path")?,
+    };
+
+    // drawtext expands `%{...}` in the text, so literal backslashes and percent signs are escaped.
+    let mut text: Vec<String> = watermark_text.map(|text| escape_filter_chars(text, "\\%")).into_iter().collect();
+    if options.timestamp_overlay {
+        text.push("%{localtime:%Y-%m-%d %H\\:%M\\:%S}".to_string());
+    }
+
+    Ok(Some(format!(
+        "drawtext=fontfile={}:text={}:fontsize={}:fontcolor=white:box=1:boxcolor=black@0.5:boxborderw=8:x={}:y={}",
+        escape_filter_value(&font_path),
+        escape_filter_value(&text.join("  ")),
+        options.overlay_font_size,
+        x,
+        y,
+    )))
+}
+
+/// Escapes a filter option value, first for the option list and then for the filtergraph.
+fn escape_filter_value(value: &str) -> String {
+    escape_filter_chars(&escape_filter_chars(value, "\\':"), "\\'[],;")
+}
+
+fn escape_filter_chars(value: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(c) {
+            escaped.push('\\');
         }
-        drop(audio_start_locked);
-        drop(video_start_locked);
-        tokio::time::sleep(Duration::from_millis(50)).await;
+        escaped.push(c);
     }
+    escaped
 }
 
-async fn adjust_ffmpeg_commands_based_on_start_times(
-    audio_start_time: Arc<Mutex<Option<Instant>>>,
-    video_start_time: Arc<Mutex<Option<Instant>>>,
-    ffmpeg_audio_command: &mut Vec<String>,
-    ffmpeg_video_command: &mut Vec<String>,
-) {
-    let (audio_start, video_start) = wait_for_start_times(audio_start_time, video_start_time).await;
-    let duration_difference = if audio_start > video_start {
-        audio_start.duration_since(video_start)
-    } else {
-        video_start.duration_since(audio_start)
+/// Caps the GOP at the keyframe interval and forces a keyframe on every interval, so the encoder
+/// neither spaces keyframes further apart nor lets them drift off the segment boundaries.
+fn keyframe_args(options: &RecordingOptions, framerate: u32) -> Result<Vec<String>, String> {
+    let interval = keyframe_interval_secs(options)?;
+    let gop_size = ((framerate as f64 * interval).round() as u32).max(1);
+
+    Ok(vec![
+        "-g".to_string(), gop_size.to_string(),
+        "-force_key_frames".to_string(), format!("expr:gte(t,n_forced*{})", interval),
+    ])
+}
+
+/// Maps the container and `video_segment_format` to the video segment file extension and the
+/// segment muxer args. `mpegts` segments each start with their own header and play on their own,
+/// `fmp4` segments only hold fragments and need `INIT_SEGMENT_FILE` in `chunks_dir` in front of
+/// them, like the media segments of MSE and fMP4 HLS streams.
+fn video_segment_format_args(options: &RecordingOptions, chunks_dir: &str) -> (&'static str, Vec<String>) {
+    let (extension, args) = match (options.container_format.as_str(), options.video_segment_format.as_str()) {
+        ("webm", _) => ("webm", vec!["-segment_format".to_string(), "webm".to_string()]),
+        (_, "fmp4") => ("m4s", vec![
+            "-segment_format".to_string(), "mp4".to_string(),
+            "-segment_header_filename".to_string(), format!("{}/{}", chunks_dir, INIT_SEGMENT_FILE),
+            "-segment_format_options".to_string(), "movflags=+frag_keyframe+empty_moov+default_base_moof".to_string(),
+        ]),
+        _ => ("mp4", vec!["-segment_format".to_string(), "mp4".to_string(), "-movflags".to_string(), "frag_keyframe+empty_moov".to_string()]),
     };
 
-    println!("Duration difference: {:?}", duration_difference);
-    println!("Audio start: {:?}", audio_start);
-    println!("Video start: {:?}", video_start);
+    (extension, args)
+}
 
-    // Convert the duration difference to a float representing seconds
-    let offset_seconds = duration_difference.as_secs() as f64 
-        + duration_difference.subsec_nanos() as f64 * 1e-9;
+/// The filters applied to the audio after the channels are mixed: `loudnorm` when
+/// `audio_normalize` is set, tuned by the `loudnorm_*` options, followed by the resampling that
+/// keeps the audio in sync.
+fn audio_output_filter(options: &RecordingOptions) -> Result<String, String> {
+    if !options.audio_normalize {
+        return Ok(AUDIO_RESAMPLE_FILTER.to_string());
+    }
 
-    // Depending on which started first, adjust the relevant FFmpeg command
-    if audio_start > video_start {
-        // Offset the video start time
-        ffmpeg_video_command.splice(0..0, vec![
-            "-itsoffset".to_string(), format!("{:.3}", offset_seconds)
-        ]);
-        println!("Applying -itsoffset {:.3} to video", offset_seconds);
-    } else if video_start > audio_start {
-        // Offset the audio start time
-        ffmpeg_audio_command.splice(0..0, vec![
-            "-itsoffset".to_string(), format!("{:.3}", offset_seconds)
-        ]);
-        println!("Applying -itsoffset {:.3} to audio", offset_seconds);
+    let mut loudnorm_params = Vec::new();
+    for (name, value, min, max) in [
+        ("I", options.loudnorm_i, -70.0, -5.0),
+        ("TP", options.loudnorm_tp, -9.0, 0.0),
+        ("LRA", options.loudnorm_lra, 1.0, 50.0),
+    ] {
+        if let Some(value) = value {
+            if !(min..=max).contains(&value) {
+                return Err(format!("Invalid loudnorm {} {}, expected a value between {} and {}", name, value, min, max));
+            }
+            loudnorm_params.push(format!("{}={}", name, value));
+        }
     }
 
-}
\ No newline at end of file
+    let loudnorm = if loudnorm_params.is_empty() {
+        "loudnorm".to_string()
+    } else {
+        format!("loudnorm={}", loudnorm_params.join(":"))
+    };
+
+    Ok(format!("{},{}", loudnorm, AUDIO_RESAMPLE_FILTER))
+}
+
+/// Builds the pan filter for the requested channel mode. Mono averages every channel, stereo
+/// averages even-numbered channels into the left and odd-numbered ones into the right, and
+/// passthrough keeps the source channels.
+fn audio_channel_filter(mode: &str, channels: u16) -> Result<Option<String>, String> {
+    let average = |indices: Vec<u16>| {
+        let weight = 1.0 / indices.len() as f64;
+        indices.iter().map(|index| format!("{:.4}*c{}", weight, index)).collect::<Vec<_>>().join("+")
+    };
+
+    match mode {
+        "passthrough" => Ok(None),
+        _ if channels == 0 => Ok(None),
+        "mono" if channels == 1 => Ok(None),
+        "mono" => Ok(Some(format!("pan=mono|c0={}", average((0..channels).collect())))),
+        "stereo" if channels == 1 => Ok(Some("pan=stereo|c0=c0|c1=c0".to_string())),
+        "stereo" if channels == 2 => Ok(None),
+        "stereo" => Ok(Some(format!(
+            "pan=stereo|c0={}|c1={}",
+            average((0..channels).step_by(2).collect()),
+            average((1..channels).step_by(2).collect()),
+        ))),
+        _ => Err(format!("Unknown audio channel mode '{}', expected mono, stereo or passthrough", mode)),
+    }
+}
+
+fn ffmpeg_audio_input_args(options: &RecordingOptions, config: &cpal::SupportedStreamConfig, input: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![
+        "-f".to_string(), ffmpeg_sample_format(config.sample_format())?.to_string(),
+        "-ar".to_string(), config.sample_rate().0.to_string(),
+        "-ac".to_string(), config.channels().to_string(),
+    ];
+    args.extend(pipe_input_args(options));
+    args.extend(["-i".to_string(), input.to_string()]);
+    Ok(args)
+}
+
+/// Queue and probing options for an input piped from the recorder, placed before its `-i`.
+fn pipe_input_args(options: &RecordingOptions) -> Vec<String> {
+    let mut args = vec!["-thread_queue_size".to_string(), options.thread_queue_size.max(1).to_string()];
+    if let Some(probesize) = options.input_probesize {
+        args.extend(["-probesize".to_string(), probesize.max(MIN_INPUT_PROBESIZE).to_string()]);
+    }
+    if let Some(analyzeduration) = options.input_analyzeduration_us {
+        args.extend(["-analyzeduration".to_string(), analyzeduration.to_string()]);
+    }
+    args
+}
+
+fn build_audio_input_stream(
+    source: &AudioSource,
+    sender: mpsc::Sender<Vec<u8>>,
+    start_time: Arc<Mutex<Option<Instant>>>,
+    compensate_latency: bool,
+    drop_counters: Arc<DropCounters>,
+    live_event_app_handle: Option<AppHandle>,
+    disconnected: Arc<AtomicBool>,
+    timing_marks: Option<AudioTimingMarks>,
+    paused: Arc<PauseState>,
+) -> Result<cpal::Stream, String> {
+    let device = source.input_device()?;
+    let config: cpal::StreamConfig = source.config.clone().into();
+
+    let device_name = source.name.clone();
+    let err_fn = move |err| match err {
+        cpal::StreamError::DeviceNotAvailable => {
+            if !disconnected.swap(true, Ordering::SeqCst) {
+                warn!("Audio device '{}' was disconnected", device_name);
+            }
+        },
+        err => error!("an error occurred on stream: {}", err),
+    };
+
+    let stream_result: Result<cpal::Stream, cpal::BuildStreamError> = match source.config.sample_format() {
+        SampleFormat::I8 => device.build_input_stream(
+            &config,
+            audio_data_callback(sender, start_time, compensate_latency, drop_counters, live_event_app_handle, timing_marks, paused, |data: &[i8]| {
+                data.iter().map(|&sample| sample as u8).collect::<Vec<u8>>()
+            }),
+            err_fn,
+            None,
+        ),
+        SampleFormat::I16 => device.build_input_stream(
+            &config,
+            audio_data_callback(sender, start_time, compensate_latency, drop_counters, live_event_app_handle, timing_marks, paused, i16_samples_to_bytes),
+            err_fn,
+            None,
+        ),
+        SampleFormat::I32 => device.build_input_stream(
+            &config,
+            audio_data_callback(sender, start_time, compensate_latency, drop_counters, live_event_app_handle, timing_marks, paused, i32_samples_to_bytes),
+            err_fn,
+            None,
+        ),
+        SampleFormat::F32 => device.build_input_stream(
+            &config,
+            audio_data_callback(sender, start_time, compensate_latency, drop_counters, live_event_app_handle, timing_marks, paused, f32_samples_to_bytes),
+            err_fn,
+            None,
+        ),
+        // Unsigned samples are shifted to signed by flipping the sign bit.
+        SampleFormat::U8 => device.build_input_stream(
+            &config,
+            audio_data_callback(sender, start_time, compensate_latency, drop_counters, live_event_app_handle, timing_marks, paused, u8_samples_to_bytes),
+            err_fn,
+            None,
+        ),
+        SampleFormat::U16 => device.build_input_stream(
+            &config,
+            audio_data_callback(sender, start_time, compensate_latency, drop_counters, live_event_app_handle, timing_marks, paused, u16_samples_to_bytes),
+            err_fn,
+            None,
+        ),
+        SampleFormat::U32 => device.build_input_stream(
+            &config,
+            audio_data_callback(sender, start_time, compensate_latency, drop_counters, live_event_app_handle, timing_marks, paused, u32_samples_to_bytes),
+            err_fn,
+            None,
+        ),
+        SampleFormat::F64 => device.build_input_stream(
+            &config,
+            audio_data_callback(sender, start_time, compensate_latency, drop_counters, live_event_app_handle, timing_marks, paused, f64_samples_to_bytes),
+            err_fn,
+            None,
+        ),
+        unsupported => return Err(format!("Unsupported sample format {:?} on '{}'", unsupported, source.name)),
+    };
+
+    stream_result.map_err(|e| format!("Failed to build input stream: {}", e))
+}
+
+fn i16_samples_to_bytes(data: &[i16]) -> Vec<u8> {
+    let mut bytes = vec![0; data.len() * 2];
+    LittleEndian::write_i16_into(data, &mut bytes);
+    bytes
+}
+
+fn i32_samples_to_bytes(data: &[i32]) -> Vec<u8> {
+    let mut bytes = vec![0; data.len() * 4];
+    LittleEndian::write_i32_into(data, &mut bytes);
+    bytes
+}
+
+fn f32_samples_to_bytes(data: &[f32]) -> Vec<u8> {
+    let mut bytes = vec![0; data.len() * 4];
+    LittleEndian::write_f32_into(data, &mut bytes);
+    bytes
+}
+
+fn u8_samples_to_bytes(data: &[u8]) -> Vec<u8> {
+    data.iter().map(|&sample| sample ^ 0x80).collect::<Vec<u8>>()
+}
+
+fn u16_samples_to_bytes(data: &[u16]) -> Vec<u8> {
+    let samples = data.iter().map(|&sample| (sample ^ 0x8000) as i16).collect::<Vec<i16>>();
+    let mut bytes = vec![0; samples.len() * 2];
+    LittleEndian::write_i16_into(&samples, &mut bytes);
+    bytes
+}
+
+fn u32_samples_to_bytes(data: &[u32]) -> Vec<u8> {
+    let samples = data.iter().map(|&sample| (sample ^ 0x8000_0000) as i32).collect::<Vec<i32>>();
+    let mut bytes = vec![0; samples.len() * 4];
+    LittleEndian::write_i32_into(&samples, &mut bytes);
+    bytes
+}
+
+fn f64_samples_to_bytes(data: &[f64]) -> Vec<u8> {
+    let samples = data.iter().map(|&sample| sample as f32).collect::<Vec<f32>>();
+    let mut bytes = vec![0; samples.len() * 4];
+    LittleEndian::write_f32_into(&samples, &mut bytes);
+    bytes
+}
+
+/// Wraps a sample-to-bytes conversion into a cpal data callback that forwards the bytes down the
+/// channel and records when the first buffer arrived, emitting `capture-live` then when
+/// `live_event_app_handle` is set. With `compensate_latency`, the start is dated back to when the
+/// first buffer was captured, by the input latency the stream reports.
+fn audio_data_callback<T: 'static>(
+    sender: mpsc::Sender<Vec<u8>>,
+    start_time: Arc<Mutex<Option<Instant>>>,
+    compensate_latency: bool,
+    drop_counters: Arc<DropCounters>,
+    live_event_app_handle: Option<AppHandle>,
+    mut timing_marks: Option<AudioTimingMarks>,
+    paused: Arc<PauseState>,
+    to_bytes: fn(&[T]) -> Vec<u8>,
+) -> impl FnMut(&[T], &cpal::InputCallbackInfo) + Send + 'static {
+    move |data: &[T], info: &cpal::InputCallbackInfo| {
+        // ffmpeg times the audio by its sample count, so samples held back leave no gap.
+        if paused.is_paused() {
+            return;
+        }
+
+        if let Some(timing_marks) = timing_marks.as_mut() {
+            timing_marks.advance(data.len(), Instant::now());
+        }
+
+        let mut first_frame_time_guard = start_time.try_lock();
+
+        if sender.try_send(to_bytes(data)).is_err() {
+            drop_counters.audio.fetch_add(1, Ordering::Relaxed);
+            error!("Channel send error. Dropping data.");
+        }
+
+        if let Ok(ref mut start_time_option) = first_frame_time_guard {
+            if start_time_option.is_none() {
+                let latency = if compensate_latency {
+                    let timestamp = info.timestamp();
+                    timestamp.callback.duration_since(&timestamp.capture).unwrap_or_default()
+                } else {
+                    Duration::ZERO
+                };
+                let now = Instant::now();
+                **start_time_option = Some(now.checked_sub(latency).unwrap_or(now));
+
+                debug!("Audio start time captured, {:?} of input latency compensated", latency);
+                if let Some(ref app_handle) = live_event_app_handle {
+                    emit_event(app_handle, "capture-live", CaptureLivePayload { source: "audio".to_string() });
+                }
+            }
+        }
+    }
+}
+
+/// Keeps the audio going when the device behind `source` is disconnected: the gap is filled with
+/// silence, so the audio stays in sync with the video, until a new default input device that
+/// records in the same format shows up, if `fallback` is set. ffmpeg was started for this format,
+/// so a device recording in any other one can't take over.
+fn spawn_audio_disconnect_handler(
+    source: &AudioSource,
+    sender: mpsc::Sender<Vec<u8>>,
+    disconnected: Arc<AtomicBool>,
+    fallback: bool,
+    drop_counters: Arc<DropCounters>,
+    app_handle: AppHandle,
+    should_stop: Arc<AtomicBool>,
+    cancellation_token: CancellationToken,
+    paused: Arc<PauseState>,
+) {
+    let config = source.config.clone();
+    let mut device_name = source.name.clone();
+    let sample_size = match ffmpeg_sample_format(config.sample_format()) {
+        Ok("s8") => 1,
+        Ok("s16le") => 2,
+        _ => 4,
+    };
+    let silence_len = (config.sample_rate().0 as f64 * SILENCE_BUFFER.as_secs_f64()) as usize * config.channels() as usize * sample_size;
+
+    std::thread::spawn(move || {
+        let host = cpal::default_host();
+        let mut disconnected = disconnected;
+        // Owned by this thread, as streams can't be sent between threads on every platform.
+        let mut _fallback_stream: Option<cpal::Stream> = None;
+
+        'recording: while !should_stop.load(Ordering::SeqCst) && !cancellation_token.is_cancelled() {
+            if !disconnected.load(Ordering::SeqCst) {
+                std::thread::sleep(DEVICE_DISCONNECT_CHECK_INTERVAL);
+                continue;
+            }
+
+            _fallback_stream = None;
+            warn!("Recording silence in place of '{}'", device_name);
+            emit_event(&app_handle, "audio-device-disconnected", AudioDeviceDisconnectedPayload {
+                device_name: device_name.clone(),
+                fallback,
+            });
+
+            let silence_started = Instant::now();
+            let mut buffer_count = 0u32;
+            let mut next_fallback_check_at = Instant::now();
+
+            loop {
+                if should_stop.load(Ordering::SeqCst) || cancellation_token.is_cancelled() {
+                    break 'recording;
+                }
+
+                if fallback && Instant::now() >= next_fallback_check_at {
+                    next_fallback_check_at = Instant::now() + DEVICE_FALLBACK_CHECK_INTERVAL;
+                    let fallback_disconnected = Arc::new(AtomicBool::new(false));
+                    match open_fallback_input_device(&host, &config, sender.clone(), Arc::clone(&drop_counters), Arc::clone(&fallback_disconnected), Arc::clone(&paused)) {
+                        Ok((name, stream)) => {
+                            info!("Switched audio from '{}' to '{}'", device_name, name);
+                            emit_event(&app_handle, "audio-device-switched", AudioDeviceSwitchedPayload { device_name: name.clone() });
+                            device_name = name;
+                            disconnected = fallback_disconnected;
+                            _fallback_stream = Some(stream);
+                            continue 'recording;
+                        },
+                        Err(e) => debug!("No fallback audio device yet: {}", e),
+                    }
+                }
+
+                if !paused.is_paused() && sender.try_send(vec![0; silence_len]).is_err() {
+                    drop_counters.audio.fetch_add(1, Ordering::Relaxed);
+                    error!("Channel send error. Dropping data.");
+                }
+
+                buffer_count += 1;
+                let next_buffer_due = silence_started + SILENCE_BUFFER * buffer_count;
+                let now = Instant::now();
+                if next_buffer_due > now {
+                    std::thread::sleep(next_buffer_due - now);
+                }
+            }
+        }
+    });
+}
+
+/// Opens the default input device if it records in `config`, feeding the same channel as the
+/// device it replaces.
+fn open_fallback_input_device(
+    host: &cpal::Host,
+    config: &cpal::SupportedStreamConfig,
+    sender: mpsc::Sender<Vec<u8>>,
+    drop_counters: Arc<DropCounters>,
+    disconnected: Arc<AtomicBool>,
+    paused: Arc<PauseState>,
+) -> Result<(String, cpal::Stream), String> {
+    let device = host.default_input_device().ok_or("No default input device available")?;
+    let source = input_audio_source(device)?;
+
+    if source.config.sample_format() != config.sample_format() || source.config.sample_rate() != config.sample_rate() || source.config.channels() != config.channels() {
+        return Err(format!("'{}' records in a different format", source.name));
+    }
+
+    // The recording has started already, so the start time is set to keep it from being taken again.
+    let stream = build_audio_input_stream(&source, sender, Arc::new(Mutex::new(Some(Instant::now()))), false, drop_counters, None, disconnected, None, paused)?;
+    stream.play().map_err(|e| format!("Failed to play '{}': {}", source.name, e))?;
+    Ok((source.name, stream))
+}
+
+/// Sends a sine wave in the format of `AudioSource::test_tone` to `sender` in real time, standing in
+/// for a microphone in `test_source` recordings.
+fn spawn_test_tone(
+    source: &AudioSource,
+    sender: mpsc::Sender<Vec<u8>>,
+    start_time: Arc<Mutex<Option<Instant>>>,
+    drop_counters: Arc<DropCounters>,
+    app_handle: AppHandle,
+    should_stop: Arc<AtomicBool>,
+    cancellation_token: CancellationToken,
+    mut timing_marks: Option<AudioTimingMarks>,
+    paused: Arc<PauseState>,
+) {
+    let sample_rate = source.config.sample_rate().0;
+    let channels = source.config.channels() as usize;
+    let frames_per_buffer = (sample_rate as f64 * TEST_TONE_BUFFER.as_secs_f64()) as usize;
+
+    std::thread::spawn(move || {
+        let started = Instant::now();
+        let mut frame_index = 0u64;
+        let mut buffer_count = 0u32;
+
+        while !should_stop.load(Ordering::SeqCst) && !cancellation_token.is_cancelled() {
+            let mut samples = Vec::with_capacity(frames_per_buffer * channels);
+            for _ in 0..frames_per_buffer {
+                let t = frame_index as f64 / sample_rate as f64;
+                let sample = ((2.0 * std::f64::consts::PI * TEST_TONE_FREQUENCY * t).sin() * TEST_TONE_AMPLITUDE) as f32;
+                samples.extend(std::iter::repeat(sample).take(channels));
+                frame_index += 1;
+            }
+
+            let paused_now = paused.is_paused();
+            if let Some(timing_marks) = timing_marks.as_mut().filter(|_| !paused_now) {
+                timing_marks.advance(samples.len(), Instant::now());
+            }
+
+            let mut bytes = vec![0; samples.len() * 4];
+            LittleEndian::write_f32_into(&samples, &mut bytes);
+            if !paused_now && sender.try_send(bytes).is_err() {
+                drop_counters.audio.fetch_add(1, Ordering::Relaxed);
+                error!("Channel send error. Dropping data.");
+            }
+
+            if let Ok(mut start_time_option) = start_time.try_lock() {
+                if start_time_option.is_none() {
+                    *start_time_option = Some(Instant::now());
+
+                    debug!("Audio start time captured");
+                    emit_event(&app_handle, "capture-live", CaptureLivePayload { source: "audio".to_string() });
+                }
+            }
+
+            buffer_count += 1;
+            let next_buffer_due = started + TEST_TONE_BUFFER * buffer_count;
+            let now = Instant::now();
+            if next_buffer_due > now {
+                std::thread::sleep(next_buffer_due - now);
+            }
+        }
+    });
+}
+
+/// Holds the input stream of the microphone level monitor shown before recording.
+#[derive(Default)]
+pub struct AudioLevelMonitor {
+    stream: Option<cpal::Stream>,
+}
+
+unsafe impl Send for AudioLevelMonitor {}
+unsafe impl Sync for AudioLevelMonitor {}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AudioLevelPayload {
+    pub rms: f32,
+    pub peak: f32,
+}
+
+/// Opens an input stream on the given device (or the default one) that emits `audio-level` with
+/// the RMS and peak level of every buffer, without recording anything.
+#[tauri::command]
+pub async fn start_audio_level_monitor(
+    app_handle: AppHandle,
+    state: State<'_, Arc<Mutex<AudioLevelMonitor>>>,
+    device_name: Option<String>,
+    device_index: Option<usize>,
+) -> Result<(), String> {
+    let mut monitor = state.lock().await;
+
+    if let Some(stream) = monitor.stream.take() {
+        let _ = stream.pause();
+    }
+
+    let host = cpal::default_host();
+    let source = select_input_device(&host, device_name.as_deref(), device_index)?;
+    let stream = build_level_monitor_stream(&source, app_handle)?;
+    stream.play().map_err(|e| format!("Failed to start audio level monitor: {}", e))?;
+
+    info!("Monitoring audio level of {}", source.name);
+    monitor.stream = Some(stream);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn stop_audio_level_monitor(state: State<'_, Arc<Mutex<AudioLevelMonitor>>>) -> Result<(), String> {
+    if let Some(stream) = state.lock().await.stream.take() {
+        let _ = stream.pause();
+        info!("Audio level monitor stopped.");
+    }
+
+    Ok(())
+}
+
+fn build_level_monitor_stream(source: &AudioSource, app_handle: AppHandle) -> Result<cpal::Stream, String> {
+    let device = source.input_device()?;
+    let config: cpal::StreamConfig = source.config.clone().into();
+
+    let err_fn = move |err| {
+        error!("an error occurred on level monitor stream: {}", err);
+    };
+
+    let stream_result: Result<cpal::Stream, cpal::BuildStreamError> = match source.config.sample_format() {
+        SampleFormat::I8 => device.build_input_stream(&config, level_callback(app_handle, |sample: &i8| *sample as f32 / i8::MAX as f32), err_fn, None),
+        SampleFormat::I16 => device.build_input_stream(&config, level_callback(app_handle, |sample: &i16| *sample as f32 / i16::MAX as f32), err_fn, None),
+        SampleFormat::I32 => device.build_input_stream(&config, level_callback(app_handle, |sample: &i32| *sample as f32 / i32::MAX as f32), err_fn, None),
+        SampleFormat::F32 => device.build_input_stream(&config, level_callback(app_handle, |sample: &f32| *sample), err_fn, None),
+        SampleFormat::U8 => device.build_input_stream(&config, level_callback(app_handle, |sample: &u8| (*sample ^ 0x80) as i8 as f32 / i8::MAX as f32), err_fn, None),
+        SampleFormat::U16 => device.build_input_stream(&config, level_callback(app_handle, |sample: &u16| (*sample ^ 0x8000) as i16 as f32 / i16::MAX as f32), err_fn, None),
+        SampleFormat::U32 => device.build_input_stream(&config, level_callback(app_handle, |sample: &u32| (*sample ^ 0x8000_0000) as i32 as f32 / i32::MAX as f32), err_fn, None),
+        SampleFormat::F64 => device.build_input_stream(&config, level_callback(app_handle, |sample: &f64| *sample as f32), err_fn, None),
+        unsupported => return Err(format!("Unsupported sample format {:?} on '{}'", unsupported, source.name)),
+    };
+
+    stream_result.map_err(|e| format!("Failed to build level monitor stream: {}", e))
+}
+
+/// Wraps a sample normalization into a cpal data callback that emits the buffer's levels in 0.0–1.0.
+fn level_callback<T: 'static>(
+    app_handle: AppHandle,
+    to_f32: fn(&T) -> f32,
+) -> impl FnMut(&[T], &cpal::InputCallbackInfo) + Send + 'static {
+    move |data: &[T], _: &_| {
+        if data.is_empty() {
+            return;
+        }
+
+        let (sum_of_squares, peak) = data.iter()
+            .map(to_f32)
+            .fold((0.0f32, 0.0f32), |(sum, peak), sample| (sum + sample * sample, peak.max(sample.abs())));
+        let rms = (sum_of_squares / data.len() as f32).sqrt();
+
+        emit_event(&app_handle, "audio-level", AudioLevelPayload { rms: rms.min(1.0), peak: peak.min(1.0) });
+    }
+}
+
+type AudioPipeWriter = JoinHandle<std::io::Result<Box<dyn AsyncWrite + Unpin + Send>>>;
+
+/// Creates a named pipe ffmpeg reads raw audio from, returning the path to pass as its input and
+/// a task that resolves to the writing end once ffmpeg has opened the pipe.
+#[cfg(unix)]
+fn open_audio_pipe(audio_file_path: &str, name: &str) -> Result<(String, AudioPipeWriter), String> {
+    let pipe_path = format!("{}/{}.pipe", audio_file_path, name);
+    create_named_pipe(&pipe_path).map_err(|e| format!("Failed to create {} pipe: {}", name, e))?;
+
+    let writer_path = pipe_path.clone();
+    let writer = tokio::spawn(async move {
+        // Opening a FIFO for writing blocks until ffmpeg opens it for reading.
+        let file = tokio::task::spawn_blocking(move || std::fs::OpenOptions::new().write(true).open(writer_path)).await??;
+        Ok(Box::new(tokio::fs::File::from_std(file)) as Box<dyn AsyncWrite + Unpin + Send>)
+    });
+
+    Ok((pipe_path, writer))
+}
+
+#[cfg(windows)]
+fn open_audio_pipe(_audio_file_path: &str, name: &str) -> Result<(String, AudioPipeWriter), String> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let pipe_path = format!(r"\\.\pipe\cap-{}-{}", name.replace('_', "-"), std::process::id());
+    let server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&pipe_path)
+        .map_err(|e| format!("Failed to create {} pipe: {}", name, e))?;
+
+    let writer = tokio::spawn(async move {
+        server.connect().await?;
+        Ok(Box::new(server) as Box<dyn AsyncWrite + Unpin + Send>)
+    });
+
+    Ok((pipe_path, writer))
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct VideoDevice {
+    pub index: usize,
+    pub name: String,
+}
+
+#[tauri::command]
+pub fn enumerate_video_devices() -> Result<Vec<VideoDevice>, String> {
+    #[cfg(target_os = "macos")]
+    {
+        let (_, stderr) = run_command(&ffmpeg_path_as_str()?, vec!["-hide_banner", "-f", "avfoundation", "-list_devices", "true", "-i", ""])?;
+        Ok(parse_avfoundation_video_devices(&stderr))
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let (_, stderr) = run_command(&ffmpeg_path_as_str()?, vec!["-hide_banner", "-f", "dshow", "-list_devices", "true", "-i", "dummy"])?;
+        Ok(parse_dshow_video_devices(&stderr))
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut devices = Vec::new();
+        let entries = std::fs::read_dir("/dev").map_err(|e| format!("Failed to list video devices: {}", e))?;
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(Ok(index)) = file_name.strip_prefix("video").map(str::parse::<usize>) {
+                let name = std::fs::read_to_string(format!("/sys/class/video4linux/{}/name", file_name))
+                    .map(|name| name.trim().to_string())
+                    .unwrap_or(file_name);
+                devices.push(VideoDevice { index, name });
+            }
+        }
+        devices.sort_by_key(|device| device.index);
+        Ok(devices)
+    }
+}
+
+/// Parses the `[index] name` lines listed under "AVFoundation video devices", skipping the
+/// "Capture screen" entries avfoundation reports alongside cameras.
+#[cfg(target_os = "macos")]
+fn parse_avfoundation_video_devices(output: &str) -> Vec<VideoDevice> {
+    let mut devices = Vec::new();
+    let mut in_video_section = false;
+
+    for line in output.lines() {
+        if line.contains("AVFoundation video devices") {
+            in_video_section = true;
+            continue;
+        }
+        if line.contains("AVFoundation audio devices") {
+            break;
+        }
+        if !in_video_section {
+            continue;
+        }
+
+        let Some((_, entry)) = line.split_once("] ") else { continue };
+        let Some((index, name)) = entry.strip_prefix('[').and_then(|entry| entry.split_once("] ")) else { continue };
+
+        if let Ok(index) = index.parse::<usize>() {
+            if !name.starts_with("Capture screen") {
+                devices.push(VideoDevice { index, name: name.to_string() });
+            }
+        }
+    }
+
+    devices
+}
+
+/// Parses the quoted device names dshow marks with `(video)`; dshow addresses cameras by name, so
+/// the index is only the position in this list.
+#[cfg(target_os = "windows")]
+fn parse_dshow_video_devices(output: &str) -> Vec<VideoDevice> {
+    output
+        .lines()
+        .filter(|line| line.trim_end().ends_with("(video)"))
+        .filter_map(|line| line.split('"').nth(1))
+        .enumerate()
+        .map(|(index, name)| VideoDevice { index, name: name.to_string() })
+        .collect()
+}
+
+/// The avfoundation screen device of the display the capturer would have recorded. Its screens
+/// are listed in the same order as `Display::all`, which `screen_index` may not match.
+#[cfg(target_os = "macos")]
+fn avfoundation_screen(options: &RecordingOptions) -> String {
+    let display_index = match Display::all() {
+        Ok(displays) => options.display_id
+            .filter(|id| *id < displays.len())
+            .or_else(|| displays.iter().position(|display| display.is_primary())),
+        Err(e) => {
+            warn!("Failed to enumerate displays ({}), using screen {}", e, options.screen_index);
+            None
+        },
+    };
+
+    match display_index {
+        Some(index) => format!("Capture screen {}", index),
+        None => options.screen_index.clone(),
+    }
+}
+
+/// Input args for ffmpeg's own screen grabber, used when the capturer can't be started (often in
+/// VMs and remote desktop sessions), and a crop filter for grabbers that can't capture a region.
+/// The grabber always records the primary display, and neither follows a window nor highlights
+/// clicks.
+fn native_capture_input_args(options: &RecordingOptions, framerate: u32, region: (usize, usize, usize, usize), display_size: (usize, usize)) -> (Vec<String>, Option<String>) {
+    let (crop_x, crop_y, width, height) = region;
+    let draw_mouse = if options.capture_cursor { "1" } else { "0" };
+
+    if options.display_id.is_some() || options.capture_window_id.is_some() || options.highlight_clicks {
+        warn!("ffmpeg's screen grabber records the primary display without window tracking or click highlights");
+    }
+
+    // avfoundation always grabs the whole display.
+    let crop_filter = if cfg!(target_os = "macos") && region != (0, 0, display_size.0, display_size.1) {
+        Some(format!("crop={}:{}:{}:{}", width, height, crop_x, crop_y))
+    } else {
+        None
+    };
+    let mut args: Vec<String> = Vec::new();
+
+    #[cfg(target_os = "macos")]
+    {
+        args.extend(["-f", "avfoundation", "-capture_cursor", draw_mouse, "-framerate", &framerate.to_string()].into_iter().map(|s| s.to_string()));
+        args.extend(pipe_input_args(options));
+        args.extend(["-i".to_string(), format!("{}:none", avfoundation_screen(options))]);
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        args.extend([
+            "-f", "gdigrab", "-draw_mouse", draw_mouse, "-framerate", &framerate.to_string(),
+            "-offset_x", &crop_x.to_string(), "-offset_y", &crop_y.to_string(),
+            "-video_size", &format!("{}x{}", width, height),
+        ].into_iter().map(|s| s.to_string()));
+        args.extend(pipe_input_args(options));
+        args.extend(["-i".to_string(), "desktop".to_string()]);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let x_display = std::env::var("DISPLAY").unwrap_or_else(|_| ":0.0".to_string());
+        args.extend([
+            "-f", "x11grab", "-draw_mouse", draw_mouse, "-framerate", &framerate.to_string(),
+            "-video_size", &format!("{}x{}", width, height),
+        ].into_iter().map(|s| s.to_string()));
+        args.extend(pipe_input_args(options));
+        args.extend(["-i".to_string(), format!("{}+{},{}", x_display, crop_x, crop_y)]);
+    }
+
+    (args, crop_filter)
+}
+
+fn ffmpeg_webcam_command(webcam_index: &str, webcam_file_path: &str) -> Result<Vec<String>, String> {
+    #[cfg(target_os = "macos")]
+    let input = vec!["-f".to_string(), "avfoundation".to_string(), "-framerate".to_string(), "30".to_string(), "-i".to_string(), format!("{}:none", webcam_index)];
+
+    #[cfg(target_os = "windows")]
+    let input = {
+        let index = webcam_index.parse::<usize>().map_err(|_| format!("Invalid webcam index: {}", webcam_index))?;
+        let device = enumerate_video_devices()?
+            .into_iter()
+            .find(|device| device.index == index)
+            .ok_or(format!("Webcam {} not found", webcam_index))?;
+        vec!["-f".to_string(), "dshow".to_string(), "-i".to_string(), format!("video={}", device.name)]
+    };
+
+    #[cfg(target_os = "linux")]
+    let input = vec!["-f".to_string(), "v4l2".to_string(), "-framerate".to_string(), "30".to_string(), "-i".to_string(), format!("/dev/video{}", webcam_index)];
+
+    let webcam_output_chunk_pattern = format!("{}/webcam_recording_%03d.ts", webcam_file_path);
+    let webcam_segment_list_filename = format!("{}/segment_list.txt", webcam_file_path);
+
+    let mut command = input;
+    command.extend(vec![
+        "-c:v", "libx264",
+        "-preset", "ultrafast",
+        "-pix_fmt", "yuv420p",
+        "-tune", "zerolatency",
+        "-force_key_frames", "expr:gte(t,n_forced*3)",
+        "-f", "segment",
+        "-segment_time", "3",
+        "-segment_time_delta", "0.01",
+        "-segment_list", &webcam_segment_list_filename,
+        "-segment_format", "mpegts",
+        "-reset_timestamps", "1",
+        &webcam_output_chunk_pattern,
+    ].into_iter().map(|s| s.to_string()));
+
+    Ok(command)
+}
+
+const X264_PRESETS: [&str; 10] = [
+    "ultrafast", "superfast", "veryfast", "faster", "fast",
+    "medium", "slow", "slower", "veryslow", "placebo",
+];
+
+/// Builds the video encoder arguments. A requested hardware encoder is used when this ffmpeg build
+/// has it; otherwise libx264 is configured from the preset and CRF. Without a CRF x264 keeps the
+/// zerolatency tuning; with one, the tuning is dropped so rate control applies.
+fn video_encoder_args(options: &RecordingOptions, ffmpeg_binary_path: &str) -> Result<Vec<String>, String> {
+    if !X264_PRESETS.contains(&options.video_preset.as_str()) {
+        return Err(format!("Unknown x264 preset '{}', expected one of: {}", options.video_preset, X264_PRESETS.join(", ")));
+    }
+
+    if let Some(ref hardware_encoder) = options.hardware_encoder {
+        let encoder = match hardware_encoder.as_str() {
+            "videotoolbox" => "h264_videotoolbox",
+            "nvenc" => "h264_nvenc",
+            "qsv" => "h264_qsv",
+            _ => return Err(format!("Unknown hardware encoder '{}', expected videotoolbox, nvenc or qsv", hardware_encoder)),
+        };
+
+        if is_encoder_available(ffmpeg_binary_path, encoder) {
+            info!("Using hardware encoder {}", encoder);
+            let mut args = hardware_encoder_args(encoder, options.video_crf);
+            args.extend(rate_control_args(options)?.unwrap_or_default());
+            return Ok(args);
+        }
+
+        warn!("Hardware encoder {} is not available in this FFmpeg build, falling back to libx264", encoder);
+    }
+
+    let mut args = vec!["-c:v".to_string(), "libx264".to_string(), "-preset".to_string(), options.video_preset.clone()];
+
+    // A target bitrate replaces the zerolatency tuning, whose single-frame VBV would fight it.
+    match (rate_control_args(options)?, options.video_crf) {
+        (Some(rate_control_args), _) => args.extend(rate_control_args),
+        (None, Some(crf)) if crf > 51 => return Err(format!("Invalid CRF {}, expected a value between 0 and 51", crf)),
+        (None, Some(crf)) => args.extend(["-crf".to_string(), crf.to_string()]),
+        (None, None) => args.extend(["-tune".to_string(), "zerolatency".to_string()]),
+    }
+
+    Ok(args)
+}
+
+/// libvpx-vp9 args for WebM recordings, tuned for realtime encoding. `video_preset` only applies to
+/// x264, but the CRF maps onto VP9's 0-63 constant-quality scale.
+fn vp9_encoder_args(options: &RecordingOptions) -> Result<Vec<String>, String> {
+    let quality_args = match (rate_control_args(options)?, options.video_crf) {
+        (Some(rate_control_args), _) => rate_control_args,
+        (None, Some(crf)) if crf > 63 => return Err(format!("Invalid CRF {}, expected a value between 0 and 63 for VP9", crf)),
+        (None, crf) => vec!["-b:v".to_string(), "0".to_string(), "-crf".to_string(), crf.unwrap_or(32).to_string()],
+    };
+
+    let mut args = vec!["-c:v".to_string(), "libvpx-vp9".to_string()];
+    args.extend(quality_args);
+    args.extend([
+        "-deadline", "realtime",
+        "-cpu-used", "8",
+        "-row-mt", "1",
+    ].into_iter().map(|s| s.to_string()));
+
+    Ok(args)
+}
+
+/// `-b:v`, `-maxrate` and `-bufsize` when a target bitrate is set, or `None` to keep encoding at
+/// constant quality. Only one of the two modes can be active, so `video_crf` must be unset.
+fn rate_control_args(options: &RecordingOptions) -> Result<Option<Vec<String>>, String> {
+    let bitrate = match &options.video_bitrate {
+        Some(bitrate) => bitrate,
+        None if options.video_maxrate.is_some() || options.video_bufsize.is_some() => {
+            return Err("video_maxrate and video_bufsize require video_bitrate to be set".to_string());
+        },
+        None => return Ok(None),
+    };
+
+    if options.video_crf.is_some() {
+        return Err("video_crf and video_bitrate select different rate control modes, set only one of them".to_string());
+    }
+
+    let mut args = vec!["-b:v".to_string(), validate_bitrate("video_bitrate", bitrate)?];
+
+    if let Some(maxrate) = &options.video_maxrate {
+        args.extend(["-maxrate".to_string(), validate_bitrate("video_maxrate", maxrate)?]);
+    }
+
+    // The encoders ignore a maxrate without a buffer to enforce it over.
+    if let Some(bufsize) = options.video_bufsize.as_ref().or(options.video_maxrate.as_ref()) {
+        args.extend(["-bufsize".to_string(), validate_bitrate("video_bufsize", bufsize)?]);
+    }
+
+    Ok(Some(args))
+}
+
+fn validate_bitrate(option_name: &str, bitrate: &str) -> Result<String, String> {
+    let value = bitrate.strip_suffix(|c: char| matches!(c, 'k' | 'K' | 'm' | 'M')).unwrap_or(bitrate);
+
+    match value.parse::<f64>() {
+        Ok(value) if value > 0.0 => Ok(bitrate.to_string()),
+        _ => Err(format!("Invalid {} '{}', expected a bitrate such as 4M or 2500k", option_name, bitrate)),
+    }
+}
+
+fn hardware_encoder_args(encoder: &str, crf: Option<u8>) -> Vec<String> {
+    let mut args = vec!["-c:v", encoder];
+
+    match encoder {
+        "h264_videotoolbox" => args.extend(["-realtime", "true", "-allow_sw", "false"]),
+        "h264_nvenc" => args.extend(["-preset", "p1", "-tune", "ll"]),
+        "h264_qsv" => args.extend(["-preset", "veryfast"]),
+        _ => {}
+    }
+
+    let mut args: Vec<String> = args.into_iter().map(|s| s.to_string()).collect();
+
+    // Map the CRF onto each encoder's constant-quality knob where it has one.
+    if let Some(crf) = crf {
+        match encoder {
+            "h264_nvenc" => args.extend(["-cq".to_string(), crf.to_string()]),
+            "h264_qsv" => args.extend(["-global_quality".to_string(), crf.to_string()]),
+            _ => {}
+        }
+    }
+
+    args
+}
+
+/// Checks `ffmpeg -encoders` for the given encoder.
+fn is_encoder_available(ffmpeg_binary_path: &str, encoder: &str) -> bool {
+    match run_command(ffmpeg_binary_path, vec!["-hide_banner", "-encoders"]) {
+        Ok((stdout, _)) => stdout.lines().any(|line| line.split_whitespace().nth(1) == Some(encoder)),
+        Err(e) => {
+            error!("Failed to list FFmpeg encoders: {}", e);
+            false
+        }
+    }
+}
+
+/// How long `benchmark_encoding` encodes for, unless asked otherwise.
+const BENCHMARK_DURATION_SECS: u64 = 5;
+const MAX_BENCHMARK_DURATION_SECS: u64 = 30;
+/// How far below its target framerate, and how many of its frames in dropped ones, a benchmark
+/// may fall and still count as sustainable.
+const BENCHMARK_TOLERANCE: f64 = 0.05;
+
+/// The encoders recording options can use, checked by `ffmpeg_info`.
+const PROBED_ENCODERS: [&str; 8] = ["libx264", "h264_nvenc", "h264_videotoolbox", "h264_qsv", "libvpx-vp9", "libopus", "aac", "libfdk_aac"];
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FfmpegInfo {
+    pub path: String,
+    pub version: String,
+    pub encoders: Vec<String>,
+    pub hwaccels: Vec<String>,
+}
+
+/// Checks the FFmpeg binary recording uses exists and can be executed, returning its path. Fails
+/// with `FfmpegNotFound`, so onboarding can offer to reinstall or locate FFmpeg.
+#[tauri::command]
+pub fn check_ffmpeg() -> Result<String, RecordingError> {
+    locate_ffmpeg().map_err(RecordingError::FfmpegNotFound)
+}
+
+/// Reports the version of the FFmpeg binary recording uses, which of the encoders the recording
+/// options rely on it was built with, and its hardware acceleration methods.
+#[tauri::command]
+pub fn ffmpeg_info() -> Result<FfmpegInfo, String> {
+    let ffmpeg_binary_path = ffmpeg_path_as_str()?;
+    let run_ffmpeg = |arg: &str| {
+        std::process::Command::new(&ffmpeg_binary_path)
+            .args(["-hide_banner", arg])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+            .map_err(|e| format!("Failed to run {} {}: {}", ffmpeg_binary_path, arg, e))
+    };
+
+    // The first line reads "ffmpeg version <version> Copyright ...".
+    let version = run_ffmpeg("-version")?
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(2))
+        .unwrap_or("unknown")
+        .to_string();
+
+    let encoders_output = run_ffmpeg("-encoders")?;
+    let encoders = PROBED_ENCODERS.iter()
+        .filter(|encoder| encoders_output.lines().any(|line| line.split_whitespace().nth(1) == Some(**encoder)))
+        .map(|encoder| encoder.to_string())
+        .collect();
+
+    // Skip the "Hardware acceleration methods:" header.
+    let hwaccels = run_ffmpeg("-hwaccels")?
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    Ok(FfmpegInfo { path: ffmpeg_binary_path.clone(), version, encoders, hwaccels })
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct EncodingBenchmark {
+    pub width: usize,
+    pub height: usize,
+    pub target_fps: u32,
+    pub video_codec: String,
+    pub frames_encoded: u64,
+    pub achieved_fps: f64,
+    /// CPU time ffmpeg spent per frame, as its `-benchmark` flag reports it. Hardware encoders
+    /// leave most of the work to the GPU, so it stays low for them.
+    pub average_encode_ms: Option<f64>,
+    /// Frames dropped because ffmpeg hadn't taken the previous ones yet.
+    pub dropped_frames: u64,
+    /// Whether the encoder kept up with the target framerate within `BENCHMARK_TOLERANCE`.
+    pub sustainable: bool,
+    /// What to change when it didn't, most effective first.
+    pub recommendations: Vec<String>,
+}
+
+/// Encodes a few seconds (5 by default) of the test pattern at the size, framerate and encoder
+/// settings of `options`, paced like a capture and dropping frames the same way, to check this
+/// machine keeps up before a long recording. The frames are discarded, nothing is written.
+#[tauri::command]
+pub async fn benchmark_encoding(options: RecordingOptions, duration_secs: Option<u64>) -> Result<EncodingBenchmark, RecordingError> {
+    validate_container_format(&options).map_err(RecordingError::InvalidOptions)?;
+    let ffmpeg_binary_path = ffmpeg_path_as_str().map_err(RecordingError::FfmpegNotFound)?;
+    let framerate = options.framerate.clamp(MIN_FRAME_RATE, MAX_FRAME_RATE);
+    let duration = Duration::from_secs(duration_secs.unwrap_or(BENCHMARK_DURATION_SECS).clamp(1, MAX_BENCHMARK_DURATION_SECS));
+
+    let (source_width, source_height) = if options.test_source {
+        TEST_SOURCE_SIZE
+    } else {
+        let display = select_display(options.display_id).map_err(|e| RecordingError::DisplayUnavailable(format!("Failed to find display: {}", e)))?;
+        (display.width() & !1, display.height() & !1)
+    };
+    let resolution = parse_resolution(&options.resolution).map_err(RecordingError::InvalidOptions)?;
+    let (width, height) = output_size(resolution, source_width, source_height);
+
+    let video_encoder_args = match options.container_format.as_str() {
+        "webm" => vp9_encoder_args(&options),
+        _ => video_encoder_args(&options, &ffmpeg_binary_path),
+    }.map_err(RecordingError::InvalidOptions)?;
+    let video_codec = codec_arg(&video_encoder_args, "-c:v").unwrap_or_default();
+
+    let mut ffmpeg_args: Vec<String> = vec![
+        "-hide_banner", "-nostats", "-benchmark",
+        "-f", "rawvideo",
+        "-pix_fmt", "bgra",
+        "-s", &format!("{}x{}", source_width, source_height),
+        "-r", &framerate.to_string(),
+        "-i", "pipe:0",
+        "-vf", &format!("scale=w={}:h={}:in_range=full:out_range=limited", width, height),
+    ].into_iter().map(|s| s.to_string()).collect();
+    ffmpeg_args.extend(video_encoder_args);
+    ffmpeg_args.extend(["-pix_fmt", "yuv420p"].into_iter().map(|s| s.to_string()));
+    ffmpeg_args.extend(keyframe_args(&options, framerate).map_err(RecordingError::InvalidOptions)?);
+    ffmpeg_args.extend(["-f", "null", "-"].into_iter().map(|s| s.to_string()));
+
+    info!("Benchmarking {} at {}x{} and {} fps for {:?}", video_codec, width, height, framerate, duration);
+
+    let mut child = Command::new(&ffmpeg_binary_path)
+        .args(&ffmpeg_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| RecordingError::FfmpegNotFound(format!("Failed to start FFmpeg: {}", e)))?;
+    let mut stdin = child.stdin.take().ok_or("Failed to open the FFmpeg input")?;
+
+    let mut pattern = TestPattern::new(source_width, source_height);
+    // The first frame waits for ffmpeg to start up, which a recording does before capture starts too.
+    stdin.write_all(pattern.next_frame()).await.map_err(|e| format!("FFmpeg exited before encoding: {}", e))?;
+    let started = Instant::now();
+
+    let (frame_tx, mut frame_rx) = mpsc::channel::<Vec<u8>>(options.channel_buffer_size.max(1));
+    let writer = tokio::spawn(async move {
+        let mut frames_written = 1u64;
+        while let Some(frame) = frame_rx.recv().await {
+            if stdin.write_all(&frame).await.is_err() {
+                break;
+            }
+            frames_written += 1;
+        }
+        let _ = stdin.shutdown().await;
+        frames_written
+    });
+
+    let spf = Duration::from_nanos(1_000_000_000 / framerate as u64);
+    let frame_total = (duration.as_secs_f64() * framerate as f64) as u32;
+    let mut dropped_frames = 0u64;
+    for frame_index in 1..frame_total {
+        if frame_tx.try_send(pattern.next_frame().to_vec()).is_err() {
+            dropped_frames += 1;
+        }
+        let frame_due = started + spf * frame_index;
+        let now = Instant::now();
+        if frame_due > now {
+            tokio::time::sleep(frame_due - now).await;
+        }
+    }
+    drop(frame_tx);
+
+    let frames_encoded = writer.await.map_err(|e| format!("Benchmark writer failed: {}", e))?;
+    let output = child.wait_with_output().await.map_err(|e| format!("Failed to wait for FFmpeg: {}", e))?;
+    let elapsed = started.elapsed();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        let stderr_lines: Vec<&str> = stderr.lines().collect();
+        return Err(RecordingError::PipelineFailed(format!("FFmpeg failed during the benchmark ({}):\n{}", output.status, stderr_lines[stderr_lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n"))));
+    }
+
+    // The benchmark line reads "bench: utime=1.234s stime=0.123s rtime=5.012s".
+    let average_encode_ms = stderr.split("utime=").nth(1)
+        .and_then(|rest| rest.split('s').next())
+        .and_then(|utime| utime.parse::<f64>().ok())
+        .filter(|_| frames_encoded > 0)
+        .map(|utime| utime * 1000.0 / frames_encoded as f64);
+    // The time until ffmpeg exits includes encoding the frames it still had queued, so a slow
+    // encoder can't pass by buffering.
+    let achieved_fps = frames_encoded.saturating_sub(1) as f64 / elapsed.as_secs_f64().max(spf.as_secs_f64());
+    let sustainable = achieved_fps >= framerate as f64 * (1.0 - BENCHMARK_TOLERANCE)
+        && dropped_frames as f64 <= frame_total as f64 * BENCHMARK_TOLERANCE;
+
+    let mut recommendations = Vec::new();
+    if !sustainable {
+        let load = (achieved_fps / framerate as f64).clamp(0.1, 1.0);
+        let available_hardware_encoder = ["videotoolbox", "nvenc", "qsv"].into_iter()
+            .find(|name| is_encoder_available(&ffmpeg_binary_path, &format!("h264_{}", name)));
+        match available_hardware_encoder {
+            Some(name) if options.hardware_encoder.is_none() && options.container_format != "webm" => {
+                recommendations.push(format!("Use the {} hardware encoder", name));
+            },
+            _ => {},
+        }
+        let preset_index = X264_PRESETS.iter().position(|preset| *preset == options.video_preset);
+        if video_codec == "libx264" && preset_index.map_or(false, |index| index > 0) {
+            recommendations.push(format!("Use a faster x264 preset than '{}', such as '{}'", options.video_preset, X264_PRESETS[preset_index.unwrap_or(1) - 1]));
+        }
+        let suggested_fps = ((achieved_fps * 0.9).floor() as u32).max(MIN_FRAME_RATE);
+        if suggested_fps < framerate {
+            recommendations.push(format!("Lower the framerate to {} fps", suggested_fps));
+        }
+        // Encoding time grows with the pixel count, so each side shrinks by the root of the shortfall.
+        let scale = load.sqrt() * 0.9;
+        let (suggested_width, suggested_height) = (((width as f64 * scale) as usize) & !1, ((height as f64 * scale) as usize) & !1);
+        if suggested_width >= 2 && suggested_height >= 2 {
+            recommendations.push(format!("Lower the resolution to {}x{}", suggested_width, suggested_height));
+        }
+    }
+
+    info!("Benchmark reached {:.1} of {} fps with {} dropped frames, sustainable: {}", achieved_fps, framerate, dropped_frames, sustainable);
+
+    Ok(EncodingBenchmark {
+        width,
+        height,
+        target_fps: framerate,
+        video_codec,
+        frames_encoded,
+        achieved_fps,
+        average_encode_ms,
+        dropped_frames,
+        sustainable,
+        recommendations,
+    })
+}
+
+/// Blends a filled circle of the given BGR color into a BGRA frame, clipped to the frame.
+fn draw_circle(frame_data: &mut [u8], width: usize, height: usize, center_x: i64, center_y: i64, radius: i64, bgr: [u8; 3], opacity: f32) {
+    for y in (center_y - radius).max(0)..(center_y + radius + 1).min(height as i64) {
+        for x in (center_x - radius).max(0)..(center_x + radius + 1).min(width as i64) {
+            let (dx, dy) = (x - center_x, y - center_y);
+            if dx * dx + dy * dy > radius * radius {
+                continue;
+            }
+
+            let offset = (y as usize * width + x as usize) * 4;
+            for (channel, &color) in bgr.iter().enumerate() {
+                let pixel = &mut frame_data[offset + channel];
+                *pixel = (*pixel as f32 * (1.0 - opacity) + color as f32 * opacity) as u8;
+            }
+        }
+    }
+}
+
+/// Fills a BGRA frame with diagonal gray stripes, so hidden protected content reads as masked
+/// rather than as a broken recording.
+fn draw_protected_content_mask(frame_data: &mut [u8], width: usize) {
+    for (index, pixel) in frame_data.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (index % width, index / width);
+        let shade = if (x + y) / PROTECTED_CONTENT_STRIPE_WIDTH % 2 == 0 { 48 } else { 72 };
+        pixel.copy_from_slice(&[shade, shade, shade, 255]);
+    }
+}
+
+/// Where the capture thread gets its frames from.
+enum FrameSource {
+    Display(Capturer),
+    /// Generated frames for `test_source` recordings.
+    TestPattern(TestPattern),
+}
+
+impl FrameSource {
+    fn frame(&mut self) -> std::io::Result<CapturedFrame<'_>> {
+        match self {
+            FrameSource::Display(capturer) => capturer.frame().map(CapturedFrame::Display),
+            FrameSource::TestPattern(pattern) => Ok(CapturedFrame::TestPattern(pattern.next_frame())),
+        }
+    }
+
+    fn draws_cursor(&self) -> bool {
+        match self {
+            FrameSource::Display(capturer) => capturer.draws_cursor(),
+            FrameSource::TestPattern(_) => false,
+        }
+    }
+
+    /// The size of the frames this source delivers.
+    fn size(&self) -> (usize, usize) {
+        match self {
+            FrameSource::Display(capturer) => (capturer.width(), capturer.height()),
+            FrameSource::TestPattern(pattern) => (pattern.width, pattern.height),
+        }
+    }
+}
+
+enum CapturedFrame<'a> {
+    Display(capture::Frame<'a>),
+    TestPattern(&'a [u8]),
+}
+
+impl CapturedFrame<'_> {
+    fn stride_override(&self) -> Option<usize> {
+        match self {
+            CapturedFrame::Display(frame) => frame.stride_override(),
+            CapturedFrame::TestPattern(_) => None,
+        }
+    }
+}
+
+impl std::ops::Deref for CapturedFrame<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            CapturedFrame::Display(frame) => frame,
+            CapturedFrame::TestPattern(frame) => frame,
+        }
+    }
+}
+
+/// BGRA color bars with a white square moving across them, so consecutive frames differ and
+/// dropped or repeated frames show up in the recording.
+struct TestPattern {
+    width: usize,
+    height: usize,
+    bars: Vec<u8>,
+    frame: Vec<u8>,
+    frame_index: usize,
+}
+
+impl TestPattern {
+    const COLORS: [[u8; 4]; 7] = [
+        [192, 192, 192, 255],
+        [0, 192, 192, 255],
+        [192, 192, 0, 255],
+        [0, 192, 0, 255],
+        [192, 0, 192, 255],
+        [0, 0, 192, 255],
+        [192, 0, 0, 255],
+    ];
+
+    fn new(width: usize, height: usize) -> Self {
+        let row: Vec<u8> = (0..width).flat_map(|x| Self::COLORS[x * Self::COLORS.len() / width]).collect();
+        let bars = row.repeat(height);
+        TestPattern { width, height, frame: bars.clone(), bars, frame_index: 0 }
+    }
+
+    fn next_frame(&mut self) -> &[u8] {
+        let size = (self.height / 4).max(1);
+        let x = (self.frame_index * 8) % self.width.saturating_sub(size).max(1);
+        let y = (self.height - size) / 2;
+
+        self.frame.copy_from_slice(&self.bars);
+        for row in y..y + size {
+            let start = (row * self.width + x) * 4;
+            self.frame[start..start + size.min(self.width - x) * 4].fill(255);
+        }
+
+        self.frame_index += 1;
+        &self.frame
+    }
+}
+
+/// Whether every pixel of a BGRA frame is black, as displays produce before anything is rendered.
+fn is_blank_frame(frame_data: &[u8]) -> bool {
+    frame_data.chunks_exact(4).all(|pixel| pixel[..3] == [0, 0, 0])
+}
+
+/// Validates the requested capture region against the display and returns it as
+/// `(x, y, width, height)`, shrunk to fit the display and to even dimensions for yuv420p.
+fn crop_region(options: &RecordingOptions, display_width: usize, display_height: usize) -> Result<Option<(usize, usize, usize, usize)>, String> {
+    let (x, y, width, height) = match (options.crop_x, options.crop_y, options.crop_width, options.crop_height) {
+        (None, None, None, None) => return Ok(None),
+        (Some(x), Some(y), Some(width), Some(height)) => (x, y, width, height),
+        _ => return Err("crop_x, crop_y, crop_width and crop_height must be set together".to_string()),
+    };
+
+    if x >= display_width || y >= display_height {
+        return Err(format!("Capture region origin {},{} lies outside the {}x{} display", x, y, display_width, display_height));
+    }
+
+    let clamped_width = width.min(display_width - x) & !1;
+    let clamped_height = height.min(display_height - y) & !1;
+
+    if clamped_width == 0 || clamped_height == 0 {
+        return Err(format!("Capture region {}x{} is too small", width, height));
+    }
+
+    if clamped_width != width || clamped_height != height {
+        warn!("Capture region {}x{} adjusted to {}x{} to fit the display", width, height, clamped_width, clamped_height);
+    }
+
+    Ok(Some((x, y, clamped_width, clamped_height)))
+}
+
+/// Returns the part of the display covered by the window at `bounds` as `(x, y, width, height)`,
+/// like `crop_region`. The display capturers only grab whole displays, so a window is recorded
+/// as the region it covers, including anything placed on top of it.
+fn window_region(options: &RecordingOptions, window_id: u64, bounds: Option<WindowBounds>, display_scale: f64, display_width: usize, display_height: usize) -> Result<(usize, usize, usize, usize), String> {
+    if options.crop_x.is_some() || options.crop_y.is_some() || options.crop_width.is_some() || options.crop_height.is_some() {
+        return Err("capture_window_id cannot be combined with a capture region".to_string());
+    }
+
+    let bounds = bounds.ok_or_else(|| format!("Window {} was not found on the selected display", window_id))?;
+    let left = ((bounds.x as f64 * display_scale).round() as i64).max(0);
+    let top = ((bounds.y as f64 * display_scale).round() as i64).max(0);
+    let right = (((bounds.x as f64 + bounds.width as f64) * display_scale).round() as i64).min(display_width as i64);
+    let bottom = (((bounds.y as f64 + bounds.height as f64) * display_scale).round() as i64).min(display_height as i64);
+
+    let width = (right - left).max(0) as usize & !1;
+    let height = (bottom - top).max(0) as usize & !1;
+    if width == 0 || height == 0 {
+        return Err(format!("Window {} is not on the selected display", window_id));
+    }
+
+    info!(
+        "Window capture is not supported by the display capturer, recording window {} as the {}x{} region at {},{}",
+        window_id, width, height, left, top,
+    );
+
+    Ok((left as usize, top as usize, width, height))
+}
+
+/// Moves a `width`x`height` capture region to the window's current position, kept within the display.
+fn follow_window(bounds: WindowBounds, display_scale: f64, width: usize, height: usize, display_width: usize, display_height: usize) -> (usize, usize) {
+    let x = (bounds.x as f64 * display_scale).round() as i64;
+    let y = (bounds.y as f64 * display_scale).round() as i64;
+    (
+        x.clamp(0, (display_width - width) as i64) as usize,
+        y.clamp(0, (display_height - height) as i64) as usize,
+    )
+}
+
+/// The size a `width`x`height` frame is scaled to for a `parse_resolution` target, rounding the
+/// dimension left out to an even value like ffmpeg does for `-2`.
+fn output_size(target: Option<(Option<usize>, Option<usize>)>, width: usize, height: usize) -> (usize, usize) {
+    let scaled = |dimension: usize, numerator: usize, denominator: usize| {
+        ((dimension as f64 * numerator as f64 / denominator as f64 / 2.0).round() as usize) * 2
+    };
+
+    match target {
+        Some((Some(target_width), Some(target_height))) => (target_width.min(width), target_height.min(height)),
+        Some((Some(target_width), None)) => {
+            let target_width = target_width.min(width);
+            (target_width, scaled(height, target_width, width))
+        },
+        Some((None, Some(target_height))) => {
+            let target_height = target_height.min(height);
+            (scaled(width, target_height, height), target_height)
+        },
+        _ => (width, height),
+    }
+}
+
+/// The value following `flag` in ffmpeg args, such as the codec after `-c:v`.
+fn codec_arg(args: &[String], flag: &str) -> Option<String> {
+    args.windows(2).find(|pair| pair[0] == flag).map(|pair| pair[1].clone())
+}
+
+/// Parses `RecordingOptions.resolution` into a target width and height, either of which may be left
+/// out so ffmpeg preserves the aspect ratio. Accepts `1280x720`, `1280x`, `x720` and presets such as
+/// `720p` or `4k`. Dimensions are rounded down to even values as yuv420p requires.
+fn parse_resolution(resolution: &str) -> Result<Option<(Option<usize>, Option<usize>)>, String> {
+    let resolution = resolution.trim().to_lowercase();
+    if resolution.is_empty() {
+        return Ok(None);
+    }
+
+    let invalid = || format!("Invalid resolution: {}", resolution);
+    let parse_dimension = |value: &str| -> Result<Option<usize>, String> {
+        if value.is_empty() {
+            return Ok(None);
+        }
+        match value.parse::<usize>() {
+            Ok(dimension) if dimension >= 2 => Ok(Some(dimension & !1)),
+            _ => Err(invalid()),
+        }
+    };
+
+    let (width, height) = match resolution.as_str() {
+        "4k" => (None, Some(2160)),
+        preset if preset.ends_with('p') => (None, parse_dimension(&preset[..preset.len() - 1])?),
+        dimensions => {
+            let (width, height) = dimensions.split_once('x').ok_or_else(invalid)?;
+            (parse_dimension(width)?, parse_dimension(height)?)
+        },
+    };
+
+    if width.is_none() && height.is_none() {
+        return Err(invalid());
+    }
+
+    Ok(Some((width, height)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct DisplayInfo {
+    pub id: usize,
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    pub is_primary: bool,
+}
+
+#[tauri::command]
+pub fn enumerate_displays() -> Result<Vec<DisplayInfo>, String> {
+    let displays = Display::all().map_err(|e| format!("Failed to enumerate displays: {}", e))?;
+
+    Ok(displays
+        .iter()
+        .enumerate()
+        .map(|(id, display)| DisplayInfo {
+            id,
+            name: display.name(),
+            width: display.width(),
+            height: display.height(),
+            is_primary: display.is_primary(),
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct WindowInfo {
+    pub id: u64,
+    pub title: String,
+    pub app: String,
+}
+
+/// Lists the windows that can be recorded with `capture_window_id`.
+#[tauri::command]
+pub fn enumerate_windows() -> Result<Vec<WindowInfo>, String> {
+    let windows = capture::windows().map_err(|e| format!("Failed to enumerate windows: {}", e))?;
+
+    Ok(windows
+        .into_iter()
+        .map(|window| WindowInfo {
+            id: window.id,
+            title: window.title,
+            app: window.app,
+        })
+        .collect())
+}
+
+/// Grabs a single frame of `display_id` and saves it at `output_path`. Without a `display_id`
+/// while recording, the recorded display is grabbed and cropped to the recorded region, so the
+/// screenshot shows what is being recorded. It is saved as a PNG or WebP for those extensions and
+/// as a JPEG otherwise. The screenshot is uploaded when `upload_options` are given, and kept
+/// locally either way. Returns the path it was saved to.
+#[tauri::command]
+pub async fn capture_screenshot(
+    display_id: Option<usize>,
+    output_path: String,
+    upload_options: Option<RecordingOptions>,
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+) -> Result<String, String> {
+    let (max_screen_width, max_screen_height, display_id, region) = {
+        let state_guard = state.lock().await;
+        let recording = state_guard.media_process.as_ref()
+            .filter(|_| display_id.is_none())
+            .and_then(|media_process| media_process.options.as_ref().filter(|options| !options.test_source).map(|options| (options.display_id, &media_process.capture)))
+            .filter(|(_, capture)| capture.width > 0 && capture.height > 0);
+        match recording {
+            Some((recording_display_id, capture)) => (state_guard.max_screen_width, state_guard.max_screen_height, recording_display_id, Some((capture.x, capture.y, capture.width, capture.height))),
+            None => (state_guard.max_screen_width, state_guard.max_screen_height, display_id, None),
+        }
+    };
+
+    let (frame_data, width, height) = tokio::task::spawn_blocking(move || grab_frame(display_id, region, max_screen_width, max_screen_height))
+        .await
+        .map_err(|e| format!("Failed to join screenshot task: {}", e))??;
+
+    let path = Path::new(&output_path);
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+    }
+    save_screenshot(frame_data, width, height, path, &ScreenshotEncoding::for_path(path))?;
+    info!("Screenshot saved to {:?}", path);
+
+    if let Some(upload_options) = upload_options {
+        let uploader = create_uploader(&upload_options)?;
+        uploader.upload(&output_path, "screenshot").await?;
+        info!("Screenshot uploaded from {:?}", path);
+    }
+
+    Ok(output_path)
+}
+
+/// Captures the first rendered frame of a display as tightly packed BGRA, along with its size,
+/// cut to `region` (`(x, y, width, height)`) if given.
+fn grab_frame(display_id: Option<usize>, region: Option<(usize, usize, usize, usize)>, max_screen_width: usize, max_screen_height: usize) -> Result<(Vec<u8>, usize, usize), String> {
+    let display = select_display(display_id).map_err(|e| format!("Failed to find display: {}", e))?;
+    let (width, height) = if display.is_primary() {
+        (max_screen_width, max_screen_height)
+    } else {
+        (display.width(), display.height())
+    };
+    let stride = width * 4;
+
+    let mut capturer = Capturer::with_cursor(display, width, height, true)
+        .map_err(|e| format!("Failed to start capture: {}", e))?;
+    let deadline = Instant::now() + SCREENSHOT_FRAME_TIMEOUT;
+
+    while Instant::now() < deadline {
+        match capturer.frame() {
+            Ok(frame) => {
+                let padded_stride = frame.stride_override().unwrap_or(stride);
+                if padded_stride < stride || frame.len() < height.saturating_sub(1) * padded_stride + stride {
+                    return Err("Captured frame is smaller than the display".to_string());
+                }
+
+                let mut frame_data = Vec::with_capacity(stride * height);
+                for row in 0..height {
+                    let start = row * padded_stride;
+                    frame_data.extend_from_slice(&frame[start..start + stride]);
+                }
+
+                // The first frames can arrive before anything has been rendered into them.
+                if is_blank_frame(&frame_data) {
+                    std::thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+
+                return match region {
+                    Some((x, y, region_width, region_height)) if x + region_width <= width && y + region_height <= height => {
+                        let region_data = (y..y + region_height)
+                            .flat_map(|row| frame_data[(row * width + x) * 4..(row * width + x + region_width) * 4].iter().copied())
+                            .collect();
+                        Ok((region_data, region_width, region_height))
+                    },
+                    _ => Ok((frame_data, width, height)),
+                };
+            },
+            Err(ref e) if e.kind() == WouldBlock => {},
+            Err(e) => return Err(format!("Failed to capture frame: {}", e)),
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    Err(format!("No frame captured within {:?}", SCREENSHOT_FRAME_TIMEOUT))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ScreenshotFormat {
+    Jpeg { quality: u8 },
+    Png { compression: CompressionType },
+    /// Lossless without a quality.
+    WebP { quality: Option<u8> },
+}
+
+/// How a screenshot is encoded, and the size it is scaled down to fit.
+#[derive(Debug, Clone)]
+struct ScreenshotEncoding {
+    format: ScreenshotFormat,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+}
+
+impl ScreenshotEncoding {
+    /// The recording thumbnail's encoding. JPEG and WebP thumbnails default to a low quality, as
+    /// they only need to be small.
+    fn from_options(options: &RecordingOptions) -> Result<Self, String> {
+        let format = match (options.screenshot_format.as_str(), options.screenshot_quality) {
+            ("jpg" | "jpeg" | "webp", Some(quality)) if !(1..=100).contains(&quality) => {
+                return Err(format!("Invalid screenshot quality {}, expected a value between 1 and 100 for JPEG and WebP", quality));
+            },
+            ("jpg" | "jpeg", quality) => ScreenshotFormat::Jpeg { quality: quality.unwrap_or(RECORDING_SCREENSHOT_QUALITY) },
+            ("png", None) => ScreenshotFormat::Png { compression: CompressionType::Default },
+            ("png", Some(0..=2)) => ScreenshotFormat::Png { compression: CompressionType::Fast },
+            ("png", Some(3..=6)) => ScreenshotFormat::Png { compression: CompressionType::Default },
+            ("png", Some(7..=9)) => ScreenshotFormat::Png { compression: CompressionType::Best },
+            ("png", Some(level)) => return Err(format!("Invalid PNG compression level {}, expected a value between 0 and 9", level)),
+            ("webp", quality) => ScreenshotFormat::WebP { quality: Some(quality.unwrap_or(RECORDING_SCREENSHOT_QUALITY)) },
+            (format, _) => return Err(format!("Unknown screenshot format '{}', expected jpg, png or webp", format)),
+        };
+
+        Ok(ScreenshotEncoding { format, max_width: options.screenshot_max_width, max_height: options.screenshot_max_height })
+    }
+
+    /// A full size screenshot in the format `path`'s extension names, falling back to JPEG.
+    fn for_path(path: &Path) -> Self {
+        let format = match path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_lowercase()).as_deref() {
+            Some("png") => ScreenshotFormat::Png { compression: CompressionType::Default },
+            Some("webp") => ScreenshotFormat::WebP { quality: None },
+            _ => ScreenshotFormat::Jpeg { quality: SCREENSHOT_QUALITY },
+        };
+
+        ScreenshotEncoding { format, max_width: None, max_height: None }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.format {
+            ScreenshotFormat::Jpeg { .. } => "jpg",
+            ScreenshotFormat::Png { .. } => "png",
+            ScreenshotFormat::WebP { .. } => "webp",
+        }
+    }
+}
+
+/// Encodes a BGRA frame at `path`, scaled down to fit the encoding's maximum size.
+fn save_screenshot(mut frame_data: Vec<u8>, width: usize, height: usize, path: &Path, encoding: &ScreenshotEncoding) -> Result<(), String> {
+    for chunk in frame_data.chunks_mut(4) {
+        chunk.swap(0, 2);
+    }
+
+    let mut image: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_raw(width as u32, height as u32, frame_data)
+        .ok_or("Frame data does not match the screenshot size".to_string())?;
+
+    let max_width = encoding.max_width.unwrap_or(u32::MAX).max(1);
+    let max_height = encoding.max_height.unwrap_or(u32::MAX).max(1);
+    if image.width() > max_width || image.height() > max_height {
+        image = DynamicImage::ImageRgba8(image).resize(max_width, max_height, FilterType::Triangle).to_rgba8();
+    }
+
+    let output_file = std::fs::File::create(path).map_err(|e| format!("Failed to create {:?}: {}", path, e))?;
+    let mut writer = std::io::BufWriter::new(output_file);
+    let (width, height) = image.dimensions();
+
+    let result = match encoding.format {
+        ScreenshotFormat::Jpeg { quality } => JpegEncoder::new_with_quality(&mut writer, quality).encode_image(&image),
+        ScreenshotFormat::Png { compression } => PngEncoder::new_with_quality(&mut writer, compression, PngFilterType::Adaptive)
+            .write_image(image.as_raw(), width, height, ColorType::Rgba8),
+        ScreenshotFormat::WebP { quality: None } => WebPEncoder::new_lossless(&mut writer).write_image(image.as_raw(), width, height, ColorType::Rgba8),
+        ScreenshotFormat::WebP { quality: Some(quality) } => writer
+            .write_all(&webp::Encoder::from_rgba(image.as_raw(), width, height).encode(quality as f32))
+            .map_err(ImageError::IoError),
+    };
+
+    result.map_err(|e| format!("Failed to save screenshot: {}", e))
+}
+
+/// Uploads a saved screenshot with the recording's uploader once a slot is free, removing the
+/// local file only when the upload succeeds.
+async fn upload_screenshot(uploader: Arc<dyn Uploader>, upload_semaphore: Arc<Semaphore>, path: String) {
+    // The semaphore is never closed, so this only waits for a free slot.
+    let _permit = upload_semaphore.acquire_owned().await;
+    match uploader.upload(&path, "screenshot").await {
+        Ok(()) => {
+            info!("Screenshot uploaded from {}", path);
+            if let Err(e) = std::fs::remove_file(&path) {
+                error!("Failed to remove screenshot after upload: {}", e);
+            }
+        },
+        Err(e) => error!("Failed to upload file: {}", e),
+    }
+}
+
+/// Receives the next buffer for an ffmpeg writer, or `None` once the channel is closed or the
+/// recording has been torn down.
+async fn recv_or_cancelled<T>(receiver: &mut mpsc::Receiver<T>, cancellation_token: &CancellationToken) -> Option<T> {
+    tokio::select! {
+        _ = cancellation_token.cancelled() => None,
+        item = receiver.recv() => item,
+    }
+}
+
+/// Writes a buffer to an ffmpeg input, giving up when the recording is torn down so a process
+/// that stopped reading can't hold the writer forever.
+async fn write_or_cancelled<W: AsyncWrite + Unpin>(writer: &mut W, bytes: &[u8], cancellation_token: &CancellationToken) -> std::io::Result<()> {
+    tokio::select! {
+        _ = cancellation_token.cancelled() => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "recording was torn down")),
+        result = writer.write_all(bytes) => result,
+    }
+}
+
+/// Resolves `display_id` (an index into `Display::all()`) to a display, falling back to the
+/// primary display when it is unset or the selected display has been disconnected.
+fn select_display(display_id: Option<usize>) -> std::io::Result<Display> {
+    if let Some(id) = display_id {
+        match Display::all() {
+            Ok(mut displays) if id < displays.len() => return Ok(displays.swap_remove(id)),
+            Ok(_) => warn!("Display {} is no longer available, falling back to primary display", id),
+            Err(e) => error!("Failed to enumerate displays ({}), falling back to primary display", e),
+        }
+    }
+
+    Display::primary()
+}
+
+use tokio::io::{BufReader, AsyncBufReadExt};
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FfmpegLogPayload {
+    pub process: String,
+    pub line: String,
+}
+
+/// Sent when an ffmpeg input's thread queue fills up, meaning `thread_queue_size` should be raised.
+#[derive(Debug, Serialize, Clone)]
+pub struct FfmpegQueueBlockingPayload {
+    pub process: String,
+    pub message: String,
+}
+
+/// Sent when the recorded microphone is disconnected. Silence is recorded from then on, until
+/// another device takes over if `fallback` is set.
+#[derive(Debug, Serialize, Clone)]
+pub struct AudioDeviceDisconnectedPayload {
+    pub device_name: String,
+    pub fallback: bool,
+}
+
+/// Sent when the default input device took over from a disconnected microphone.
+#[derive(Debug, Serialize, Clone)]
+pub struct AudioDeviceSwitchedPayload {
+    pub device_name: String,
+}
+
+/// Sent when the capturer couldn't be started and ffmpeg grabs the screen itself instead.
+#[derive(Debug, Serialize, Clone)]
+pub struct CaptureFallbackPayload {
+    pub reason: String,
+}
+
+/// Sent once per recording for `audio` and for `video` when the first sample or frame is captured.
+#[derive(Debug, Serialize, Clone)]
+pub struct CaptureLivePayload {
+    pub source: String,
+}
+
+/// Capture performance over the last second: frames captured, frames dropped because the encoder
+/// fell behind, and frame slots filled by repeating the previous frame.
+#[derive(Debug, Serialize, Clone)]
+pub struct CaptureStatsPayload {
+    pub fps: f64,
+    pub target_fps: u32,
+    pub dropped: u64,
+    pub repeated: u32,
+}
+
+/// Emitted when the capture starts or stops looking like protected content. `reason` is
+/// `black_frames`, `capture_error` or, once it's over, `content_visible`.
+#[derive(Debug, Serialize, Clone)]
+pub struct ProtectedContentPayload {
+    pub active: bool,
+    pub reason: String,
+    pub masked: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CaptureStalledPayload {
+    pub stalled_ms: u64,
+    pub recovered: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AvOffsetWarningPayload {
+    pub offset_ms: u64,
+    pub threshold_ms: u64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FfmpegExitedPayload {
+    pub process: String,
+    pub exit_code: Option<i32>,
+    pub stderr_tail: String,
+}
+
+/// An ffmpeg binary that can't be found is reported as such, any other spawn failure as is.
+fn ffmpeg_spawn_error(error: Error) -> RecordingError {
+    match error.kind() {
+        std::io::ErrorKind::NotFound => RecordingError::FfmpegNotFound(format!("Failed to start FFmpeg: {}", error)),
+        _ => RecordingError::Other(error.to_string()),
+    }
+}
+
+/// Waits for an ffmpeg process to exit, killing it if it hasn't within a few seconds.
+async fn wait_for_process_exit(process: &SharedChild, process_name: &str) {
+    let mut process = process.lock().await;
+    match tokio::time::timeout(Duration::from_secs(5), process.wait()).await {
+        Ok(Ok(status)) => info!("FFmpeg {} process exited with {}", process_name, status),
+        Ok(Err(e)) => error!("Failed to wait for FFmpeg {} process: {}", process_name, e),
+        Err(_) => {
+            warn!("FFmpeg {} process did not exit in time, killing it", process_name);
+            let _ = process.kill().await.map_err(|e| e.to_string());
+        },
+    }
+}
+
+/// Records the first failure of the recording pipeline so `stop_media_recording` can return it,
+/// and surfaces it to the frontend straight away.
+async fn report_pipeline_failure(failure: &Arc<Mutex<Option<String>>>, app_handle: &AppHandle, message: String) {
+    error!("{}", message);
+    emit_event(app_handle, "recording-error", RecordingErrorPayload::from(&RecordingError::PipelineFailed(message.clone())));
+
+    let mut failure = failure.lock().await;
+    if failure.is_none() {
+        *failure = Some(message);
+    }
+}
+
+async fn start_recording_process(
+    ffmpeg_binary_path_str: &str, 
+    args: &[String], 
+    process_name: &str,
+    stderr_tail: StderrTail,
+    spawn_retries: u32,
+    app_handle: AppHandle,
+) -> Result<tokio::process::Child, std::io::Error> {
+    // A missing or non-executable binary won't be fixed by trying again, but running out of
+    // processes or file handles can be.
+    let mut attempt = 0;
+    let mut process = loop {
+        match Command::new(ffmpeg_binary_path_str).args(args).stdin(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+            Ok(process) => break process,
+            Err(e) if attempt < spawn_retries && !matches!(e.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied) => {
+                attempt += 1;
+                warn!("Failed to start FFmpeg {} process ({}), retrying ({}/{})", process_name, e, attempt, spawn_retries);
+                tokio::time::sleep(PROCESS_SPAWN_RETRY_DELAY * attempt).await;
+            },
+            Err(e) => return Err(e),
+        }
+    };
+
+    if let Some(process_stderr) = process.stderr.take() {
+      let process_name = process_name.to_string();
+      tokio::spawn(async move {
+            let mut process_reader = BufReader::new(process_stderr).lines();
+            while let Ok(Some(line)) = process_reader.next_line().await {
+                debug!("FFmpeg process STDERR: {}", line);
+
+                let mut tail = stderr_tail.lock().await;
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line.clone());
+                drop(tail);
+
+                if line.contains(THREAD_QUEUE_BLOCKING_WARNING) {
+                    warn!("FFmpeg {} process: {}", process_name, line);
+                    emit_event(&app_handle, "ffmpeg-queue-blocking", FfmpegQueueBlockingPayload {
+                        process: process_name.clone(),
+                        message: line.clone(),
+                    });
+                }
+
+                emit_event(&app_handle, "ffmpeg-log", FfmpegLogPayload {
+                    process: process_name.clone(),
+                    line,
+                });
+            }
+        }.in_current_span());
+    }
+
+    Ok(process)
+}
+
+/// Watches a just started ffmpeg process for `timeout`, so one that fails to initialize (bad
+/// args, a missing codec or an output that can't be opened) fails the start with its error
+/// instead of the recording breaking later on a closed pipe.
+async fn wait_for_process_startup(process: &SharedChild, process_name: &str, stderr_tail: &StderrTail, timeout: Duration) -> Result<(), RecordingError> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let status = process.lock().await.try_wait().map_err(|e| format!("Failed to poll FFmpeg {} process: {}", process_name, e))?;
+        if let Some(status) = status {
+            // Give the stderr reader a moment to catch up with the last lines.
+            tokio::time::sleep(PROCESS_STARTUP_POLL_INTERVAL).await;
+            let stderr_tail = stderr_tail.lock().await.iter().cloned().collect::<Vec<_>>().join("\n");
+            return Err(RecordingError::PipelineFailed(format!("FFmpeg {} process failed to start ({}):\n{}", process_name, status, stderr_tail)));
+        }
+
+        let fatal_line = stderr_tail.lock().await.iter()
+            .find(|line| FFMPEG_FATAL_ERRORS.iter().any(|error| line.contains(error)))
+            .cloned();
+        if let Some(line) = fatal_line {
+            return Err(RecordingError::PipelineFailed(format!("FFmpeg {} process failed to start: {}", process_name, line)));
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(());
+        }
+        tokio::time::sleep(PROCESS_STARTUP_POLL_INTERVAL).await;
+    }
+}
+
+/// Waits for both the audio and the video to deliver their first data, giving up after `timeout`
+/// so a device that never produces data fails the recording instead of hanging it.
+async fn wait_for_start_times(
+    audio_start_time: Arc<Mutex<Option<Instant>>>,
+    video_start_time: Arc<Mutex<Option<Instant>>>,
+    timeout: Duration,
+) -> Result<(Instant, Instant), String> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let audio_start_locked = audio_start_time.lock().await;
+        let video_start_locked = video_start_time.lock().await;
+        
+        if audio_start_locked.is_some() && video_start_locked.is_some() {
+            let audio_start = *audio_start_locked.as_ref().unwrap();
+            let video_start = *video_start_locked.as_ref().unwrap();
+            return Ok((audio_start, video_start));
+        }
+
+        if Instant::now() >= deadline {
+            let missing = match (audio_start_locked.is_some(), video_start_locked.is_some()) {
+                (false, true) => "the audio device produced no data",
+                (true, false) => "the screen capture produced no frames",
+                _ => "neither the audio device nor the screen capture produced data",
+            };
+            return Err(format!("Recording did not start within {:?}: {}", timeout, missing));
+        }
+
+        drop(audio_start_locked);
+        drop(video_start_locked);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+async fn adjust_ffmpeg_commands_based_on_start_times(
+    audio_start_time: Arc<Mutex<Option<Instant>>>,
+    video_start_time: Arc<Mutex<Option<Instant>>>,
+    ffmpeg_audio_command: &mut Vec<String>,
+    ffmpeg_video_command: &mut Vec<String>,
+    start_timeout: Duration,
+    warning_threshold: Duration,
+    app_handle: &AppHandle,
+) -> Result<f64, String> {
+    let (audio_start, video_start) = wait_for_start_times(audio_start_time, video_start_time, start_timeout).await?;
+    let duration_difference = if audio_start > video_start {
+        audio_start.duration_since(video_start)
+    } else {
+        video_start.duration_since(audio_start)
+    };
+
+    debug!("Duration difference: {:?}", duration_difference);
+    debug!("Audio start: {:?}", audio_start);
+    debug!("Video start: {:?}", video_start);
+
+    // The offset hides the gap in the output, but one this large usually means the audio device
+    // was slow to deliver its first buffer, which the user should know about.
+    if duration_difference > warning_threshold {
+        warn!("A/V start offset of {:?} exceeds the {:?} warning threshold", duration_difference, warning_threshold);
+        emit_event(app_handle, "av-offset-warning", AvOffsetWarningPayload {
+            offset_ms: duration_difference.as_millis() as u64,
+            threshold_ms: warning_threshold.as_millis() as u64,
+        });
+    }
+
+    // Convert the duration difference to a float representing seconds
+    let offset_seconds = duration_difference.as_secs() as f64 
+        + duration_difference.subsec_nanos() as f64 * 1e-9;
+
+    // Depending on which started first, adjust the relevant FFmpeg command
+    if audio_start > video_start {
+        // Offset the video start time
+        ffmpeg_video_command.splice(0..0, vec![
+            "-itsoffset".to_string(), format!("{:.3}", offset_seconds)
+        ]);
+        info!("Applying -itsoffset {:.3} to video", offset_seconds);
+        Ok(offset_seconds)
+    } else if video_start > audio_start {
+        // Offset the audio start time
+        ffmpeg_audio_command.splice(0..0, vec![
+            "-itsoffset".to_string(), format!("{:.3}", offset_seconds)
+        ]);
+        info!("Applying -itsoffset {:.3} to audio", offset_seconds);
+        Ok(-offset_seconds)
+    } else {
+        Ok(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::UploadError;
+    use futures::future::BoxFuture;
+    use std::sync::Mutex as StdMutex;
+
+    /// Records every upload and fails them all when `fail` is set.
+    struct MockUploader {
+        uploads: StdMutex<Vec<(String, String)>>,
+        fail: bool,
+    }
+
+    impl MockUploader {
+        fn new(fail: bool) -> Arc<Self> {
+            Arc::new(Self { uploads: StdMutex::new(Vec::new()), fail })
+        }
+    }
+
+    impl Uploader for MockUploader {
+        fn upload<'a>(&'a self, path: &'a str, kind: &'a str) -> BoxFuture<'a, Result<(), UploadError>> {
+            Box::pin(async move {
+                self.uploads.lock().unwrap().push((path.to_string(), kind.to_string()));
+                if self.fail {
+                    Err(UploadError::Failed("mock failure".to_string()))
+                } else {
+                    Ok(())
+                }
+            })
+        }
+    }
+
+    fn temp_screenshot(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("cap-{}-{}.jpg", name, std::process::id()));
+        std::fs::write(&path, b"screenshot").unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn screenshot_is_uploaded_with_the_recording_uploader() {
+        let uploader = MockUploader::new(false);
+        let path = temp_screenshot("uploaded");
+
+        upload_screenshot(uploader.clone(), Arc::new(Semaphore::new(1)), path.clone()).await;
+
+        assert_eq!(*uploader.uploads.lock().unwrap(), vec![(path.clone(), "screenshot".to_string())]);
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn screenshot_is_kept_when_the_upload_fails() {
+        let uploader = MockUploader::new(true);
+        let path = temp_screenshot("failed");
+
+        upload_screenshot(uploader.clone(), Arc::new(Semaphore::new(1)), path.clone()).await;
+
+        assert_eq!(uploader.uploads.lock().unwrap().len(), 1);
+        assert!(Path::new(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn i32_samples_are_written_little_endian() {
+        assert_eq!(
+            i32_samples_to_bytes(&[1, -2, 0x1234_5678]),
+            vec![0x01, 0x00, 0x00, 0x00, 0xFE, 0xFF, 0xFF, 0xFF, 0x78, 0x56, 0x34, 0x12]
+        );
+    }
+
+    #[test]
+    fn test_source_delivers_full_frames_that_change() {
+        let (width, height) = TEST_SOURCE_SIZE;
+        let mut source = FrameSource::TestPattern(TestPattern::new(width, height));
+        assert_eq!(source.size(), (width, height));
+        assert!(!source.draws_cursor());
+
+        let first = source.frame().expect("test pattern frame").to_vec();
+        let second = source.frame().expect("test pattern frame");
+        assert_eq!(first.len(), width * height * 4);
+        assert_eq!(second.len(), first.len());
+        assert!(second.stride_override().is_none());
+        assert_ne!(first, &*second);
+        assert!(!is_blank_frame(&first));
+    }
+}
@@ -1,26 +1,54 @@
 use std::path::{Path, PathBuf};
-use std::collections::HashSet;
-use std::io::{self, BufReader, BufRead, ErrorKind};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufReader, BufRead, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ffi::OsString;
 use std::fs::File;
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
-use tokio::sync:: {Mutex};
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}};
+use tokio::sync:: {Mutex, Notify, Semaphore};
 use tokio::task::JoinHandle;
-use tokio::time::{Duration};
+use tokio::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use chrono::{DateTime, SecondsFormat, Utc};
 use serde::{Serialize, Deserialize};
-use tauri::State;
+use tauri::{AppHandle, Manager, State};
 use futures::future::join_all;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{debug, error, info, warn, Instrument};
 
-use crate::upload::{upload_file};
+use crate::error::RecordingError;
+use crate::upload::{create_uploader, Uploader, UploadTarget};
+use crate::utils::{available_disk_space_mb, ffmpeg_path_as_str, is_session_locked, locate_ffmpeg, ntp_clock_offset_ms};
 
-use crate::media::MediaRecorder;
+use crate::media::{MediaRecorder, PauseState, TimingLog, TIMING_LOG_FILE, INIT_SEGMENT_FILE, ChannelFullPolicy, MIN_INPUT_PROBESIZE, SEGMENT_DURATION_SECS, keyframe_interval_secs, overlay_filter, validate_output_scale, validate_output_sample_rate, validate_aac_profile, validate_audio_output_format, ChannelStats, DropCounters, DropStats, EncodingInfo, CaptureInfo, DEFAULT_FRAME_RATE, enumerate_audio_devices, enumerate_displays, enumerate_windows, validate_extra_ffmpeg_args};
 
 pub struct RecordingState {
+  /// Set from the moment a recording starts (including its countdown) until it has been fully
+  /// stopped or cancelled, so a second one can't be started over it.
+  pub is_recording: bool,
   pub media_process: Option<MediaRecorder>,
   pub recording_options: Option<RecordingOptions>,
   pub shutdown_flag: Arc<AtomicBool>,
-  pub video_uploading_finished: Arc<AtomicBool>,
-  pub audio_uploading_finished: Arc<AtomicBool>,
+  pub upload_cancelled: Arc<AtomicBool>,
+  pub failed_uploads: Arc<Mutex<Vec<String>>>,
+  /// Durations of uploaded segments, keyed by `<video_type>/<filename>`, since the files are gone
+  /// by the time the manifest is written.
+  pub segment_durations: Arc<Mutex<HashMap<String, f64>>>,
+  pub upload_progress: Arc<UploadProgress>,
+  pub upload_handles: Vec<JoinHandle<Result<(), String>>>,
+  /// Where the current (or last) recording uploads to, unset when it doesn't upload.
+  pub uploader: Option<Arc<dyn Uploader>>,
+  /// Errors of the upload loops `get_upload_status` has already joined.
+  pub upload_loop_errors: Vec<String>,
+  /// Cancelled when the current recording is stopped or cancelled, ending every task it spawned.
+  pub cancellation_token: CancellationToken,
+  /// Span of the current (or last) recording. Its tasks and the commands that stop or finalize it
+  /// log under it, so their lines carry its `video_id`.
+  pub span: tracing::Span,
+  pub drop_counters: Arc<DropCounters>,
+  pub av_offset_seconds: f64,
+  pub session: Option<RecordingSession>,
   pub data_dir: Option<PathBuf>,
+  pub output_dir: Option<PathBuf>,
   pub max_screen_width: usize,
   pub max_screen_height: usize,
 }
@@ -30,6 +58,42 @@ unsafe impl Sync for RecordingState {}
 unsafe impl Send for MediaRecorder {}
 unsafe impl Sync for MediaRecorder {}
 
+const UPLOAD_MAX_ATTEMPTS: u32 = 5;
+const UPLOAD_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const UPLOAD_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const DISK_SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const SCREEN_LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Lower bound of `segment_poll_interval_ms`, to keep a misconfigured loop from spinning.
+const MIN_SEGMENT_POLL_INTERVAL_MS: u64 = 10;
+const RECORDING_OPTIONS_FILE: &str = "recording_options.json";
+const REPLAY_RETENTION_INTERVAL: Duration = Duration::from_secs(1);
+/// Segments the replay retention deleted, one per line, next to the `segment_list.txt` listing them.
+const PRUNED_SEGMENT_LIST_FILE: &str = "pruned_segments.txt";
+const MAX_START_DELAY_SECS: u64 = 60;
+const MANIFEST_FILE: &str = "manifest.json";
+/// How far a track's total duration may be off from its segment count before it is flagged.
+const VERIFY_DURATION_TOLERANCE_SECS: f64 = 1.0;
+const PREVIEW_SEGMENT_COUNT: usize = 3;
+const NTP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Marks a recording in progress in its chunks directory, see `SessionMarker`.
+const SESSION_MARKER_FILE: &str = "session.json";
+/// The init segment and `fmp4` segments of a track joined back into one file for muxing, removed
+/// once the mux is done.
+const JOINED_SEGMENTS_FILE: &str = "joined.mp4";
+
+/// One run of `start_dual_recording`, described by the manifest written when it stops.
+#[derive(Debug, Clone)]
+pub struct RecordingSession {
+  pub id: String,
+  pub started_at: DateTime<Utc>,
+  pub encoding: EncodingInfo,
+  /// `ntp` when the offset was measured against `ntp_server`, `system` otherwise.
+  pub clock_source: String,
+  /// Added to the system clock for the manifest's wall-clock timestamps.
+  pub clock_offset_ms: i64,
+  pub video_started_at: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RecordingOptions {
   pub user_id: String,
@@ -37,107 +101,2412 @@ pub struct RecordingOptions {
   pub screen_index: String,
   pub video_index: String,
   pub audio_name: String,
+  #[serde(default)]
+  pub audio_device_index: Option<usize>,
+  /// Extra microphones recorded as separate tracks next to `audio_name`.
+  #[serde(default)]
+  pub audio_track_names: Vec<String>,
   pub aws_region: String,
   pub aws_bucket: String,
+  #[serde(default)]
+  pub display_id: Option<usize>,
+  #[serde(default)]
+  pub resolution: String,
+  /// Size the video is encoded at when no `resolution` is set: `physical`, every captured pixel,
+  /// or `logical`, the display's size in points, which halves a Retina recording in each direction.
+  #[serde(default = "default_output_scale")]
+  pub output_scale: String,
+  #[serde(default = "default_framerate")]
+  pub framerate: u32,
+  #[serde(default = "default_true")]
+  pub upload_enabled: bool,
+  /// Wakes the upload loops as soon as ffmpeg lists a new segment, through file system
+  /// notifications. Turn it off where those aren't delivered, such as on network drives.
+  #[serde(default = "default_true")]
+  pub segment_notifications: bool,
+  /// How often the upload loops check for new segments, and retry failed ones, without a
+  /// notification.
+  #[serde(default = "default_segment_poll_interval_ms")]
+  pub segment_poll_interval_ms: u64,
+  #[serde(default)]
+  pub webcam_index: String,
+  #[serde(default)]
+  pub capture_system_audio: bool,
+  #[serde(default)]
+  pub system_audio_name: Option<String>,
+  #[serde(default = "default_channel_buffer_size")]
+  pub channel_buffer_size: usize,
+  /// `drop` discards video frames while the channel to ffmpeg is full, `block` holds capture back
+  /// until there is room, bounding memory use at the cost of capture timing.
+  #[serde(default = "default_channel_full_policy")]
+  pub channel_full_policy: String,
+  /// Packets ffmpeg queues per input. Raise it when `ffmpeg-queue-blocking` is emitted.
+  #[serde(default = "default_thread_queue_size")]
+  pub thread_queue_size: u32,
+  /// `-probesize` (bytes, at least 32) and `-analyzeduration` (microseconds) of the piped audio
+  /// and video inputs. Their format is given up front, so lowering these shortens ffmpeg's startup.
+  #[serde(default)]
+  pub input_probesize: Option<u64>,
+  #[serde(default)]
+  pub input_analyzeduration_us: Option<u64>,
+  #[serde(default = "default_min_free_disk_mb")]
+  pub min_free_disk_mb: u64,
+  #[serde(default)]
+  pub stop_on_low_disk: bool,
+  /// Stops the recording once it has recorded this long, not counting pauses, emitting
+  /// `max-duration-reached`.
+  #[serde(default)]
+  pub max_duration_secs: Option<u64>,
+  /// Pauses the capture while the screen is locked and resumes it on unlock, with
+  /// `recording-paused` and `recording-resumed` events.
+  #[serde(default)]
+  pub pause_on_screen_lock: bool,
+  #[serde(default = "default_video_preset")]
+  pub video_preset: String,
+  /// Constant quality, used unless `video_bitrate` selects bitrate control instead.
+  #[serde(default)]
+  pub video_crf: Option<u8>,
+  /// Target video bitrate such as `4M` or `2500k`. This switches the encoder from constant quality
+  /// to bitrate control, so it can't be combined with `video_crf`.
+  #[serde(default)]
+  pub video_bitrate: Option<String>,
+  /// Caps the bitrate (constrained VBR, or CBR when equal to `video_bitrate`).
+  #[serde(default)]
+  pub video_maxrate: Option<String>,
+  /// Rate control buffer size, defaulting to `video_maxrate` when only that is set.
+  #[serde(default)]
+  pub video_bufsize: Option<String>,
+  #[serde(default)]
+  pub hardware_encoder: Option<String>,
+  /// Seconds between video keyframes, one per segment by default. It has to divide the segment
+  /// duration so that every segment starts on a keyframe.
+  #[serde(default)]
+  pub keyframe_interval_secs: Option<f64>,
+  #[serde(default)]
+  pub crop_x: Option<usize>,
+  #[serde(default)]
+  pub crop_y: Option<usize>,
+  #[serde(default)]
+  pub crop_width: Option<usize>,
+  #[serde(default)]
+  pub crop_height: Option<usize>,
+  /// Records only this window (an id from `enumerate_windows`), following it as it moves.
+  /// Cannot be combined with the crop options.
+  #[serde(default)]
+  pub capture_window_id: Option<u64>,
+  #[serde(default = "default_true")]
+  pub record_audio: bool,
+  #[serde(default)]
+  pub screenshot_delay_ms: u64,
+  /// Format of the recording thumbnail: `jpg`, `png` or `webp`. The web app picks up any of them,
+  /// but its social preview images can't show WebP.
+  #[serde(default = "default_screenshot_format")]
+  pub screenshot_format: String,
+  /// JPEG or lossy WebP quality (1-100), or PNG compression level (0-9), of the thumbnail.
+  #[serde(default)]
+  pub screenshot_quality: Option<u8>,
+  /// Bounds the thumbnail is scaled down to fit, keeping its aspect ratio.
+  #[serde(default)]
+  pub screenshot_max_width: Option<u32>,
+  #[serde(default)]
+  pub screenshot_max_height: Option<u32>,
+  /// Where segments and the other recording files are uploaded, S3 unless set.
+  #[serde(default)]
+  pub upload_target: UploadTarget,
+  #[serde(default)]
+  pub s3_endpoint: Option<String>,
+  #[serde(default)]
+  pub s3_force_path_style: bool,
+  #[serde(default = "default_audio_channel_mode")]
+  pub audio_channel_mode: String,
+  #[serde(default)]
+  pub replay_window_secs: Option<u64>,
+  #[serde(default)]
+  pub capture_cursor: bool,
+  #[serde(default)]
+  pub highlight_clicks: bool,
+  /// Covers the video with gray stripes while protected content blacks out the capture, see
+  /// the `protected-content` event.
+  #[serde(default)]
+  pub mask_protected_content: bool,
+  #[serde(default)]
+  pub output_dir: Option<PathBuf>,
+  #[serde(default = "default_av_offset_warning_ms")]
+  pub av_offset_warning_ms: u64,
+  /// Dates the microphone's start back by the input latency its stream reports, the time the
+  /// first buffer spent in the device and driver before it was delivered. The A/V offset is the
+  /// gap between the audio and video start times, so this moves the audio earlier against the
+  /// video by that latency. The video's own capture latency isn't accounted for.
+  #[serde(default)]
+  pub compensate_input_latency: bool,
+  /// Writes the capture time of every video frame, and of the audio sample position every
+  /// so often, to `timing_log.txt` in the output directory, to check the A/V sync against.
+  #[serde(default)]
+  pub emit_timing_log: bool,
+  #[serde(default = "default_start_timeout_ms")]
+  pub start_timeout_ms: u64,
+  #[serde(default = "default_audio_segment_format")]
+  pub audio_segment_format: String,
+  /// `mpegts` for video segments that each play on their own, or `fmp4` for smaller fragment-only
+  /// `.m4s` segments that share an `init.mp4` header, as MSE players expect.
+  #[serde(default = "default_video_segment_format")]
+  pub video_segment_format: String,
+  #[serde(default)]
+  pub hls_playlist: bool,
+  #[serde(default = "default_max_concurrent_uploads")]
+  pub max_concurrent_uploads: usize,
+  #[serde(default = "default_container_format")]
+  pub container_format: String,
+  /// Seconds counted down with `recording-countdown` events before capture starts.
+  #[serde(default)]
+  pub start_delay_secs: u64,
+  /// Records audio and video with one ffmpeg process into the video segments, leaving the audio
+  /// segments empty.
+  #[serde(default)]
+  pub single_muxed_output: bool,
+  /// Records through ffmpeg's own screen grabber (avfoundation, gdigrab or x11grab) when the
+  /// capturer fails to start, instead of failing the recording. No thumbnail is taken then.
+  #[serde(default = "default_true")]
+  pub native_capture_fallback: bool,
+  /// Records a generated test pattern and sine tone instead of the display and microphone, so the
+  /// segmenting and uploads can be exercised without either, such as in CI. The display, window
+  /// and audio device options are ignored.
+  #[serde(default)]
+  pub test_source: bool,
+  /// Switches to the default input device when the microphone is disconnected, if it records in
+  /// the same format. Silence is recorded in the meantime, or for the rest of the recording when
+  /// this is off or no such device shows up.
+  #[serde(default = "default_true")]
+  pub audio_device_fallback: bool,
+  /// Extra output args for the audio ffmpeg process (the video one with `single_muxed_output`),
+  /// added right before the segment pattern. An escape hatch for advanced users: args that change
+  /// the output or the segmenting are rejected, but other misuse can still break the recording.
+  #[serde(default)]
+  pub extra_audio_ffmpeg_args: Vec<String>,
+  /// Like `extra_audio_ffmpeg_args`, for the video ffmpeg process.
+  #[serde(default)]
+  pub extra_video_ffmpeg_args: Vec<String>,
+  /// How long the capturer may keep failing frames before it is re-created. A static screen
+  /// delivers no new frames without failing, so it never counts.
+  #[serde(default = "default_capture_stall_threshold_secs")]
+  pub capture_stall_threshold_secs: u64,
+  /// How long the ffmpeg processes are watched after starting, so one that fails to initialize
+  /// fails the start instead of the recording.
+  #[serde(default = "default_ffmpeg_startup_timeout_ms")]
+  pub ffmpeg_startup_timeout_ms: u64,
+  /// How often starting an ffmpeg process is retried when it fails for a reason other than a
+  /// missing or non-executable binary.
+  #[serde(default = "default_ffmpeg_spawn_retries")]
+  pub ffmpeg_spawn_retries: u32,
+  #[serde(default)]
+  pub audio_normalize: bool,
+  /// Sample rate all audio is resampled to, such as 48000, so recordings from different devices
+  /// match. The device's rate is kept when unset.
+  #[serde(default)]
+  pub output_sample_rate: Option<u32>,
+  /// AAC profile of the audio: `lc`, or `he` and `hev2` for HE-AAC at lower bitrates, which need
+  /// an FFmpeg built with libfdk_aac. HE-AACv2 only works on stereo audio. Not used for WebM.
+  #[serde(default)]
+  pub aac_profile: Option<String>,
+  /// Sample format the audio encoder is given, such as `fltp` or `s16`. It has to be one the
+  /// encoder supports: `fltp` for AAC, `s16` for HE-AAC and `s16` or `flt` for Opus.
+  #[serde(default)]
+  pub audio_output_format: Option<String>,
+  #[serde(default)]
+  pub loudnorm_i: Option<f64>,
+  #[serde(default)]
+  pub loudnorm_tp: Option<f64>,
+  #[serde(default)]
+  pub loudnorm_lra: Option<f64>,
+  #[serde(default)]
+  pub keep_local_chunks: bool,
+  /// AES-256 key every uploaded file but the manifest is encrypted with, see `encryption.rs`.
+  /// Encrypted files are uploaded with `.enc` appended to their name, which the web app can't
+  /// play. The key is never saved with the options.
+  #[serde(default, skip_serializing)]
+  pub encryption_key: Option<[u8; 32]>,
+  /// Where the manifest's wall-clock timestamps come from: `system`, the system clock shifted by
+  /// `clock_offset_ms`, or `ntp`, the offset measured against `ntp_server` when the recording
+  /// starts. Recordings made on several machines can be lined up by these timestamps.
+  #[serde(default = "default_clock_source")]
+  pub clock_source: String,
+  #[serde(default = "default_ntp_server")]
+  pub ntp_server: String,
+  #[serde(default)]
+  pub clock_offset_ms: i64,
+  /// Burns the local time into the video, with `watermark_text` in front of it if set.
+  #[serde(default)]
+  pub timestamp_overlay: bool,
+  /// Text burned into the video, such as a label for the recording.
+  #[serde(default)]
+  pub watermark_text: Option<String>,
+  /// Corner of the overlay: `top-left`, `top-right`, `bottom-left` or `bottom-right`.
+  #[serde(default = "default_overlay_position")]
+  pub overlay_position: String,
+  /// Font size of the overlay in output pixels.
+  #[serde(default = "default_overlay_font_size")]
+  pub overlay_font_size: u32,
+  /// Font file for the overlay, instead of a common system font.
+  #[serde(default)]
+  pub overlay_font_path: Option<String>,
+}
+
+fn default_true() -> bool {
+  true
+}
+
+fn default_output_scale() -> String {
+  "physical".to_string()
+}
+
+fn default_overlay_position() -> String {
+  "bottom-right".to_string()
+}
+
+fn default_overlay_font_size() -> u32 {
+  24
+}
+
+fn default_framerate() -> u32 {
+  DEFAULT_FRAME_RATE
+}
+
+fn default_channel_buffer_size() -> usize {
+  2048
+}
+
+fn default_channel_full_policy() -> String {
+  "drop".to_string()
+}
+
+fn default_thread_queue_size() -> u32 {
+  4096
+}
+
+fn default_clock_source() -> String {
+  "system".to_string()
+}
+
+fn default_ntp_server() -> String {
+  "pool.ntp.org".to_string()
+}
+
+fn default_min_free_disk_mb() -> u64 {
+  500
+}
+
+fn default_video_preset() -> String {
+  "ultrafast".to_string()
+}
+
+fn default_audio_channel_mode() -> String {
+  "passthrough".to_string()
+}
+
+fn default_av_offset_warning_ms() -> u64 {
+  200
+}
+
+fn default_start_timeout_ms() -> u64 {
+  5000
+}
+
+fn default_audio_segment_format() -> String {
+  "adts".to_string()
+}
+
+fn default_video_segment_format() -> String {
+  "mpegts".to_string()
+}
+
+fn default_screenshot_format() -> String {
+  "jpg".to_string()
+}
+
+fn default_max_concurrent_uploads() -> usize {
+  3
+}
+
+fn default_container_format() -> String {
+  "mpegts".to_string()
+}
+
+fn default_ffmpeg_startup_timeout_ms() -> u64 {
+  500
+}
+
+fn default_ffmpeg_spawn_retries() -> u32 {
+  2
+}
+
+fn default_segment_poll_interval_ms() -> u64 {
+  50
+}
+
+fn default_capture_stall_threshold_secs() -> u64 {
+  5
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RecordingLifecyclePayload {
+  pub video_id: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RecordingCountdownPayload {
+  pub remaining_secs: u64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RecordingErrorPayload {
+  pub code: String,
+  pub message: String,
+}
+
+impl From<&RecordingError> for RecordingErrorPayload {
+  fn from(error: &RecordingError) -> Self {
+    RecordingErrorPayload { code: error.code().to_string(), message: error.to_string() }
+  }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DiskSpaceLowPayload {
+  pub available_mb: u64,
+  pub threshold_mb: u64,
+  pub auto_stop: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MaxDurationReachedPayload {
+  pub video_id: String,
+  pub max_duration_secs: u64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RecordingPausePayload {
+  pub video_id: String,
+  pub reason: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SegmentUploadedPayload {
+  pub filename: String,
+  pub video_type: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct UploadFailedPayload {
+  pub filename: String,
+  pub video_type: String,
+  pub error: String,
+  pub attempt: u32,
+  pub will_retry: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RecordingOptionProblem {
+  pub field: String,
+  pub message: String,
+}
+
+/// Counts the segments an upload loop has found on disk and how many of them it has uploaded.
+#[derive(Debug, Default)]
+pub struct UploadCounters {
+  pub discovered: AtomicU64,
+  pub uploaded: AtomicU64,
+  /// Segments whose upload has been started but not yet finished.
+  pub in_flight: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+pub struct UploadProgress {
+  pub video: UploadCounters,
+  pub audio: UploadCounters,
+  pub webcam: UploadCounters,
+  pub final_loop_started: AtomicBool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct UploadTypeProgress {
+  pub segments_discovered: u64,
+  pub segments_uploaded: u64,
+  pub percent: f64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct UploadProgressStats {
+  pub video: UploadTypeProgress,
+  pub audio: UploadTypeProgress,
+  pub webcam: UploadTypeProgress,
+  pub total_percent: f64,
+  pub final_loop_started: bool,
+}
+
+impl UploadCounters {
+  fn snapshot(&self) -> UploadTypeProgress {
+    let segments_discovered = self.discovered.load(Ordering::Relaxed);
+    let segments_uploaded = self.uploaded.load(Ordering::Relaxed);
+    UploadTypeProgress {
+      segments_discovered,
+      segments_uploaded,
+      percent: upload_percent(segments_uploaded, segments_discovered),
+    }
+  }
+}
+
+impl UploadProgress {
+  fn counters(&self, video_type: &str) -> &UploadCounters {
+    match video_type {
+      video_type if video_type.starts_with("audio") => &self.audio,
+      "webcam" => &self.webcam,
+      _ => &self.video,
+    }
+  }
+
+  pub fn snapshot(&self) -> UploadProgressStats {
+    let (video, audio, webcam) = (self.video.snapshot(), self.audio.snapshot(), self.webcam.snapshot());
+    let discovered = video.segments_discovered + audio.segments_discovered + webcam.segments_discovered;
+    let uploaded = video.segments_uploaded + audio.segments_uploaded + webcam.segments_uploaded;
+    UploadProgressStats {
+      video,
+      audio,
+      webcam,
+      total_percent: upload_percent(uploaded, discovered),
+      final_loop_started: self.final_loop_started.load(Ordering::Relaxed),
+    }
+  }
+}
+
+fn upload_percent(uploaded: u64, discovered: u64) -> f64 {
+  if discovered == 0 {
+    100.0
+  } else {
+    uploaded as f64 / discovered as f64 * 100.0
+  }
+}
+
+pub fn emit_event<S: Serialize + Clone>(app_handle: &AppHandle, event: &str, payload: S) {
+  if let Err(e) = app_handle.emit_all(event, payload) {
+    error!("Failed to emit {} event: {}", event, e);
+  }
+}
+
+#[tauri::command]
+pub async fn start_dual_recording(
+  app_handle: AppHandle,
+  state: State<'_, Arc<Mutex<RecordingState>>>,
+  options: RecordingOptions,
+) -> Result<(), RecordingError> {
+  let span = tracing::info_span!("recording", video_id = %options.video_id);
+  {
+    let mut state_guard = state.lock().await;
+    if state_guard.is_recording {
+      return Err(RecordingError::AlreadyRecording);
+    }
+    state_guard.is_recording = true;
+    state_guard.span = span.clone();
+  }
+
+  let result = start_recording(app_handle, &state, options).instrument(span).await;
+  if result.is_err() {
+    state.lock().await.is_recording = false;
+  }
+  result
+}
+
+async fn start_recording(
+  app_handle: AppHandle,
+  state: &State<'_, Arc<Mutex<RecordingState>>>,
+  options: RecordingOptions,
+) -> Result<(), RecordingError> {
+  info!("Starting screen recording...");
+  // Checked before anything else, so a missing binary isn't reported as whichever device or
+  // ffmpeg process happened to fail first.
+  let ffmpeg_path = locate_ffmpeg().map_err(RecordingError::FfmpegNotFound)?;
+  info!("Using FFmpeg at {}", ffmpeg_path);
+  let cancellation_token = CancellationToken::new();
+
+  if options.start_delay_secs > 0 {
+    if options.start_delay_secs > MAX_START_DELAY_SECS {
+      return Err(RecordingError::InvalidOptions(format!("Start delay of {}s exceeds the maximum of {}s", options.start_delay_secs, MAX_START_DELAY_SECS)));
+    }
+
+    // The state is only held briefly so that stopping or cancelling during the countdown can get
+    // to the token, which aborts the recording before any device or ffmpeg has been started.
+    state.lock().await.cancellation_token = cancellation_token.clone();
+    run_countdown(&app_handle, options.start_delay_secs, &cancellation_token).await;
+  }
+
+  // The NTP query can take up to its timeout, which other commands shouldn't wait on.
+  let (clock_source, clock_offset_ms) = resolve_clock_offset(&options).await;
+
+  let mut state_guard = state.lock().await;
+
+  // Whatever stopped or cancelled the countdown has already cleared `is_recording`.
+  if cancellation_token.is_cancelled() {
+    info!("Recording aborted during the countdown");
+    return Ok(());
+  }
+  
+  let shutdown_flag = Arc::new(AtomicBool::new(false));
+
+  let data_dir = match state_guard.data_dir.clone() {
+    Some(data_dir) => data_dir,
+    None => {
+      let data_dir = app_handle.path_resolver().app_data_dir()
+          .ok_or("Data directory is not set and the app data directory could not be resolved".to_string())?;
+      info!("No data directory set, using the app data directory {:?}", data_dir);
+      state_guard.data_dir = Some(data_dir.clone());
+      data_dir
+    },
+  };
+
+  let output_dir = resolve_output_dir(&options, &data_dir);
+
+  info!("output_dir: {:?}", output_dir);
+  
+  let audio_chunks_dir = output_dir.join("chunks/audio");
+  let video_chunks_dir = output_dir.join("chunks/video");
+  let webcam_chunks_dir = output_dir.join("chunks/webcam");
+  let screenshot_dir = output_dir.join("screenshots");
+
+  clean_and_create_dir(&audio_chunks_dir)?;
+  clean_and_create_dir(&video_chunks_dir)?;
+  clean_and_create_dir(&webcam_chunks_dir)?;
+  clean_and_create_dir(&screenshot_dir)?;
+  for track_index in 0..options.audio_track_names.len() {
+    clean_and_create_dir(&output_dir.join("chunks").join(audio_track_video_type(track_index)))?;
+  }
+
+  if let Some(available_mb) = available_disk_space_mb(&output_dir) {
+    if available_mb < options.min_free_disk_mb {
+      return Err(RecordingError::InsufficientDiskSpace { available_mb, required_mb: options.min_free_disk_mb });
+    }
+  }
+
+  let is_local_mode = match dotenv_codegen::dotenv!("NEXT_PUBLIC_LOCAL_MODE") {
+      "true" => true,
+      _ => false,
+  };
+
+  // A misconfigured upload target fails the start rather than every upload.
+  let uploader = if !is_local_mode && options.upload_enabled {
+      Some(create_uploader(&options)?)
+  } else {
+      None
+  };
+  // The semaphore is shared so the limit applies to the uploads of all tracks and the thumbnail
+  // together.
+  let upload_semaphore = Arc::new(Semaphore::new(options.max_concurrent_uploads.max(1)));
+  
+  let audio_name = if options.audio_name.is_empty() {
+    None
+  } else {
+    Some(options.audio_name.clone())
+  };
+
+  let timing_log = if options.emit_timing_log {
+    Some(TimingLog::create(&output_dir.join(TIMING_LOG_FILE))?)
+  } else {
+    None
+  };
+
+  let media_recording_preparation = prepare_media_recording(&options, &audio_chunks_dir, &video_chunks_dir, &screenshot_dir, &webcam_chunks_dir, audio_name, state_guard.max_screen_width, state_guard.max_screen_height, timing_log, uploader.clone(), Arc::clone(&upload_semaphore), cancellation_token.clone(), app_handle.clone());
+  let media_recording_result = media_recording_preparation.await.map_err(|e| {
+    // Nothing else may keep running on the token of a recording that never started.
+    cancellation_token.cancel();
+    emit_event(&app_handle, "recording-error", RecordingErrorPayload::from(&e));
+    e
+  })?;
+
+  state_guard.drop_counters = Arc::clone(&media_recording_result.drop_counters);
+  state_guard.av_offset_seconds = media_recording_result.av_offset_seconds;
+  let started_at = Utc::now();
+  state_guard.session = Some(RecordingSession {
+    id: format!("{}-{}", options.video_id, started_at.timestamp_millis()),
+    started_at,
+    encoding: media_recording_result.encoding.clone(),
+    clock_source,
+    clock_offset_ms,
+    video_started_at: Arc::clone(&media_recording_result.video_started_at),
+  });
+  let pause_state = Arc::clone(&media_recording_result.paused);
+  let native_capture = media_recording_result.capture.native_capture;
+  state_guard.media_process = Some(media_recording_result);
+  state_guard.recording_options = Some(options.clone());
+  state_guard.output_dir = Some(output_dir.clone());
+  if let Err(e) = write_session_marker(&output_dir, &SessionMarker {
+    options: options.clone(),
+    started_at,
+    av_offset_seconds: state_guard.av_offset_seconds,
+    encrypted: options.encryption_key.is_some(),
+  }) {
+    warn!("{}, the recording can't be recovered after a crash", e);
+  }
+  state_guard.shutdown_flag = shutdown_flag.clone();
+  state_guard.cancellation_token = cancellation_token.clone();
+  state_guard.upload_cancelled = Arc::new(AtomicBool::new(false));
+  state_guard.failed_uploads = Arc::new(Mutex::new(Vec::new()));
+  state_guard.upload_loop_errors = Vec::new();
+  state_guard.uploader = uploader.clone();
+  state_guard.segment_durations = Arc::new(Mutex::new(HashMap::new()));
+  state_guard.upload_progress = Arc::new(UploadProgress::default());
+
+  emit_event(&app_handle, "recording-started", RecordingLifecyclePayload { video_id: options.video_id.clone() });
+
+  spawn_disk_space_monitor(app_handle.clone(), output_dir.clone(), options.clone(), shutdown_flag.clone(), cancellation_token.clone());
+
+  // ffmpeg's own screen grabber keeps going while the audio would be held back.
+  if options.pause_on_screen_lock && native_capture {
+      warn!("Ignoring pause_on_screen_lock, the screen is grabbed by ffmpeg which can't be paused");
+  } else if options.pause_on_screen_lock {
+      spawn_screen_lock_monitor(app_handle.clone(), options.video_id.clone(), Arc::clone(&pause_state), shutdown_flag.clone(), cancellation_token.clone());
+  }
+
+  if let Some(max_duration_secs) = options.max_duration_secs.filter(|secs| *secs > 0) {
+      spawn_max_duration_timer(app_handle.clone(), options.video_id.clone(), max_duration_secs, pause_state, shutdown_flag.clone(), cancellation_token.clone());
+  }
+
+  if let Some(replay_window_secs) = options.replay_window_secs {
+      if !is_local_mode && options.upload_enabled {
+          warn!("Ignoring the replay window because uploading is enabled");
+      } else {
+          spawn_replay_retention(output_dir.join("chunks"), replay_window_secs, shutdown_flag.clone(), cancellation_token.clone());
+      }
+  }
+
+  if let Some(uploader) = uploader {
+      info!("Starting upload loops...");
+
+      // The loops run until the recording is stopped, so they are left in the background and
+      // awaited by `stop_all_recordings` instead of holding up this command.
+      let span = state_guard.span.clone();
+      let mut upload_handles = vec![
+        tokio::spawn(start_upload_loop(video_chunks_dir.clone(), options.clone(), uploader.clone(), "video".to_string(), shutdown_flag.clone(), state_guard.upload_cancelled.clone(), cancellation_token.clone(), state_guard.failed_uploads.clone(), state_guard.segment_durations.clone(), state_guard.upload_progress.clone(), upload_semaphore.clone(), app_handle.clone()).instrument(span.clone())),
+        tokio::spawn(start_upload_loop(audio_chunks_dir, options.clone(), uploader.clone(), "audio".to_string(), shutdown_flag.clone(), state_guard.upload_cancelled.clone(), cancellation_token.clone(), state_guard.failed_uploads.clone(), state_guard.segment_durations.clone(), state_guard.upload_progress.clone(), upload_semaphore.clone(), app_handle.clone()).instrument(span.clone())),
+      ];
+
+      if !options.webcam_index.is_empty() {
+        upload_handles.push(tokio::spawn(start_upload_loop(webcam_chunks_dir, options.clone(), uploader.clone(), "webcam".to_string(), shutdown_flag.clone(), state_guard.upload_cancelled.clone(), cancellation_token.clone(), state_guard.failed_uploads.clone(), state_guard.segment_durations.clone(), state_guard.upload_progress.clone(), upload_semaphore.clone(), app_handle.clone()).instrument(span.clone())));
+      }
+
+      for track_index in 0..options.audio_track_names.len() {
+        let video_type = audio_track_video_type(track_index);
+        upload_handles.push(tokio::spawn(start_upload_loop(output_dir.join("chunks").join(&video_type), options.clone(), uploader.clone(), video_type, shutdown_flag.clone(), state_guard.upload_cancelled.clone(), cancellation_token.clone(), state_guard.failed_uploads.clone(), state_guard.segment_durations.clone(), state_guard.upload_progress.clone(), upload_semaphore.clone(), app_handle.clone()).instrument(span.clone())));
+      }
+
+      state_guard.upload_handles = upload_handles;
+  } else if is_local_mode {
+      info!("Skipping upload loops due to NEXT_PUBLIC_LOCAL_MODE being set to 'true'.");
+  } else {
+      info!("Skipping upload loops because uploading is disabled. Chunks are kept in {:?}", output_dir.join("chunks"));
+  }
+
+  Ok(())
+}
+
+/// What stopping a recording got done. Every step is attempted even when an earlier one failed,
+/// so a failed ffmpeg shutdown still has its segments uploaded and its manifest written.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct StopSummary {
+  /// Whether a recording was running to be stopped.
+  pub was_recording: bool,
+  /// The capture and every ffmpeg process stopped cleanly.
+  pub media_stopped: bool,
+  /// Every upload loop finished without giving up on a segment, or there was nothing to upload.
+  pub uploads_completed: bool,
+  pub failed_uploads: Vec<String>,
+  pub manifest_written: bool,
+  pub manifest_uploaded: bool,
+  /// What went wrong along the way, in order.
+  pub errors: Vec<RecordingErrorPayload>,
+}
+
+impl StopSummary {
+  fn fail(&mut self, error: RecordingError) {
+    error!("{}", error);
+    self.errors.push(RecordingErrorPayload::from(&error));
+  }
+}
+
+/// Stops the recording and waits for its uploads. Only a recording that produced no segments at
+/// all fails, anything else is reported in the summary.
+#[tauri::command]
+pub async fn stop_all_recordings(app_handle: AppHandle, state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<StopSummary, RecordingError> {
+    let span = state.lock().await.span.clone();
+    async move {
+        if !state.lock().await.is_recording {
+            info!("No recording in progress, nothing to stop");
+            return Ok(StopSummary::default());
+        }
+
+        // The flag is only cleared once the uploads are done, as a new recording would replace the
+        // upload state they report into.
+        let result = stop_recording(app_handle, &state).await;
+        let mut guard = state.lock().await;
+        if let Ok(output_dir) = current_output_dir(&guard) {
+            remove_session_marker(&output_dir);
+        }
+        guard.is_recording = false;
+        result
+    }.instrument(span).await
+}
+
+async fn stop_recording(app_handle: AppHandle, state: &State<'_, Arc<Mutex<RecordingState>>>) -> Result<StopSummary, RecordingError> {
+    let mut guard = state.lock().await;
+    let mut summary = StopSummary { uploads_completed: true, ..Default::default() };
+
+    // Whichever way this returns, nothing of the recording should outlive it. The upload loops are
+    // awaited below before the token is cancelled, so their final pass still runs.
+    let _cancel_on_return = guard.cancellation_token.clone().drop_guard();
+    
+    info!("Stopping media recording...");
+    
+    summary.was_recording = guard.media_process.is_some();
+    let stop_result = match guard.media_process.take() {
+        Some(mut media_process) => {
+            info!("Stopping media recording...");
+            media_process.stop_media_recording().await
+        },
+        None => Ok(()),
+    };
+
+    // Only flag the upload loops once ffmpeg has exited, so their final pass sees the last segment.
+    guard.shutdown_flag.store(true, Ordering::SeqCst);
+    match stop_result {
+        Ok(()) => summary.media_stopped = true,
+        Err(e) => summary.fail(RecordingError::PipelineFailed(e)),
+    }
+
+    let output_dir = current_output_dir(&guard);
+    if let Err(e) = &output_dir {
+        summary.fail(RecordingError::Other(e.clone()));
+    }
+
+    // ffmpeg lists every segment it finished, including the last partial one, so an empty list
+    // means it never received any data and there is nothing to wait for or upload.
+    let no_segments = output_dir.as_ref().map_or(false, |output_dir| load_ordered_segment_list(&output_dir.join("chunks/video")).is_empty());
+    if summary.was_recording && no_segments {
+        for upload_handle in std::mem::take(&mut guard.upload_handles) {
+            upload_handle.abort();
+        }
+        guard.session = None;
+
+        let error = no_segments_error();
+        warn!("{}", error);
+        emit_event(&app_handle, "recording-error", RecordingErrorPayload::from(&error));
+        return Err(error);
+    }
+
+    let is_local_mode = match dotenv_codegen::dotenv!("NEXT_PUBLIC_LOCAL_MODE") {
+        "true" => true,
+        _ => false,
+    };
+
+    let upload_enabled = guard.recording_options.as_ref().map_or(true, |options| options.upload_enabled);
+
+    if !is_local_mode && upload_enabled {
+        let upload_handles = std::mem::take(&mut guard.upload_handles);
+        let upload_progress = guard.upload_progress.clone();
+
+        // Release the state while waiting so the upload progress can be queried in the meantime.
+        drop(guard);
+
+        info!("Waiting for uploads to finish... {:.0}% uploaded", upload_progress.snapshot().total_percent);
+
+        for upload_handle in upload_handles {
+            let error = match upload_handle.await {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => format!("Upload loop failed: {}", e),
+                Err(e) => format!("Upload loop panicked: {}", e),
+            };
+            summary.uploads_completed = false;
+            summary.fail(RecordingError::UploadFailed(error));
+        }
+
+        guard = state.lock().await;
+
+        // Loops that get_upload_status joined already have their errors kept there.
+        for error in std::mem::take(&mut guard.upload_loop_errors) {
+            summary.uploads_completed = false;
+            summary.fail(RecordingError::UploadFailed(error));
+        }
+
+        let failed_uploads = guard.failed_uploads.lock().await.clone();
+        if !failed_uploads.is_empty() {
+            error!("{} segment upload(s) failed after retries: {:?}", failed_uploads.len(), failed_uploads);
+            summary.uploads_completed = false;
+            summary.fail(RecordingError::UploadFailed(format!("{} segment upload(s) failed after {} attempts", failed_uploads.len(), UPLOAD_MAX_ATTEMPTS)));
+            summary.failed_uploads = failed_uploads;
+        }
+    }
+
+    let hls_output_dir = output_dir.as_ref().ok().filter(|_| guard.recording_options.as_ref().map_or(false, |options| options.hls_playlist));
+    if let (Some(options), Some(output_dir)) = (guard.recording_options.as_ref(), hls_output_dir) {
+        let tracks = ["video", "audio", "webcam"].into_iter().map(String::from)
+            .chain((0..options.audio_track_names.len()).map(audio_track_video_type));
+        for track in tracks {
+            if let Err(e) = write_hls_playlist(&output_dir.join("chunks").join(&track)).await {
+                summary.fail(RecordingError::Other(format!("Failed to write the {} HLS playlist: {}", track, e)));
+            }
+        }
+    }
+    
+    if let Some(session) = guard.session.take() {
+        match (write_manifest(&guard, &session).await, guard.uploader.clone()) {
+            (Ok(manifest_path), Some(uploader)) if !is_local_mode && upload_enabled => {
+                summary.manifest_written = true;
+                match uploader.upload(&manifest_path.to_string_lossy(), "manifest").await {
+                    Ok(()) => summary.manifest_uploaded = true,
+                    Err(e) => summary.fail(RecordingError::UploadFailed(format!("Failed to upload the recording manifest: {}", e))),
+                }
+            },
+            (Ok(_), _) => summary.manifest_written = true,
+            (Err(e), _) => summary.fail(RecordingError::Other(format!("Failed to write the recording manifest: {}", e))),
+        }
+    }
+
+    match summary.errors.len() {
+        0 => info!("All recordings and uploads stopped."),
+        failures => warn!("Recording stopped with {} failure(s)", failures),
+    }
+
+    let video_id = guard.recording_options.as_ref().map(|options| options.video_id.clone()).unwrap_or_default();
+    emit_event(&app_handle, "recording-stopped", RecordingLifecyclePayload { video_id });
+
+    Ok(summary)
+}
+
+#[derive(Debug, Serialize)]
+struct RecordingManifest {
+    session_id: String,
+    video_id: String,
+    started_at: String,
+    stopped_at: String,
+    #[serde(flatten)]
+    encoding: EncodingInfo,
+    container_format: String,
+    encrypted: bool,
+    clock_source: String,
+    clock_offset_ms: i64,
+    /// Corrected wall-clock time of the first video frame.
+    wall_clock_start: Option<String>,
+    segment_duration_secs: u64,
+    av_offset_seconds: f64,
+    tracks: BTreeMap<String, Vec<ManifestSegment>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestSegment {
+    filename: String,
+    /// Unknown when the segment was neither probed before its upload nor kept locally.
+    duration_secs: Option<f64>,
+    /// Corrected wall-clock time the segment starts at, counted from `wall_clock_start` through the
+    /// durations of the segments before it. Only given for video segments.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wall_clock_start: Option<String>,
+}
+
+/// Writes `manifest.json` into the recording's output directory, listing every track's segments
+/// in recording order so the recording can be assembled without guessing. Returns its path.
+async fn write_manifest(state: &RecordingState, session: &RecordingSession) -> Result<PathBuf, String> {
+    let options = state.recording_options.as_ref().ok_or("No recording options to write the manifest from".to_string())?;
+    let output_dir = current_output_dir(state)?;
+    let ffmpeg_path = ffmpeg_path_as_str()?;
+    let segment_durations = state.segment_durations.lock().await.clone();
+    let wall_clock_start = session.video_started_at.lock().await
+        .map(|video_started_at| video_started_at + chrono::Duration::milliseconds(session.clock_offset_ms));
+
+    let track_names = ["video", "audio", "webcam"].into_iter().map(String::from)
+        .chain((0..options.audio_track_names.len()).map(audio_track_video_type));
+    let mut tracks = BTreeMap::new();
+    for track in track_names {
+        let chunks_dir = output_dir.join("chunks").join(&track);
+        let mut segments = Vec::new();
+        let mut segment_start = wall_clock_start.filter(|_| track == "video");
+        for filename in load_ordered_segment_list(&chunks_dir) {
+            let duration_secs = match segment_durations.get(&format!("{}/{}", track, filename)) {
+                Some(duration) => Some(*duration),
+                None => segment_duration_secs(&ffmpeg_path, &chunks_dir.join(&filename)).await,
+            };
+            let wall_clock_start = segment_start.map(|segment_start| segment_start.to_rfc3339_opts(SecondsFormat::Millis, true));
+            // Without this segment's duration, the start of every later one is unknown too.
+            segment_start = segment_start.zip(duration_secs)
+                .map(|(segment_start, duration_secs)| segment_start + chrono::Duration::milliseconds((duration_secs * 1000.0).round() as i64));
+            segments.push(ManifestSegment { filename, duration_secs, wall_clock_start });
+        }
+        if !segments.is_empty() {
+            tracks.insert(track, segments);
+        }
+    }
+
+    let manifest = RecordingManifest {
+        session_id: session.id.clone(),
+        video_id: options.video_id.clone(),
+        started_at: session.started_at.to_rfc3339(),
+        stopped_at: Utc::now().to_rfc3339(),
+        encoding: session.encoding.clone(),
+        container_format: options.container_format.clone(),
+        encrypted: options.encryption_key.is_some(),
+        clock_source: session.clock_source.clone(),
+        clock_offset_ms: session.clock_offset_ms,
+        wall_clock_start: wall_clock_start.map(|wall_clock_start| wall_clock_start.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        segment_duration_secs: SEGMENT_DURATION_SECS,
+        av_offset_seconds: state.av_offset_seconds,
+        tracks,
+    };
+
+    let manifest_path = output_dir.join(MANIFEST_FILE);
+    let contents = serde_json::to_string_pretty(&manifest).map_err(|e| format!("Failed to serialize the manifest: {}", e))?;
+    std::fs::write(&manifest_path, contents).map_err(|e| format!("Failed to write {:?}: {}", manifest_path, e))?;
+    info!("Wrote recording manifest {:?}", manifest_path);
+
+    Ok(manifest_path)
+}
+
+/// Stitches the local video and audio segments of the last recording into `output.mp4` in the data
+/// directory and returns its path. Uploaded segments are deleted, so this needs the recording to have
+/// been made with uploading disabled, in local mode or with `keep_local_chunks` set.
+#[tauri::command]
+pub async fn finalize_recording(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<String, RecordingError> {
+    let span = state.lock().await.span.clone();
+    async move {
+        let guard = state.lock().await;
+
+        if guard.media_process.is_some() {
+            return Err(RecordingError::RecordingInProgress("Cannot finalize a recording that is still in progress".to_string()));
+        }
+
+        let output_dir = current_output_dir(&guard)?;
+        let container_format = guard.recording_options.as_ref().map_or("mpegts", |options| options.container_format.as_str());
+        let output_path = output_dir.join(format!("output.{}", output_extension(container_format)));
+
+        // Every segment has its timestamps reset, so the offset applied between the tracks while
+        // recording has to be applied again when muxing them.
+        mux_segments(&output_dir.join("chunks"), guard.av_offset_seconds, &output_path).await?;
+
+        Ok(output_path.to_string_lossy().to_string())
+    }.instrument(span).await
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FinalizedRecording {
+    pub path: String,
+    pub size_bytes: u64,
+    /// `None` when ffmpeg couldn't read the duration back from the muxed file.
+    pub duration_secs: Option<f64>,
+}
+
+/// Like `finalize_recording`, but muxes into `output_path`, typically picked through a save dialog.
+/// Its extension has to match the recording's container and its directory has to be writable.
+#[tauri::command]
+pub async fn finalize_to_path(output_path: String, state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<FinalizedRecording, RecordingError> {
+    let span = state.lock().await.span.clone();
+    async move {
+        let guard = state.lock().await;
+
+        if guard.media_process.is_some() {
+            return Err(RecordingError::RecordingInProgress("Cannot finalize a recording that is still in progress".to_string()));
+        }
+
+        let output_dir = current_output_dir(&guard)?;
+        let container_format = guard.recording_options.as_ref().map_or("mpegts", |options| options.container_format.as_str());
+        let expected_extension = output_extension(container_format);
+
+        let output_path = PathBuf::from(output_path);
+        let extension = output_path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        if extension.as_deref() != Some(expected_extension) {
+            return Err(RecordingError::InvalidOptions(format!("The output path has to end in .{} for this recording", expected_extension)));
+        }
+
+        let parent_dir = output_path.parent()
+            .filter(|parent_dir| !parent_dir.as_os_str().is_empty())
+            .ok_or(RecordingError::InvalidOptions("The output path has no parent directory".to_string()))?;
+        if !parent_dir.is_dir() {
+            return Err(RecordingError::InvalidOptions(format!("{:?} is not a directory", parent_dir)));
+        }
+        check_dir_writable(parent_dir).map_err(|e| RecordingError::InvalidOptions(format!("{:?} is not writable: {}", parent_dir, e)))?;
+
+        mux_segments(&output_dir.join("chunks"), guard.av_offset_seconds, &output_path).await?;
+
+        let size_bytes = std::fs::metadata(&output_path).map_err(|e| format!("Failed to read {:?}: {}", output_path, e))?.len();
+        let duration_secs = match ffmpeg_path_as_str() {
+            Ok(ffmpeg_path) => segment_duration_secs(&ffmpeg_path, &output_path).await,
+            Err(_) => None,
+        };
+        info!("Recording finalized to {:?}, {} bytes", output_path, size_bytes);
+
+        Ok(FinalizedRecording { path: output_path.to_string_lossy().to_string(), size_bytes, duration_secs })
+    }.instrument(span).await
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SegmentProblem {
+    pub filename: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TrackVerification {
+    pub segment_count: usize,
+    /// Segment count times the segment duration.
+    pub expected_duration_secs: f64,
+    pub actual_duration_secs: f64,
+    pub duration_mismatch: bool,
+    pub problems: Vec<SegmentProblem>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RecordingVerification {
+    pub valid: bool,
+    pub tracks: BTreeMap<String, TrackVerification>,
+}
+
+/// Decodes every segment of the last recording that is still on disk and checks that the durations
+/// of each track add up to its segment count. Uploaded segments can't be decoded any more, so only
+/// the duration probed before their upload is checked.
+#[tauri::command]
+pub async fn verify_recording(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<RecordingVerification, RecordingError> {
+    let guard = state.lock().await;
+
+    if guard.media_process.is_some() {
+        return Err(RecordingError::RecordingInProgress("Cannot verify a recording that is still in progress".to_string()));
+    }
+
+    let audio_track_count = guard.recording_options.as_ref().ok_or("No recording to verify".to_string())?.audio_track_names.len();
+    let output_dir = current_output_dir(&guard)?;
+    let segment_durations = guard.segment_durations.lock().await.clone();
+    // Decoding every segment takes a while, and other commands shouldn't wait on it.
+    drop(guard);
+    let ffmpeg_path = ffmpeg_path_as_str().map_err(RecordingError::FfmpegNotFound)?;
+
+    let track_names = ["video", "audio", "webcam"].into_iter().map(String::from)
+        .chain((0..audio_track_count).map(audio_track_video_type));
+    let mut tracks = BTreeMap::new();
+    for track in track_names {
+        let chunks_dir = output_dir.join("chunks").join(&track);
+        let segments = load_ordered_segment_list(&chunks_dir);
+        if segments.is_empty() {
+            continue;
+        }
+
+        let mut problems = Vec::new();
+        let mut actual_duration_secs = 0.0;
+        for filename in &segments {
+            let segment_path = chunks_dir.join(filename);
+            let duration_secs = if segment_path.is_file() {
+                if let Err(message) = decode_segment(&ffmpeg_path, &segment_path).await {
+                    problems.push(SegmentProblem { filename: filename.clone(), message });
+                }
+                segment_duration_secs(&ffmpeg_path, &segment_path).await
+            } else {
+                segment_durations.get(&format!("{}/{}", track, filename)).copied()
+            };
+
+            match duration_secs {
+                Some(duration_secs) if duration_secs > 0.0 => actual_duration_secs += duration_secs,
+                Some(_) => problems.push(SegmentProblem { filename: filename.clone(), message: "Segment has a duration of zero".to_string() }),
+                None => problems.push(SegmentProblem { filename: filename.clone(), message: "Segment duration could not be determined".to_string() }),
+            }
+        }
+
+        // The last segment is cut short by the stop, so the total may fall up to one segment short.
+        let segment_secs = SEGMENT_DURATION_SECS as f64;
+        let expected_duration_secs = segments.len() as f64 * segment_secs;
+        let duration_mismatch = actual_duration_secs < expected_duration_secs - segment_secs - VERIFY_DURATION_TOLERANCE_SECS
+            || actual_duration_secs > expected_duration_secs + VERIFY_DURATION_TOLERANCE_SECS;
+        if duration_mismatch {
+            warn!("The {} track is {:.2}s long, expected about {:.0}s from its {} segments", track, actual_duration_secs, expected_duration_secs, segments.len());
+        }
+
+        tracks.insert(track, TrackVerification {
+            segment_count: segments.len(),
+            expected_duration_secs,
+            actual_duration_secs,
+            duration_mismatch,
+            problems,
+        });
+    }
+
+    let valid = tracks.contains_key("video") && tracks.values().all(|track| !track.duration_mismatch && track.problems.is_empty());
+    info!("Recording verification finished, valid: {}", valid);
+
+    Ok(RecordingVerification { valid, tracks })
+}
+
+/// Decodes a whole segment, failing with the first error ffmpeg reports.
+async fn decode_segment(ffmpeg_path: &str, segment_path: &Path) -> Result<(), String> {
+    let output = tokio::process::Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-v", "error", "-i"])
+        .arg(segment_input(segment_path))
+        .args(["-f", "null", "-"])
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    match stderr.lines().find(|line| !line.trim().is_empty()) {
+        Some(line) => Err(line.to_string()),
+        None if !output.status.success() => Err(format!("FFmpeg exited with {}", output.status)),
+        None => Ok(()),
+    }
+}
+
+/// Copies the segments the replay window currently retains into their own directory under
+/// `replays/` and muxes them into a playable MP4 there, returning its path.
+#[tauri::command]
+pub async fn save_replay(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<String, RecordingError> {
+    let guard = state.lock().await;
+
+    let replay_window_secs = guard.recording_options.as_ref()
+        .and_then(|options| options.replay_window_secs)
+        .ok_or(RecordingError::InvalidOptions("The current recording has no replay window".to_string()))?;
+    let output_dir = current_output_dir(&guard)?;
+    let container_format = guard.recording_options.as_ref().map_or("mpegts", |options| options.container_format.as_str()).to_string();
+    // Copying and muxing take a while, and the recording shouldn't be held up until they are done.
+    drop(guard);
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_millis();
+    let replay_dir = output_dir.join("replays").join(timestamp.to_string());
+    copy_latest_segments(&output_dir.join("chunks"), &replay_dir.join("chunks"), replay_segment_count(replay_window_secs))?;
+
+    let output_path = replay_dir.join(format!("output.{}", output_extension(&container_format)));
+    // The retained tail starts mid-recording, so the start offset between the tracks no longer applies.
+    mux_segments(&replay_dir.join("chunks"), 0.0, &output_path).await?;
+
+    info!("Replay saved to {:?}", output_path);
+
+    Ok(output_path.to_string_lossy().to_string())
+}
+
+/// Muxes the last `segment_count` finished segments of the current recording (3 by default) into a
+/// video under `preview/` in its output directory and returns its path, replacing the last preview.
+/// Only segments ffmpeg has listed are used, so the one it is still writing is never read.
+/// Uploaded segments are deleted as soon as they are up, so a recording that uploads needs
+/// `keep_local_chunks` set for this.
+#[tauri::command]
+pub async fn render_preview(segment_count: Option<usize>, state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<String, RecordingError> {
+    let guard = state.lock().await;
+    if guard.uploader.is_some() && !guard.recording_options.as_ref().map_or(false, |options| options.keep_local_chunks) {
+        return Err(RecordingError::InvalidOptions("Previews of a recording that uploads need keep_local_chunks set".to_string()));
+    }
+    let output_dir = current_output_dir(&guard)?;
+    let container_format = guard.recording_options.as_ref().map_or("mpegts", |options| options.container_format.as_str()).to_string();
+    // Muxing takes a while, and the recording shouldn't be held up until it is done.
+    drop(guard);
+
+    let preview_dir = output_dir.join("preview");
+    if preview_dir.exists() {
+        std::fs::remove_dir_all(&preview_dir).map_err(|e| format!("Failed to delete the previous preview: {}", e))?;
+    }
+    copy_latest_segments(&output_dir.join("chunks"), &preview_dir.join("chunks"), segment_count.unwrap_or(PREVIEW_SEGMENT_COUNT).max(1))?;
+
+    let output_path = preview_dir.join(format!("output.{}", output_extension(&container_format)));
+    mux_segments(&preview_dir.join("chunks"), 0.0, &output_path).await?;
+
+    info!("Preview rendered to {:?}", output_path);
+
+    Ok(output_path.to_string_lossy().to_string())
+}
+
+/// Copies the last `segment_count` segments of the video and audio tracks that are still on disk
+/// from `chunks_dir` into `target_dir`, along with segment lists naming just those. Segments that
+/// are deleted while they are copied are left out.
+fn copy_latest_segments(chunks_dir: &Path, target_dir: &Path, segment_count: usize) -> Result<(), String> {
+    for track in ["video", "audio"] {
+        let track_dir = chunks_dir.join(track);
+        let target_track_dir = target_dir.join(track);
+        std::fs::create_dir_all(&target_track_dir).map_err(|e| format!("Failed to create {:?}: {}", target_track_dir, e))?;
+
+        let segments = load_ordered_segment_list(&track_dir)
+            .into_iter()
+            .filter(|segment_filename| track_dir.join(segment_filename).is_file())
+            .collect::<Vec<_>>();
+        let mut latest = Vec::new();
+
+        for segment_filename in &segments[segments.len().saturating_sub(segment_count)..] {
+            match std::fs::copy(track_dir.join(segment_filename), target_track_dir.join(segment_filename)) {
+                Ok(_) => latest.push(segment_filename.as_str()),
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    warn!("Segment {} was deleted while it was copied, leaving it out", segment_filename);
+                },
+                Err(e) => return Err(format!("Failed to copy segment {}: {}", segment_filename, e)),
+            }
+        }
+
+        let init_segment_path = track_dir.join(INIT_SEGMENT_FILE);
+        if init_segment_path.is_file() {
+            std::fs::copy(&init_segment_path, target_track_dir.join(INIT_SEGMENT_FILE))
+                .map_err(|e| format!("Failed to copy the init segment: {}", e))?;
+        }
+
+        std::fs::write(target_track_dir.join("segment_list.txt"), latest.join("\n"))
+            .map_err(|e| format!("Failed to write the segment list: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Keeps only the segments that fall inside the replay window, deleting older ones once a second.
+/// ffmpeg keeps `segment_list.txt` open and appends at its own offset, so it can't be rewritten
+/// underneath it; deleted segments are recorded in `PRUNED_SEGMENT_LIST_FILE` instead, which the
+/// segment list readers leave out.
+fn spawn_replay_retention(chunks_dir: PathBuf, replay_window_secs: u64, shutdown_flag: Arc<AtomicBool>, cancellation_token: CancellationToken) {
+    let segment_count = replay_segment_count(replay_window_secs);
+
+    tokio::spawn(async move {
+        while !shutdown_flag.load(Ordering::SeqCst) {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => break,
+                _ = tokio::time::sleep(REPLAY_RETENTION_INTERVAL) => {},
+            }
+
+            for track in ["video", "audio", "webcam"] {
+                let track_dir = chunks_dir.join(track);
+                let segments = load_ordered_segment_list(&track_dir);
+
+                let expired = &segments[..segments.len().saturating_sub(segment_count)];
+                if expired.is_empty() {
+                    continue;
+                }
+
+                // Listed as pruned first, so a reader never picks up a segment that is about to go.
+                if let Err(e) = append_pruned_segments(&track_dir, expired) {
+                    error!("Failed to record expired replay segments in {:?}: {}", track_dir, e);
+                    continue;
+                }
+                for segment_filename in expired {
+                    let segment_path = track_dir.join(segment_filename);
+                    if segment_path.is_file() {
+                        if let Err(e) = std::fs::remove_file(&segment_path) {
+                            error!("Failed to remove expired replay segment {:?}: {}", segment_path, e);
+                        }
+                    }
+                }
+            }
+        }
+    }.in_current_span());
+}
+
+fn replay_segment_count(replay_window_secs: u64) -> usize {
+    // Keep one extra segment so the window is always fully covered.
+    (replay_window_secs.div_ceil(SEGMENT_DURATION_SECS) + 1) as usize
+}
+
+fn no_segments_error() -> RecordingError {
+    RecordingError::RecordingTooShort { segment_duration_secs: SEGMENT_DURATION_SECS }
+}
+
+/// Reads a segment list in the order ffmpeg wrote it, leaving out segments the replay retention
+/// deleted.
+fn load_ordered_segment_list(chunks_dir: &Path) -> Vec<String> {
+    let pruned = load_pruned_segments(chunks_dir);
+    std::fs::read_to_string(chunks_dir.join("segment_list.txt"))
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.is_empty() && !pruned.contains(*line))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+fn load_pruned_segments(chunks_dir: &Path) -> HashSet<String> {
+    std::fs::read_to_string(chunks_dir.join(PRUNED_SEGMENT_LIST_FILE))
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+fn append_pruned_segments(chunks_dir: &Path, segments: &[String]) -> io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(chunks_dir.join(PRUNED_SEGMENT_LIST_FILE))?;
+    for segment_filename in segments {
+        writeln!(file, "{}", segment_filename)?;
+    }
+    Ok(())
+}
+
+/// Extension of the file the segments of a `container_format` recording are muxed into.
+fn output_extension(container_format: &str) -> &'static str {
+    match container_format {
+        "webm" => "webm",
+        _ => "mp4",
+    }
+}
+
+/// Muxes the video and (if any) audio segments under `chunks_dir` into `output_path`, delaying
+/// the video by `av_offset_seconds`, or the audio when it is negative.
+async fn mux_segments(chunks_dir: &Path, av_offset_seconds: f64, output_path: &Path) -> Result<(), String> {
+    let result = mux_concat_lists(chunks_dir, av_offset_seconds, output_path).await;
+
+    for track_dir in ["video", "audio"] {
+        let joined_path = chunks_dir.join(track_dir).join(JOINED_SEGMENTS_FILE);
+        if let Err(e) = std::fs::remove_file(&joined_path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to remove {:?}: {}", joined_path, e);
+            }
+        }
+    }
+
+    result
+}
+
+async fn mux_concat_lists(chunks_dir: &Path, av_offset_seconds: f64, output_path: &Path) -> Result<(), String> {
+    let video_concat_list = write_concat_list(&chunks_dir.join("video")).await?
+        .ok_or_else(|| no_segments_error().to_string())?;
+    let audio_concat_list = write_concat_list(&chunks_dir.join("audio")).await?;
+
+    let mut ffmpeg_args: Vec<String> = vec!["-y".to_string()];
+
+    // Without separate audio segments (no audio, or a single muxed output) the video segments are
+    // already in sync.
+    if av_offset_seconds > 0.0 && audio_concat_list.is_some() {
+        ffmpeg_args.extend(["-itsoffset".to_string(), format!("{:.3}", av_offset_seconds)]);
+    }
+    ffmpeg_args.extend(concat_input_args(&video_concat_list));
+
+    if let Some(ref audio_concat_list) = audio_concat_list {
+        if av_offset_seconds < 0.0 {
+            ffmpeg_args.extend(["-itsoffset".to_string(), format!("{:.3}", -av_offset_seconds)]);
+        }
+        ffmpeg_args.extend(concat_input_args(audio_concat_list));
+        ffmpeg_args.extend(["-map", "0:v", "-map", "1:a"].into_iter().map(|s| s.to_string()));
+    }
+
+    // WebM recordings hold Opus audio, which needs neither the ADTS conversion nor the MP4 flags.
+    if output_path.extension().map_or(false, |extension| extension == "webm") {
+        ffmpeg_args.extend(["-c", "copy"].into_iter().map(|s| s.to_string()));
+    } else {
+        if audio_concat_list.is_some() {
+            ffmpeg_args.extend(["-bsf:a", "aac_adtstoasc"].into_iter().map(|s| s.to_string()));
+        }
+        ffmpeg_args.extend(["-c", "copy", "-movflags", "+faststart"].into_iter().map(|s| s.to_string()));
+    }
+    ffmpeg_args.push(output_path.to_string_lossy().to_string());
+
+    info!("Finalizing recording into {:?}", output_path);
+
+    let output = tokio::process::Command::new(ffmpeg_path_as_str()?)
+        .args(&ffmpeg_args)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run FFmpeg: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr_lines: Vec<&str> = stderr.lines().collect();
+        let stderr_tail = stderr_lines[stderr_lines.len().saturating_sub(20)..].join("\n");
+        return Err(format!("FFmpeg failed to finalize the recording ({}):\n{}", output.status, stderr_tail));
+    }
+
+    Ok(())
+}
+
+/// Writes `playlist.m3u8` next to the segments left in `chunks_dir`, using each segment's actual
+/// duration for its `#EXTINF`. Segments with their own header have their timestamps reset, so they
+/// are separated by discontinuities. Fragmented MP4 segments that carry their own header get an
+/// `#EXT-X-MAP` pointing at that header's byte range, since HLS only plays fMP4 through one.
+async fn write_hls_playlist(chunks_dir: &Path) -> Result<(), String> {
+    let ffmpeg_path = ffmpeg_path_as_str()?;
+    let mut entries = Vec::new();
+
+    for segment_filename in load_ordered_segment_list(chunks_dir) {
+        let segment_path = chunks_dir.join(&segment_filename);
+        // Uploaded segments have already been deleted.
+        if !segment_path.exists() {
+            continue;
+        }
+        let duration = segment_duration_secs(&ffmpeg_path, &segment_path).await
+            .unwrap_or(SEGMENT_DURATION_SECS as f64);
+        let header_range = fmp4_header_range(&segment_path)
+            .map_err(|e| format!("Failed to read segment {:?}: {}", segment_path, e))?;
+        entries.push((segment_filename, duration, header_range));
+    }
+
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    // fmp4 segments continue each other's timestamps after one shared header, which needs version 7.
+    let has_init_segment = chunks_dir.join(INIT_SEGMENT_FILE).is_file();
+    let target_duration = entries.iter().map(|(_, duration, _)| duration.ceil() as u64).max().unwrap_or(SEGMENT_DURATION_SECS);
+    let has_fmp4_segments = entries.iter().any(|(_, _, header_range)| header_range.is_some());
+    let version = if has_init_segment || has_fmp4_segments { 7 } else { 3 };
+    let mut playlist = format!("#EXTM3U\n#EXT-X-VERSION:{}\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n", version, target_duration);
+    if has_init_segment {
+        playlist.push_str(&format!("#EXT-X-MAP:URI=\"{}\"\n", INIT_SEGMENT_FILE));
+    }
+    for (index, (segment_filename, duration, header_range)) in entries.iter().enumerate() {
+        if index > 0 && !has_init_segment {
+            playlist.push_str("#EXT-X-DISCONTINUITY\n");
+        }
+        match header_range {
+            Some((header_length, segment_length)) if !has_init_segment => {
+                playlist.push_str(&format!("#EXT-X-MAP:URI=\"{}\",BYTERANGE=\"{}@0\"\n", segment_filename, header_length));
+                playlist.push_str(&format!("#EXTINF:{:.3},\n", duration));
+                playlist.push_str(&format!("#EXT-X-BYTERANGE:{}@{}\n{}\n", segment_length - header_length, header_length, segment_filename));
+            },
+            _ => playlist.push_str(&format!("#EXTINF:{:.3},\n{}\n", duration, segment_filename)),
+        }
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+
+    let playlist_path = chunks_dir.join("playlist.m3u8");
+    std::fs::write(&playlist_path, playlist).map_err(|e| format!("Failed to write {:?}: {}", playlist_path, e))?;
+    info!("Wrote HLS playlist {:?}", playlist_path);
+
+    Ok(())
+}
+
+/// Returns the length of the header (everything before the first `moof` box) and the total length
+/// of a fragmented MP4 file, or `None` when `path` isn't one, such as an MPEG-TS or WebM segment.
+fn fmp4_header_range(path: &Path) -> io::Result<Option<(u64, u64)>> {
+    let mut file = File::open(path)?;
+    let file_length = file.metadata()?.len();
+    let mut offset = 0u64;
+    let mut has_moov = false;
+
+    while offset + 8 <= file_length {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header[..8])?;
+        let box_type = &header[4..8];
+        let box_length = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            0 => file_length - offset,
+            1 => {
+                file.read_exact(&mut header[8..16])?;
+                u64::from_be_bytes(header[8..16].try_into().expect("8 byte slice"))
+            },
+            length => length as u64,
+        };
+
+        if offset == 0 && box_type != b"ftyp" {
+            return Ok(None);
+        }
+        match box_type {
+            b"moov" => has_moov = true,
+            b"moof" => return Ok(has_moov.then_some((offset, file_length))),
+            _ => {},
+        }
+        if box_length < 8 {
+            return Ok(None);
+        }
+        offset += box_length;
+    }
+
+    Ok(None)
+}
+
+/// Reads a segment's duration from the `Duration: HH:MM:SS.ss` line ffmpeg prints for its input.
+async fn segment_duration_secs(ffmpeg_path: &str, segment_path: &Path) -> Option<f64> {
+    let output = tokio::process::Command::new(ffmpeg_path)
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(segment_input(segment_path))
+        .output()
+        .await
+        .ok()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    let timestamp = stderr.split("Duration: ").nth(1)?.split(',').next()?;
+    let mut seconds = 0.0;
+    for part in timestamp.trim().split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+/// The ffmpeg input for a segment: the segment itself, or for an `fmp4` segment it and the init
+/// segment it can't be read without, one after the other.
+fn segment_input(segment_path: &Path) -> OsString {
+    let init_segment_path = segment_path.with_file_name(INIT_SEGMENT_FILE);
+    if segment_path.extension().map_or(false, |extension| extension == "m4s") && init_segment_path.is_file() {
+        let mut input = OsString::from("concat:");
+        input.push(init_segment_path.as_os_str());
+        input.push("|");
+        input.push(segment_path.as_os_str());
+        input
+    } else {
+        segment_path.as_os_str().to_owned()
+    }
+}
+
+/// Writes an ffmpeg concat demuxer list for the segments in `chunks_dir`, in recording order.
+/// Returns `None` when no segments were recorded.
+async fn write_concat_list(chunks_dir: &Path) -> Result<Option<PathBuf>, String> {
+    let mut concat_list = String::new();
+    let segments = load_ordered_segment_list(chunks_dir);
+    if let Some(missing) = segments.iter().map(|segment_filename| chunks_dir.join(segment_filename)).find(|segment_path| !segment_path.is_file()) {
+        return Err(format!("Segment {:?} is missing, it may already have been uploaded", missing));
+    }
+
+    let init_segment_path = chunks_dir.join(INIT_SEGMENT_FILE);
+    if !segments.is_empty() && init_segment_path.is_file() {
+        // fmp4 segments are fragments of one file, which they make up again behind the init segment.
+        let joined_path = chunks_dir.join(JOINED_SEGMENTS_FILE);
+        let part_paths: Vec<PathBuf> = std::iter::once(init_segment_path)
+            .chain(segments.iter().map(|segment_filename| chunks_dir.join(segment_filename)))
+            .collect();
+        let joined_path_owned = joined_path.clone();
+        tokio::task::spawn_blocking(move || join_segment_files(&part_paths, &joined_path_owned))
+            .await
+            .map_err(|e| format!("Failed to join the segments: {}", e))??;
+        concat_list.push_str(&format!("file '{}'\n", joined_path.to_string_lossy().replace('\'', "'\\''")));
+    } else {
+        for segment_filename in &segments {
+            let segment_path = chunks_dir.join(segment_filename);
+            concat_list.push_str(&format!("file '{}'\n", segment_path.to_string_lossy().replace('\'', "'\\''")));
+        }
+    }
+
+    if concat_list.is_empty() {
+        return Ok(None);
+    }
+
+    let concat_list_path = chunks_dir.join("concat_list.txt");
+    std::fs::write(&concat_list_path, concat_list).map_err(|e| format!("Failed to write {:?}: {}", concat_list_path, e))?;
+    Ok(Some(concat_list_path))
+}
+
+fn join_segment_files(part_paths: &[PathBuf], joined_path: &Path) -> Result<(), String> {
+    let mut joined = File::create(joined_path).map_err(|e| format!("Failed to create {:?}: {}", joined_path, e))?;
+    for part_path in part_paths {
+        let mut part = File::open(part_path).map_err(|e| format!("Failed to open {:?}: {}", part_path, e))?;
+        io::copy(&mut part, &mut joined).map_err(|e| format!("Failed to write {:?}: {}", joined_path, e))?;
+    }
+    Ok(())
+}
+
+fn concat_input_args(concat_list_path: &Path) -> Vec<String> {
+    vec![
+        "-f".to_string(), "concat".to_string(),
+        "-safe".to_string(), "0".to_string(),
+        "-i".to_string(), concat_list_path.to_string_lossy().to_string(),
+    ]
+}
+
+/// Reports how many of the segments written so far have been uploaded, per track and overall.
+#[tauri::command]
+pub async fn get_upload_progress(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<UploadProgressStats, RecordingError> {
+    let guard = state.lock().await;
+    Ok(guard.upload_progress.snapshot())
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct UploadStatus {
+  /// Upload loops still running, one per uploaded track.
+  pub loops_running: usize,
+  /// Segments being uploaded right now.
+  pub segments_in_flight: u64,
+  /// Upload loops that ended with an error or panicked.
+  pub loop_errors: Vec<String>,
+  /// Segments given up on after every attempt failed.
+  pub failed_segments: Vec<String>,
+  pub has_failures: bool,
+}
+
+/// Reports the uploads of the current (or last) recording that are still in flight and which have
+/// failed. Upload loops that have finished are joined here, so their errors show up right away
+/// instead of only once the recording is stopped.
+#[tauri::command]
+pub async fn get_upload_status(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<UploadStatus, RecordingError> {
+    let mut guard = state.lock().await;
+
+    let (finished, running): (Vec<_>, Vec<_>) = std::mem::take(&mut guard.upload_handles)
+        .into_iter()
+        .partition(|upload_handle| upload_handle.is_finished());
+    guard.upload_handles = running;
+    for upload_handle in finished {
+        let error = match upload_handle.await {
+            Ok(Ok(())) => continue,
+            Ok(Err(e)) => format!("Upload loop failed: {}", e),
+            Err(e) => format!("Upload loop panicked: {}", e),
+        };
+        error!("{}", error);
+        guard.upload_loop_errors.push(error);
+    }
+
+    let progress = &guard.upload_progress;
+    let segments_in_flight = [&progress.video, &progress.audio, &progress.webcam].iter()
+        .map(|counters| counters.in_flight.load(Ordering::Relaxed))
+        .sum();
+    let failed_segments = guard.failed_uploads.lock().await.clone();
+
+    Ok(UploadStatus {
+        loops_running: guard.upload_handles.len(),
+        segments_in_flight,
+        has_failures: !guard.upload_loop_errors.is_empty() || !failed_segments.is_empty(),
+        loop_errors: guard.upload_loop_errors.clone(),
+        failed_segments,
+    })
+}
+
+/// Throws the current recording away: stops capture and every ffmpeg process without waiting for
+/// the last segments, abandons pending uploads and deletes the chunk directories.
+#[tauri::command]
+pub async fn cancel_recording(app_handle: AppHandle, state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<(), RecordingError> {
+    let mut guard = state.lock().await;
+
+    // The recording options and output directory left over from the last recording belong to
+    // one that has ended, and its chunks may still be needed to finalize it.
+    if !guard.is_recording {
+        info!("No recording in progress, nothing to cancel");
+        return Ok(());
+    }
+
+    // Without a media process the recording is still counting down, with nothing recorded yet, or
+    // already being stopped, so cancelling the token is all that's left to do.
+    if guard.media_process.is_none() {
+        info!("Cancelling a recording that isn't capturing");
+        guard.cancellation_token.cancel();
+        guard.is_recording = false;
+        return Ok(());
+    }
+
+    info!("Cancelling recording...");
+
+    guard.upload_cancelled.store(true, Ordering::SeqCst);
+    guard.cancellation_token.cancel();
+    guard.shutdown_flag.store(true, Ordering::SeqCst);
+
+    for upload_handle in std::mem::take(&mut guard.upload_handles) {
+        upload_handle.abort();
+    }
+
+    if let Some(mut media_process) = guard.media_process.take() {
+        media_process.cancel_media_recording().await;
+    }
+    guard.session = None;
+    guard.is_recording = false;
+
+    let output_dir = current_output_dir(&guard)?;
+
+    let track_count = guard.recording_options.as_ref().map_or(0, |options| options.audio_track_names.len());
+    let chunks_dirs = ["audio", "video", "webcam"].into_iter().map(String::from)
+        .chain((0..track_count).map(audio_track_video_type));
+
+    for chunks_dir in chunks_dirs {
+        let chunks_dir = output_dir.join("chunks").join(chunks_dir);
+        if chunks_dir.exists() {
+            std::fs::remove_dir_all(&chunks_dir)
+                .map_err(|e| format!("Failed to delete {:?}: {}", chunks_dir, e))?;
+        }
+    }
+
+    remove_session_marker(&output_dir);
+
+    info!("Recording cancelled and chunks deleted.");
+
+    let video_id = guard.recording_options.take().map(|options| options.video_id).unwrap_or_default();
+    emit_event(&app_handle, "recording-cancelled", RecordingLifecyclePayload { video_id });
+
+    Ok(())
+}
+
+/// The offset wall-clock timestamps are corrected by and its source. A failed NTP query falls back
+/// to the configured offset, as the recording itself doesn't depend on it.
+async fn resolve_clock_offset(options: &RecordingOptions) -> (String, i64) {
+    if options.clock_source != "ntp" {
+        return ("system".to_string(), options.clock_offset_ms);
+    }
+
+    let ntp_server = options.ntp_server.clone();
+    match tokio::task::spawn_blocking(move || ntp_clock_offset_ms(&ntp_server, NTP_TIMEOUT)).await {
+        Ok(Ok(offset_ms)) => {
+            info!("System clock is {}ms behind {}", offset_ms, options.ntp_server);
+            ("ntp".to_string(), offset_ms)
+        },
+        Ok(Err(e)) => {
+            warn!("Falling back to the system clock: {}", e);
+            ("system".to_string(), options.clock_offset_ms)
+        },
+        Err(e) => {
+            warn!("Falling back to the system clock, the NTP query failed: {}", e);
+            ("system".to_string(), options.clock_offset_ms)
+        },
+    }
+}
+
+/// Emits `recording-countdown` once a second from `start_delay_secs` down to 1, returning early
+/// when the recording is stopped or cancelled in the meantime.
+async fn run_countdown(app_handle: &AppHandle, start_delay_secs: u64, cancellation_token: &CancellationToken) {
+    for remaining_secs in (1..=start_delay_secs).rev() {
+        emit_event(app_handle, "recording-countdown", RecordingCountdownPayload { remaining_secs });
+        tokio::select! {
+            _ = cancellation_token.cancelled() => return,
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {},
+        }
+    }
 }
 
+/// Sets the directory recordings are written to unless `output_dir` overrides it, creating it if
+/// needed. Without one, the app data directory is used.
 #[tauri::command]
-pub async fn start_dual_recording(
-  state: State<'_, Arc<Mutex<RecordingState>>>,
-  options: RecordingOptions,
-) -> Result<(), String> {
-  println!("Starting screen recording...");
-  let mut state_guard = state.lock().await;
-  
-  let shutdown_flag = Arc::new(AtomicBool::new(false));
+pub async fn set_data_dir(data_dir: String, state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<(), RecordingError> {
+    let mut guard = state.lock().await;
 
-  let data_dir = state_guard.data_dir.as_ref()
-      .ok_or("Data directory is not set in the recording state".to_string())?.clone();
+    if guard.is_recording {
+        return Err(RecordingError::RecordingInProgress("Cannot change the data directory while a recording is in progress".to_string()));
+    }
 
-  println!("data_dir: {:?}", data_dir);
-  
-  let audio_chunks_dir = data_dir.join("chunks/audio");
-  let video_chunks_dir = data_dir.join("chunks/video");
-  let screenshot_dir = data_dir.join("screenshots");
+    let data_dir = PathBuf::from(data_dir);
+    std::fs::create_dir_all(&data_dir)
+        .and_then(|_| check_dir_writable(&data_dir))
+        .map_err(|e| RecordingError::InvalidOptions(format!("Data directory {:?} is not writable: {}", data_dir, e)))?;
 
-  clean_and_create_dir(&audio_chunks_dir)?;
-  clean_and_create_dir(&video_chunks_dir)?;
-  clean_and_create_dir(&screenshot_dir)?;
-  
-  let audio_name = if options.audio_name.is_empty() {
-    None
-  } else {
-    Some(options.audio_name.clone())
-  };
-  
-  let media_recording_preparation = prepare_media_recording(&options, &audio_chunks_dir, &video_chunks_dir, &screenshot_dir, audio_name, state_guard.max_screen_width, state_guard.max_screen_height);
-  let media_recording_result = media_recording_preparation.await.map_err(|e| e.to_string())?;
+    info!("Data directory set to {:?}", data_dir);
+    guard.data_dir = Some(data_dir);
 
-  state_guard.media_process = Some(media_recording_result);
-  state_guard.recording_options = Some(options.clone());
-  state_guard.shutdown_flag = shutdown_flag.clone();
-  state_guard.video_uploading_finished = Arc::new(AtomicBool::new(false));
-  state_guard.audio_uploading_finished = Arc::new(AtomicBool::new(false));
+    Ok(())
+}
 
-  let is_local_mode = match dotenv_codegen::dotenv!("NEXT_PUBLIC_LOCAL_MODE") {
-      "true" => true,
-      _ => false,
-  };
+/// Deletes the local chunks and screenshots of the last recording, or of the recording in
+/// `session_dir` when given, to reclaim the disk space before the next recording would.
+/// `session_dir` has to be the data directory or an output directory recordings are made in, or
+/// lie inside one. Replays and finalized recordings are kept.
+#[tauri::command]
+pub async fn clear_recording_data(session_dir: Option<String>, app_handle: AppHandle, state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<(), RecordingError> {
+    let guard = state.lock().await;
+
+    if guard.is_recording {
+        return Err(RecordingError::RecordingInProgress("Cannot clear recording data while a recording or its uploads are in progress".to_string()));
+    }
+
+    let session_dir = match session_dir {
+        Some(session_dir) => {
+            let saved_output_dir = load_recording_options(app_handle)
+                .unwrap_or_else(|e| {
+                    warn!("Failed to load the saved recording options: {}", e);
+                    None
+                })
+                .and_then(|options| requested_output_dir(&options).cloned());
+            let allowed_dirs: Vec<PathBuf> = guard.data_dir.iter().chain(guard.output_dir.iter()).cloned().chain(saved_output_dir).collect();
+            resolve_session_dir(Path::new(&session_dir), &allowed_dirs)?
+        },
+        None => current_output_dir(&guard)?,
+    };
 
-  if !is_local_mode {
-      let screen_upload = start_upload_loop(video_chunks_dir.clone(), options.clone(), "video".to_string(), shutdown_flag.clone(), state_guard.video_uploading_finished.clone());
-      let audio_upload = start_upload_loop(audio_chunks_dir, options.clone(), "audio".to_string(), shutdown_flag.clone(), state_guard.audio_uploading_finished.clone());
+    for dir in [session_dir.join("chunks"), session_dir.join("screenshots")] {
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).map_err(|e| format!("Failed to delete {:?}: {}", dir, e))?;
+        }
+    }
 
-      drop(state_guard);
+    info!("Recording data in {:?} cleared.", session_dir);
 
-      println!("Starting upload loops...");
+    Ok(())
+}
 
-      match tokio::try_join!(screen_upload, audio_upload) {
-          Ok(_) => {
-              println!("Both upload loops completed successfully.");
-          },
-          Err(e) => {
-              eprintln!("An error occurred: {}", e);
-          },
-      }
-  } else {
-      println!("Skipping upload loops due to NEXT_PUBLIC_LOCAL_MODE being set to 'true'.");
+/// Canonicalizes `session_dir` and checks that it is one of `allowed_dirs` or inside one, so a
+/// path from the frontend can't point the deletion anywhere else, also not through `..` or links.
+fn resolve_session_dir(session_dir: &Path, allowed_dirs: &[PathBuf]) -> Result<PathBuf, RecordingError> {
+    let session_dir = session_dir.canonicalize()
+        .map_err(|e| RecordingError::InvalidOptions(format!("Invalid session directory {:?}: {}", session_dir, e)))?;
+    let allowed = allowed_dirs.iter()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .any(|dir| session_dir.starts_with(dir));
+    if !allowed {
+        return Err(RecordingError::InvalidOptions(format!("{:?} is not inside the data directory or an output directory", session_dir)));
+    }
+    Ok(session_dir)
+}
+
+/// Written into the chunks directory when a recording starts and removed once it has been stopped
+/// or cancelled, so a recording cut short by a crash is found on the next start.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct SessionMarker {
+  options: RecordingOptions,
+  started_at: DateTime<Utc>,
+  av_offset_seconds: f64,
+  /// The key isn't saved, so the segments of an encrypted recording can't be uploaded once recovered.
+  encrypted: bool,
+}
+
+fn write_session_marker(output_dir: &Path, marker: &SessionMarker) -> Result<(), String> {
+  let marker_path = output_dir.join("chunks").join(SESSION_MARKER_FILE);
+  let json = serde_json::to_string_pretty(marker).map_err(|e| format!("Failed to serialize the session marker: {}", e))?;
+  std::fs::write(&marker_path, json).map_err(|e| format!("Failed to write {:?}: {}", marker_path, e))
+}
+
+fn remove_session_marker(output_dir: &Path) {
+  let marker_path = output_dir.join("chunks").join(SESSION_MARKER_FILE);
+  if let Err(e) = std::fs::remove_file(&marker_path) {
+    if e.kind() != ErrorKind::NotFound {
+      warn!("Failed to remove {:?}: {}", marker_path, e);
+    }
   }
+}
 
-  Ok(())
+/// A recording that was still running when the app exited.
+#[derive(Debug, Serialize, Clone)]
+pub struct InterruptedSession {
+  pub output_dir: PathBuf,
+  pub video_id: String,
+  pub started_at: String,
+  /// Segments left on disk per track; uploaded ones are gone.
+  pub segment_counts: BTreeMap<String, usize>,
+  pub encrypted: bool,
+  /// Where the segments were muxed to by the `finalize` action.
+  pub output_path: Option<String>,
+  /// Why the action failed for this recording, whose chunks are then kept.
+  pub error: Option<RecordingErrorPayload>,
 }
 
+/// Looks for recordings interrupted by a crash in the data directory and the saved `output_dir`,
+/// meant to be called on startup. Depending on `action`, the ones with segments left are:
+///
+/// - `detect` (the default): only reported.
+/// - `finalize`: muxed into `output.<ext>` in their directory, like `finalize_recording`. This
+///   needs every segment, so recordings that were partly uploaded can only be uploaded or cleaned.
+/// - `upload`: uploaded with the options they were recorded with.
+/// - `clean`: deleted.
+///
+/// Interrupted recordings without any segments are always deleted. Their chunks are removed after
+/// any action but `detect`, and they aren't reported again. An action failing for one recording
+/// is reported in its `error` and doesn't keep the others from being handled.
 #[tauri::command]
-pub async fn stop_all_recordings(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<(), String> {
-    let mut guard = state.lock().await;
-    
-    println!("Stopping media recording...");
-    
-    guard.shutdown_flag.store(true, Ordering::SeqCst);
+pub async fn recover_or_clean_sessions(
+    action: Option<String>,
+    app_handle: AppHandle,
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+) -> Result<Vec<InterruptedSession>, RecordingError> {
+    let action = action.unwrap_or_else(|| "detect".to_string());
+    if !["detect", "finalize", "upload", "clean"].contains(&action.as_str()) {
+        return Err(RecordingError::InvalidOptions(format!("Unknown action '{}', expected detect, finalize, upload or clean", action)));
+    }
 
-    if let Some(mut media_process) = guard.media_process.take() {
-        println!("Stopping media recording...");
-        media_process.stop_media_recording().await.expect("Failed to stop media recording");
+    let guard = state.lock().await;
+    if guard.is_recording {
+        return Err(RecordingError::RecordingInProgress("Cannot recover sessions while a recording is in progress".to_string()));
     }
 
-    let is_local_mode = match dotenv_codegen::dotenv!("NEXT_PUBLIC_LOCAL_MODE") {
-        "true" => true,
-        _ => false,
-    };
+    let saved_output_dir = load_recording_options(app_handle)
+        .unwrap_or_else(|e| {
+            warn!("Failed to load the saved recording options: {}", e);
+            None
+        })
+        .and_then(|options| requested_output_dir(&options).cloned());
+    let mut session_dirs: Vec<PathBuf> = guard.data_dir.iter().cloned().chain(saved_output_dir).collect();
+    session_dirs.dedup();
+    drop(guard);
+
+    let mut sessions = Vec::new();
+    for session_dir in session_dirs {
+        let chunks_dir = session_dir.join("chunks");
+        let marker_path = chunks_dir.join(SESSION_MARKER_FILE);
+        if !marker_path.is_file() {
+            continue;
+        }
+
+        let marker: SessionMarker = match std::fs::read_to_string(&marker_path).map_err(|e| e.to_string()).and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string())) {
+            Ok(marker) => marker,
+            Err(e) => {
+                warn!("Ignoring the unreadable session marker {:?}: {}", marker_path, e);
+                continue;
+            },
+        };
+
+        let track_names: Vec<String> = ["video", "audio", "webcam"].into_iter().map(String::from)
+            .chain((0..marker.options.audio_track_names.len()).map(audio_track_video_type))
+            .collect();
+        let segment_counts: BTreeMap<String, usize> = track_names.iter()
+            .map(|track| (track.clone(), remaining_segments(&chunks_dir.join(track)).len()))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+
+        if segment_counts.is_empty() {
+            info!("Cleaning up the interrupted recording in {:?}, which has no segments left", session_dir);
+            if let Err(e) = std::fs::remove_dir_all(&chunks_dir) {
+                error!("Failed to delete {:?}: {}", chunks_dir, e);
+            }
+            continue;
+        }
+
+        warn!("Found a recording interrupted in {:?} with {:?} segments", session_dir, segment_counts);
+
+        let result = match action.as_str() {
+            "finalize" => finalize_interrupted_session(&session_dir, &chunks_dir, &marker).await.map(Some),
+            "upload" => upload_interrupted_session(&session_dir, &chunks_dir, &marker, &track_names).await.map(|_| None),
+            _ => Ok(None),
+        };
+        let result = result.and_then(|output_path| {
+            if action != "detect" {
+                std::fs::remove_dir_all(&chunks_dir).map_err(|e| format!("Failed to delete {:?}: {}", chunks_dir, e))?;
+                info!("Interrupted recording in {:?} handled ({})", session_dir, action);
+            }
+            Ok(output_path)
+        });
+        let (output_path, error) = match result {
+            Ok(output_path) => (output_path, None),
+            Err(e) => {
+                error!("Failed to {} the interrupted recording in {:?}: {}", action, session_dir, e);
+                (None, Some(RecordingErrorPayload::from(&e)))
+            },
+        };
+
+        sessions.push(InterruptedSession {
+            output_dir: session_dir,
+            video_id: marker.options.video_id.clone(),
+            started_at: marker.started_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            segment_counts,
+            encrypted: marker.encrypted,
+            output_path,
+            error,
+        });
+    }
+
+    Ok(sessions)
+}
 
-    if !is_local_mode {
-        while !guard.video_uploading_finished.load(Ordering::SeqCst) 
-            || !guard.audio_uploading_finished.load(Ordering::SeqCst) {
-            println!("Waiting for uploads to finish...");
-            tokio::time::sleep(Duration::from_millis(50)).await;
+/// Muxes an interrupted recording into `output.<ext>` in `session_dir` and returns its path.
+async fn finalize_interrupted_session(session_dir: &Path, chunks_dir: &Path, marker: &SessionMarker) -> Result<String, RecordingError> {
+    // The tracks are muxed side by side, so a segment missing from one would shift the rest of it.
+    for track in ["video", "audio"] {
+        let track_dir = chunks_dir.join(track);
+        let listed = load_ordered_segment_list(&track_dir).len();
+        let uploaded = listed - remaining_segments(&track_dir).len();
+        if uploaded > 0 {
+            return Err(format!("{} of the {} {} segments in {:?} were already uploaded, so the recording can only be uploaded or cleaned", uploaded, listed, track, session_dir).into());
         }
     }
-    
-    println!("All recordings and uploads stopped.");
 
+    let output_path = session_dir.join(format!("output.{}", output_extension(&marker.options.container_format)));
+    mux_segments(chunks_dir, marker.av_offset_seconds, &output_path).await?;
+    Ok(output_path.to_string_lossy().to_string())
+}
+
+/// Uploads the segments an interrupted recording has left with the options it was recorded with.
+async fn upload_interrupted_session(session_dir: &Path, chunks_dir: &Path, marker: &SessionMarker, track_names: &[String]) -> Result<(), RecordingError> {
+    if marker.encrypted {
+        return Err(RecordingError::UploadFailed(format!("The recording in {:?} was encrypted and its key is gone, so it can only be finalized or cleaned", session_dir)));
+    }
+    let uploader = create_uploader(&marker.options)?;
+    for track in track_names {
+        let track_dir = chunks_dir.join(track);
+        for segment_path in remaining_segments(&track_dir) {
+            uploader.upload(&segment_path.to_string_lossy(), track).await
+                .map_err(|e| RecordingError::UploadFailed(format!("Failed to upload {:?}: {}", segment_path, e)))?;
+            if let Err(e) = std::fs::remove_file(&segment_path) {
+                warn!("Failed to remove {:?} after its upload: {}", segment_path, e);
+            }
+        }
+    }
     Ok(())
 }
 
+/// Paths of the segments listed in `chunks_dir` that are still on disk.
+fn remaining_segments(chunks_dir: &Path) -> Vec<PathBuf> {
+    load_ordered_segment_list(chunks_dir).into_iter()
+        .map(|filename| chunks_dir.join(filename))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Periodically checks the free space where chunks are written, emitting `disk-space-low` once it
+/// drops below `min_free_disk_mb` and stopping the recording if `stop_on_low_disk` is set.
+fn spawn_disk_space_monitor(app_handle: AppHandle, dir: PathBuf, options: RecordingOptions, shutdown_flag: Arc<AtomicBool>, cancellation_token: CancellationToken) {
+    tokio::spawn(async move {
+        while !shutdown_flag.load(Ordering::SeqCst) {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => break,
+                _ = tokio::time::sleep(DISK_SPACE_CHECK_INTERVAL) => {},
+            }
+
+            let Some(available_mb) = available_disk_space_mb(&dir) else { continue };
+            if available_mb >= options.min_free_disk_mb {
+                continue;
+            }
+
+            warn!("Low disk space: {} MB available, threshold is {} MB", available_mb, options.min_free_disk_mb);
+            emit_event(&app_handle, "disk-space-low", DiskSpaceLowPayload {
+                available_mb,
+                threshold_mb: options.min_free_disk_mb,
+                auto_stop: options.stop_on_low_disk,
+            });
+
+            if options.stop_on_low_disk && !shutdown_flag.load(Ordering::SeqCst) {
+                let state = app_handle.state::<Arc<Mutex<RecordingState>>>();
+                if let Err(e) = stop_all_recordings(app_handle.clone(), state).await {
+                    error!("Failed to stop recording on low disk space: {}", e);
+                }
+            }
+            break;
+        }
+    }.in_current_span());
+}
+
+/// Pauses the recording while the screen is locked, so a user stepping away doesn't leave a long
+/// black stretch in it, and resumes it once unlocked.
+fn spawn_screen_lock_monitor(app_handle: AppHandle, video_id: String, pause_state: Arc<PauseState>, shutdown_flag: Arc<AtomicBool>, cancellation_token: CancellationToken) {
+    tokio::spawn(async move {
+        while !shutdown_flag.load(Ordering::SeqCst) {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => break,
+                _ = tokio::time::sleep(SCREEN_LOCK_CHECK_INTERVAL) => {},
+            }
+
+            let locked = match tokio::task::spawn_blocking(is_session_locked).await {
+                Ok(Some(locked)) => locked,
+                Ok(None) => {
+                    warn!("The screen lock state can't be read on this platform, recording won't pause on lock");
+                    break;
+                },
+                Err(e) => {
+                    error!("Failed to check the screen lock state: {}", e);
+                    continue;
+                },
+            };
+            if !pause_state.set_paused(locked) {
+                continue;
+            }
+
+            let payload = RecordingPausePayload { video_id: video_id.clone(), reason: "screen_locked".to_string() };
+            if locked {
+                info!("Screen locked, pausing the recording");
+                emit_event(&app_handle, "recording-paused", payload);
+            } else {
+                info!("Screen unlocked after {:?} paused in total, resuming the recording", pause_state.total_paused());
+                emit_event(&app_handle, "recording-resumed", payload);
+            }
+        }
+    }.in_current_span());
+}
+
+/// Stops the recording through `stop_all_recordings` once it has recorded `max_duration_secs`.
+/// Time spent paused doesn't count.
+fn spawn_max_duration_timer(app_handle: AppHandle, video_id: String, max_duration_secs: u64, pause_state: Arc<PauseState>, shutdown_flag: Arc<AtomicBool>, cancellation_token: CancellationToken) {
+    tokio::spawn(async move {
+        let max_duration = Duration::from_secs(max_duration_secs);
+        let started_at = Instant::now();
+        loop {
+            let recorded = started_at.elapsed().saturating_sub(pause_state.total_paused());
+            if recorded >= max_duration {
+                break;
+            }
+            // A pause only pushes the limit back, so sleeping for what's left never overshoots it.
+            tokio::select! {
+                _ = cancellation_token.cancelled() => return,
+                _ = tokio::time::sleep(max_duration - recorded) => {},
+            }
+        }
+
+        // The recording was stopped some other way in the meantime.
+        if shutdown_flag.load(Ordering::SeqCst) {
+            return;
+        }
+
+        info!("Recording reached its maximum duration of {}s, stopping", max_duration_secs);
+        emit_event(&app_handle, "max-duration-reached", MaxDurationReachedPayload { video_id, max_duration_secs });
+
+        let state = app_handle.state::<Arc<Mutex<RecordingState>>>();
+        if let Err(e) = stop_all_recordings(app_handle.clone(), state).await {
+            error!("Failed to stop recording at its maximum duration: {}", e);
+        }
+    }.in_current_span());
+}
+
+/// Reports how many audio and video buffers the current (or last) recording dropped because the
+/// ffmpeg writers couldn't keep up.
+#[tauri::command]
+pub async fn get_drop_stats(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<DropStats, RecordingError> {
+    let guard = state.lock().await;
+    Ok(guard.drop_counters.snapshot())
+}
+
+/// What the current recording actually runs with, as opposed to what was requested.
+#[derive(Debug, Serialize, Clone)]
+pub struct ActiveRecordingConfig {
+  pub options: RecordingOptions,
+  pub encoding: EncodingInfo,
+  pub capture: CaptureInfo,
+  pub output_dir: PathBuf,
+  pub audio_chunks_dir: PathBuf,
+  pub video_chunks_dir: PathBuf,
+}
+
+/// The configuration of the recording in progress, with clamped values and fallbacks applied, or
+/// `None` while not recording.
+#[tauri::command]
+pub async fn get_active_recording_config(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<Option<ActiveRecordingConfig>, RecordingError> {
+    let guard = state.lock().await;
+    let (media_process, output_dir) = match (guard.media_process.as_ref(), guard.output_dir.clone()) {
+        (Some(media_process), Some(output_dir)) => (media_process, output_dir),
+        _ => return Ok(None),
+    };
+
+    Ok(media_process.effective_options().map(|options| ActiveRecordingConfig {
+        options,
+        encoding: media_process.encoding.clone(),
+        capture: media_process.capture.clone(),
+        audio_chunks_dir: output_dir.join("chunks/audio"),
+        video_chunks_dir: output_dir.join("chunks/video"),
+        output_dir,
+    }))
+}
+
+/// How full the channels to the ffmpeg writers are; all zeros while not recording.
+#[tauri::command]
+pub async fn get_channel_stats(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<ChannelStats, RecordingError> {
+    let guard = state.lock().await;
+    Ok(guard.media_process.as_ref().map(|media_process| media_process.channel_stats()).unwrap_or_default())
+}
+
+/// Checks the whole recording configuration without starting anything, returning every problem
+/// found rather than stopping at the first.
+#[tauri::command]
+pub async fn validate_recording_options(
+    app_handle: AppHandle,
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    options: RecordingOptions,
+) -> Result<Vec<RecordingOptionProblem>, RecordingError> {
+    let mut problems = Vec::new();
+    let mut problem = |field: &str, message: String| problems.push(RecordingOptionProblem { field: field.to_string(), message });
+
+    if options.test_source && options.capture_window_id.is_some() {
+        problem("capture_window_id", "Windows can't be captured with the test source".to_string());
+    }
+
+    if !options.test_source && !options.audio_name.is_empty() && options.audio_name != "None" {
+        match enumerate_audio_devices() {
+            Ok(devices) if !devices.iter().any(|device| device.name == options.audio_name) => problem("audio_name", format!("Audio device '{}' was not found", options.audio_name)),
+            Ok(_) => {},
+            Err(e) => problem("audio_name", e),
+        }
+    }
+
+    if let Some(audio_device_index) = options.audio_device_index.filter(|_| !options.test_source) {
+        match enumerate_audio_devices() {
+            Ok(devices) if audio_device_index >= devices.len() => problem("audio_device_index", format!("Audio device {} does not exist, {} device(s) found", audio_device_index, devices.len())),
+            Ok(_) => {},
+            Err(e) => problem("audio_device_index", e),
+        }
+    }
+
+    match enumerate_displays() {
+        _ if options.test_source => {},
+        Ok(displays) => {
+            // The frontend sends screens as avfoundation-style names such as "Capture screen 0".
+            let screen_number = options.screen_index.rsplit(' ').next().and_then(|index| index.parse::<usize>().ok());
+            match screen_number {
+                Some(index) if index < displays.len() => {},
+                Some(index) => problem("screen_index", format!("Screen {} does not exist, {} display(s) found", index, displays.len())),
+                None => problem("screen_index", format!("'{}' is not a valid screen", options.screen_index)),
+            }
+
+            if let Some(display_id) = options.display_id {
+                if !displays.iter().any(|display| display.id == display_id) {
+                    problem("display_id", format!("Display {} was not found", display_id));
+                }
+            }
+        },
+        Err(e) => problem("screen_index", e),
+    }
+
+    if let Err(e) = locate_ffmpeg() {
+        problem("ffmpeg", e);
+    }
+
+    if options.upload_enabled {
+        match &options.upload_target {
+            UploadTarget::S3 => {
+                if options.aws_region.trim().is_empty() {
+                    problem("aws_region", "AWS region must not be empty".to_string());
+                }
+                if options.aws_bucket.trim().is_empty() {
+                    problem("aws_bucket", "AWS bucket must not be empty".to_string());
+                }
+            },
+            UploadTarget::Http { .. } => {
+                if let Err(e) = create_uploader(&options) {
+                    problem("upload_target", e);
+                }
+            },
+        }
+    }
+
+    if let Err(e) = validate_extra_ffmpeg_args("extra_audio_ffmpeg_args", &options.extra_audio_ffmpeg_args) {
+        problem("extra_audio_ffmpeg_args", e);
+    }
+    if let Err(e) = validate_extra_ffmpeg_args("extra_video_ffmpeg_args", &options.extra_video_ffmpeg_args) {
+        problem("extra_video_ffmpeg_args", e);
+    }
+
+    if let Err(e) = ChannelFullPolicy::parse(&options.channel_full_policy) {
+        problem("channel_full_policy", e);
+    }
+
+    if options.clock_source != "system" && options.clock_source != "ntp" {
+        problem("clock_source", format!("Unknown clock source '{}', expected system or ntp", options.clock_source));
+    }
+
+    if let Err(e) = keyframe_interval_secs(&options) {
+        problem("keyframe_interval_secs", e);
+    }
+
+    if let Err(e) = validate_output_scale(&options) {
+        problem("output_scale", e);
+    }
+
+    if let Err(e) = validate_output_sample_rate(&options) {
+        problem("output_sample_rate", e);
+    }
+
+    if let Err(e) = validate_aac_profile(&options, None) {
+        problem("aac_profile", e);
+    }
+
+    if let Err(e) = validate_audio_output_format(&options) {
+        problem("audio_output_format", e);
+    }
+
+    if let Err(e) = overlay_filter(&options) {
+        problem("timestamp_overlay", e);
+    }
+
+    if options.thread_queue_size == 0 {
+        problem("thread_queue_size", "Thread queue size must be at least 1".to_string());
+    }
+
+    if let Some(input_probesize) = options.input_probesize.filter(|probesize| *probesize < MIN_INPUT_PROBESIZE) {
+        problem("input_probesize", format!("Probe size of {} bytes is below ffmpeg's minimum of {}", input_probesize, MIN_INPUT_PROBESIZE));
+    }
+
+    if let Some(window_id) = options.capture_window_id.filter(|_| !options.test_source) {
+        match enumerate_windows() {
+            Ok(windows) if !windows.iter().any(|window| window.id == window_id) => problem("capture_window_id", format!("Window {} was not found", window_id)),
+            Ok(_) => {},
+            Err(e) => problem("capture_window_id", e),
+        }
+    }
+
+    if options.max_duration_secs == Some(0) {
+        problem("max_duration_secs", "Maximum duration must be at least 1s".to_string());
+    }
+
+    if options.start_delay_secs > MAX_START_DELAY_SECS {
+        problem("start_delay_secs", format!("Start delay of {}s exceeds the maximum of {}s", options.start_delay_secs, MAX_START_DELAY_SECS));
+    }
+
+    if let Some(output_dir) = requested_output_dir(&options) {
+        if !output_dir.is_dir() {
+            problem("output_dir", format!("Output directory {:?} does not exist, the data directory will be used instead", output_dir));
+        } else if let Err(e) = check_dir_writable(output_dir) {
+            problem("output_dir", format!("Output directory {:?} is not writable, the data directory will be used instead: {}", output_dir, e));
+        }
+    }
+
+    // Recording falls back to the app data directory when none is set, so that is what gets checked.
+    let data_dir = state.lock().await.data_dir.clone().or_else(|| app_handle.path_resolver().app_data_dir());
+    match data_dir {
+        Some(data_dir) => {
+            let writable = std::fs::create_dir_all(&data_dir).and_then(|_| check_dir_writable(&data_dir));
+            if let Err(e) = writable {
+                problem("data_dir", format!("Data directory {:?} is not writable: {}", data_dir, e));
+            }
+        },
+        None => problem("data_dir", "Data directory is not set and the app data directory could not be resolved".to_string()),
+    }
+
+    Ok(problems)
+}
+
+/// Saves the given options as the last-used recording options.
+#[tauri::command]
+pub fn save_recording_options(app_handle: AppHandle, options: RecordingOptions) -> Result<(), String> {
+    let path = recording_options_path(&app_handle)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create config directory: {}", e))?;
+    }
+
+    let json = serde_json::to_string_pretty(&options).map_err(|e| format!("Failed to serialize recording options: {}", e))?;
+    std::fs::write(&path, json).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+/// Loads the last-used recording options, if any were saved. A saved microphone that is no longer
+/// connected is cleared so the default input device is used instead.
+#[tauri::command]
+pub fn load_recording_options(app_handle: AppHandle) -> Result<Option<RecordingOptions>, String> {
+    let path = recording_options_path(&app_handle)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let json = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    let mut options: RecordingOptions = serde_json::from_str(&json).map_err(|e| format!("Failed to parse recording options: {}", e))?;
+
+    if !options.audio_name.is_empty() && options.audio_name != "None" {
+        let devices = enumerate_audio_devices()?;
+        if !devices.iter().any(|device| device.name == options.audio_name) {
+            info!("Saved audio device '{}' is no longer available, clearing it", options.audio_name);
+            options.audio_name.clear();
+        }
+
+        // Indices shift as devices come and go, so only keep one that still points at the saved device.
+        if let Some(audio_device_index) = options.audio_device_index {
+            if devices.get(audio_device_index).map_or(true, |device| device.name != options.audio_name) {
+                options.audio_device_index = None;
+            }
+        }
+    }
+
+    Ok(Some(options))
+}
+
+fn requested_output_dir(options: &RecordingOptions) -> Option<&PathBuf> {
+    options.output_dir.as_ref().filter(|dir| !dir.as_os_str().is_empty())
+}
+
+/// Picks the directory a recording is written to: `options.output_dir` when it exists and is
+/// writable, otherwise the app data directory.
+fn resolve_output_dir(options: &RecordingOptions, data_dir: &Path) -> PathBuf {
+    let output_dir = match requested_output_dir(options) {
+        Some(output_dir) => output_dir,
+        None => return data_dir.to_path_buf(),
+    };
+
+    if !output_dir.is_dir() {
+        warn!("Output directory {:?} does not exist, falling back to {:?}", output_dir, data_dir);
+        return data_dir.to_path_buf();
+    }
+
+    match check_dir_writable(output_dir) {
+        Ok(()) => output_dir.clone(),
+        Err(e) => {
+            warn!("Output directory {:?} is not writable ({}), falling back to {:?}", output_dir, e, data_dir);
+            data_dir.to_path_buf()
+        },
+    }
+}
+
+/// Directory the current or last recording was written to.
+fn current_output_dir(state: &RecordingState) -> Result<PathBuf, String> {
+    state.output_dir.clone()
+        .or_else(|| state.data_dir.clone())
+        .ok_or("Data directory is not set in the recording state".to_string())
+}
+
+fn check_dir_writable(dir: &Path) -> io::Result<()> {
+    let probe_path = dir.join(".write_test");
+    std::fs::write(&probe_path, b"").and_then(|_| std::fs::remove_file(&probe_path))
+}
+
+fn recording_options_path(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    app_handle.path_resolver().app_config_dir()
+        .map(|dir| dir.join(RECORDING_OPTIONS_FILE))
+        .ok_or("Failed to resolve the app config directory".to_string())
+}
+
+/// The chunks directory and upload `video_type` of an extra microphone track.
+pub fn audio_track_video_type(track_index: usize) -> String {
+    format!("audio_{}", track_index)
+}
+
 fn clean_and_create_dir(dir: &Path) -> Result<(), String> {
     if dir.exists() {
         // Instead of just reading the directory, this will also handle subdirectories.
@@ -160,64 +2529,228 @@ fn clean_and_create_dir(dir: &Path) -> Result<(), String> {
     }
 }
 
+#[tracing::instrument(name = "upload_loop", skip_all, fields(video_type = %video_type))]
 async fn start_upload_loop(
     chunks_dir: PathBuf,
     options: RecordingOptions,
+    uploader: Arc<dyn Uploader>,
     video_type: String,
     shutdown_flag: Arc<AtomicBool>,
-    uploading_finished: Arc<AtomicBool>,
+    upload_cancelled: Arc<AtomicBool>,
+    cancellation_token: CancellationToken,
+    failed_uploads: Arc<Mutex<Vec<String>>>,
+    segment_durations: Arc<Mutex<HashMap<String, f64>>>,
+    upload_progress: Arc<UploadProgress>,
+    upload_semaphore: Arc<Semaphore>,
+    app_handle: AppHandle,
 ) -> Result<(), String> {
     let mut watched_segments: HashSet<String> = HashSet::new();
+    let mut discovered_segments: HashSet<String> = HashSet::new();
+    let upload_counters = upload_progress.counters(&video_type);
+    // Segments whose upload failed, keyed to the number of attempts so far and when to retry next.
+    let mut retry_queue: HashMap<String, (u32, Instant)> = HashMap::new();
     let mut is_final_loop = false;
+    let mut init_segment_uploaded = false;
+    let ffmpeg_path = ffmpeg_path_as_str().ok();
+    let poll_interval = Duration::from_millis(options.segment_poll_interval_ms.max(MIN_SEGMENT_POLL_INTERVAL_MS));
+    let segment_list_changed = Arc::new(Notify::new());
+    let _segment_list_watcher = if options.segment_notifications {
+        watch_segment_list(&chunks_dir, Arc::clone(&segment_list_changed))
+    } else {
+        None
+    };
 
     loop {
         let mut upload_tasks = vec![];
+        let mut upload_segments = vec![];
+        if upload_cancelled.load(Ordering::SeqCst) || cancellation_token.is_cancelled() {
+            info!("Upload loop for {} cancelled", video_type);
+            break;
+        }
+
         if shutdown_flag.load(Ordering::SeqCst) {
-            if is_final_loop {
+            if is_final_loop && retry_queue.is_empty() {
                 break;
             }
+            if !is_final_loop {
+                wait_for_segment_list_to_settle(&chunks_dir.join("segment_list.txt")).await;
+            }
             is_final_loop = true;
+            upload_progress.final_loop_started.store(true, Ordering::Relaxed);
         }
 
+        let now = Instant::now();
         let current_segments = load_segment_list(&chunks_dir.join("segment_list.txt"))
             .map_err(|e| e.to_string())?
             .difference(&watched_segments)
+            .filter(|segment| retry_queue.get(*segment).map_or(true, |(_, retry_at)| *retry_at <= now))
             .cloned()
             .collect::<HashSet<String>>();
 
+        // fmp4 segments can't be played without the init segment, which ffmpeg writes before the
+        // first of them and keeps locally for finalizing.
+        let init_segment_path = chunks_dir.join(INIT_SEGMENT_FILE);
+        if !init_segment_uploaded && (!current_segments.is_empty() || is_final_loop) && init_segment_path.is_file() {
+            match uploader.upload(&init_segment_path.to_string_lossy(), &video_type).await {
+                Ok(()) => init_segment_uploaded = true,
+                Err(e) if is_final_loop => {
+                    error!("Failed to upload the {} init segment: {}", video_type, e);
+                    failed_uploads.lock().await.push(INIT_SEGMENT_FILE.to_string());
+                    init_segment_uploaded = true;
+                },
+                Err(e) => warn!("Failed to upload the {} init segment, retrying with the next segments: {}", video_type, e),
+            }
+        }
+
         for segment_filename in &current_segments {
             let segment_path = chunks_dir.join(segment_filename);
             if segment_path.is_file() {
-                let options_clone = options.clone();
+                if discovered_segments.insert(segment_filename.clone()) {
+                    upload_counters.discovered.fetch_add(1, Ordering::Relaxed);
+                }
+
+                let uploader = Arc::clone(&uploader);
                 let video_type_clone = video_type.clone();
                 let segment_path_clone = segment_path.clone();
+                let upload_semaphore = Arc::clone(&upload_semaphore);
+                let upload_cancellation_token = cancellation_token.clone();
+                let segment_durations = Arc::clone(&segment_durations);
+                let ffmpeg_path = ffmpeg_path.clone();
+                let duration_key = format!("{}/{}", video_type, segment_filename);
                 upload_tasks.push(tokio::spawn(async move {
-                    let filepath_str = segment_path_clone.to_str().unwrap_or_default().to_owned();
-                    println!("Uploading video for {}: {}", video_type_clone, filepath_str);
-                    upload_file(Some(options_clone), filepath_str, video_type_clone).await.map(|_| ())
-                }));
+                    let upload = async {
+                        if let Some(ref ffmpeg_path) = ffmpeg_path {
+                            if let Some(duration) = segment_duration_secs(ffmpeg_path, &segment_path_clone).await {
+                                segment_durations.lock().await.insert(duration_key, duration);
+                            }
+                        }
+                        let _permit = upload_semaphore.acquire_owned().await.map_err(|e| e.to_string())?;
+                        let filepath_str = segment_path_clone.to_str().unwrap_or_default().to_owned();
+                        debug!("Uploading video for {}: {}", video_type_clone, filepath_str);
+                        uploader.upload(&filepath_str, &video_type_clone).await.map_err(String::from)
+                    };
+                    tokio::select! {
+                        _ = upload_cancellation_token.cancelled() => Err("Upload cancelled".to_string()),
+                        result = upload => result,
+                    }
+                }.instrument(tracing::info_span!("upload", segment = %segment_filename))));
+                upload_segments.push(segment_filename.clone());
+            } else {
+                watched_segments.insert(segment_filename.clone());
             }
-            watched_segments.insert(segment_filename.clone());
         }
 
         if !upload_tasks.is_empty() {
-            let _ = join_all(upload_tasks).await;
+            upload_counters.in_flight.fetch_add(upload_tasks.len() as u64, Ordering::Relaxed);
+            let results = join_all(upload_tasks).await;
+            upload_counters.in_flight.fetch_sub(results.len() as u64, Ordering::Relaxed);
+
+            if cancellation_token.is_cancelled() {
+                info!("Upload loop for {} cancelled", video_type);
+                break;
+            }
+
+            for (segment_filename, result) in upload_segments.into_iter().zip(results) {
+                let error = match result {
+                    Ok(Ok(())) => {
+                        // The upload has finished reading the file and the segment won't be retried,
+                        // so it is safe to delete now.
+                        if !options.keep_local_chunks {
+                            let segment_path = chunks_dir.join(&segment_filename);
+                            if let Err(e) = tokio::fs::remove_file(&segment_path).await {
+                                error!("Failed to remove {:?} after upload: {}", segment_path, e);
+                            }
+                        }
+                        emit_event(&app_handle, "segment-uploaded", SegmentUploadedPayload {
+                            filename: segment_filename.clone(),
+                            video_type: video_type.clone(),
+                        });
+                        upload_counters.uploaded.fetch_add(1, Ordering::Relaxed);
+                        retry_queue.remove(&segment_filename);
+                        watched_segments.insert(segment_filename);
+                        continue;
+                    },
+                    Ok(Err(e)) => e,
+                    Err(e) => e.to_string(),
+                };
+
+                let attempts = retry_queue.get(&segment_filename).map_or(0, |(attempts, _)| *attempts) + 1;
+                emit_event(&app_handle, "upload-failed", UploadFailedPayload {
+                    filename: segment_filename.clone(),
+                    video_type: video_type.clone(),
+                    error: error.clone(),
+                    attempt: attempts,
+                    will_retry: attempts < UPLOAD_MAX_ATTEMPTS,
+                });
+                if attempts >= UPLOAD_MAX_ATTEMPTS {
+                    error!("Giving up on {} upload {} after {} attempts: {}", video_type, segment_filename, attempts, error);
+                    retry_queue.remove(&segment_filename);
+                    watched_segments.insert(segment_filename.clone());
+                    failed_uploads.lock().await.push(segment_filename);
+                } else {
+                    let backoff = (UPLOAD_INITIAL_BACKOFF * 2u32.pow(attempts - 1)).min(UPLOAD_MAX_BACKOFF);
+                    warn!("Upload of {} {} failed (attempt {}): {}. Retrying in {:?}", video_type, segment_filename, attempts, error, backoff);
+                    retry_queue.insert(segment_filename, (attempts, Instant::now() + backoff));
+                }
+            }
         }
         
-        tokio::time::sleep(Duration::from_millis(50)).await;
+        tokio::select! {
+            _ = cancellation_token.cancelled() => {},
+            _ = segment_list_changed.notified() => {},
+            _ = tokio::time::sleep(poll_interval) => {},
+        }
     }
-    uploading_finished.store(true, Ordering::SeqCst);
     Ok(())
 }
 
+/// Wakes `segment_list_changed` whenever ffmpeg writes the segment list in `chunks_dir`. `None`
+/// when the directory can't be watched, leaving the upload loop to poll.
+fn watch_segment_list(chunks_dir: &Path, segment_list_changed: Arc<Notify>) -> Option<RecommendedWatcher> {
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let lists_changed = event.map_or(false, |event| {
+            event.paths.iter().any(|path| path.file_name().map_or(false, |name| name == "segment_list.txt"))
+        });
+        if lists_changed {
+            segment_list_changed.notify_one();
+        }
+    });
+
+    match watcher.and_then(|mut watcher| watcher.watch(chunks_dir, RecursiveMode::NonRecursive).map(|_| watcher)) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn!("Failed to watch {:?} for new segments, polling instead: {}", chunks_dir, e);
+            None
+        },
+    }
+}
+
+/// Re-reads the segment list until it stops changing, so the final upload pass doesn't miss a
+/// segment ffmpeg is still listing.
+async fn wait_for_segment_list_to_settle(segment_list_path: &Path) {
+    let mut previous = std::fs::read_to_string(segment_list_path).unwrap_or_default();
+
+    for _ in 0..20 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let current = std::fs::read_to_string(segment_list_path).unwrap_or_default();
+        if current == previous {
+            return;
+        }
+        previous = current;
+    }
+
+    warn!("Segment list {:?} kept changing, uploading what is listed so far", segment_list_path);
+}
+
 fn load_segment_list(segment_list_path: &Path) -> io::Result<HashSet<String>> {
     let file = File::open(segment_list_path)?;
     let reader = BufReader::new(file);
+    let pruned = segment_list_path.parent().map(load_pruned_segments).unwrap_or_default();
 
     let mut segments = HashSet::new();
     for line_result in reader.lines() {
         let line = line_result?;
-        if !line.is_empty() {
+        if !line.is_empty() && !pruned.contains(&line) {
             segments.insert(line);
         }
     }
@@ -230,14 +2763,25 @@ async fn prepare_media_recording(
   audio_chunks_dir: &Path,
   screenshot_dir: &Path,
   video_chunks_dir: &Path,
+  webcam_chunks_dir: &Path,
   audio_name: Option<String>,
   max_screen_width: usize,
   max_screen_height: usize,
-) -> Result<MediaRecorder, String> {
+  timing_log: Option<Arc<TimingLog>>,
+  uploader: Option<Arc<dyn Uploader>>,
+  upload_semaphore: Arc<Semaphore>,
+  cancellation_token: CancellationToken,
+  app_handle: AppHandle,
+) -> Result<MediaRecorder, RecordingError> {
   let mut media_recorder = MediaRecorder::new();
+  media_recorder.cancellation_token = cancellation_token;
+  media_recorder.timing_log = timing_log;
+  media_recorder.uploader = uploader;
+  media_recorder.upload_semaphore = upload_semaphore;
   let audio_file_path = audio_chunks_dir.to_str().unwrap();
   let video_file_path = video_chunks_dir.to_str().unwrap();
   let screenshot_dir_path = screenshot_dir.to_str().unwrap();
-  media_recorder.start_media_recording(options.clone(), audio_file_path, screenshot_dir_path, video_file_path, audio_name.as_ref().map(String::as_str), max_screen_width, max_screen_height).await?;
+  let webcam_file_path = webcam_chunks_dir.to_str().unwrap();
+  media_recorder.start_media_recording(options.clone(), audio_file_path, screenshot_dir_path, video_file_path, webcam_file_path, audio_name.as_ref().map(String::as_str), max_screen_width, max_screen_height, app_handle).await?;
   Ok(media_recorder)
 }
\ No newline at end of file
@@ -1,120 +1,523 @@
 use reqwest;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
+use tracing::{debug, error, info, warn};
 
+use crate::encryption::encrypt_file;
+use crate::error::UploadError;
 use crate::recording::RecordingOptions;
 
-pub async fn upload_file(
-    options: Option<RecordingOptions>,
-    file_path: String,
-    file_type: String,
-) -> Result<String, String> {
-    if let Some(ref options) = options {
-        println!("Uploading video...");
+/// Files larger than this are uploaded in parts, so an interrupted upload only repeats one part.
+const MULTIPART_THRESHOLD: u64 = 32 * 1024 * 1024;
+/// S3 requires every part but the last to be at least 5 MiB.
+const MULTIPART_PART_SIZE: u64 = 8 * 1024 * 1024;
+const PART_MAX_ATTEMPTS: u32 = 4;
+const PART_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Kind of the `test_upload_connection` marker, which is keyed outside of any recording.
+const CONNECTION_TEST_KIND: &str = "connection-test";
+
+/// An upload backend. The upload loops retry failed files and limit how many upload at once the
+/// same way whichever one is used.
+pub trait Uploader: Send + Sync {
+    /// Uploads the file at `path` as a `kind` file: a track such as `video` or `audio`, or
+    /// `screenshot`, `manifest` and the like.
+    fn upload<'a>(&'a self, path: &'a str, kind: &'a str) -> BoxFuture<'a, Result<(), UploadError>>;
+}
+
+/// The uploader for the options' `upload_target`, encrypting files first when the options carry
+/// an `encryption_key`.
+pub fn create_uploader(options: &RecordingOptions) -> Result<Arc<dyn Uploader>, UploadError> {
+    let client = reqwest::Client::new();
+    let uploader: Box<dyn Uploader> = match &options.upload_target {
+        UploadTarget::S3 => Box::new(S3Uploader { options: options.clone(), client }),
+        UploadTarget::Http { url, method, headers } => {
+            let url = reqwest::Url::parse(url).map_err(|e| UploadError::InvalidTarget(format!("Invalid upload URL '{}': {}", url, e)))?;
+            if url.cannot_be_a_base() {
+                return Err(UploadError::InvalidTarget(format!("Upload URL '{}' can't take a path", url)));
+            }
+            let method = match method.to_uppercase().as_str() {
+                "PUT" => reqwest::Method::PUT,
+                "POST" => reqwest::Method::POST,
+                _ => return Err(UploadError::InvalidTarget(format!("Unsupported upload method '{}', expected PUT or POST", method))),
+            };
+            Box::new(HttpUploader { options: options.clone(), url, method, headers: headers.clone(), client })
+        },
+    };
+
+    Ok(match options.encryption_key {
+        Some(key) => Arc::new(EncryptingUploader { inner: uploader, key }),
+        None => Arc::from(uploader),
+    })
+}
+
+/// Where recording files are uploaded to, set by `upload_target`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum UploadTarget {
+    /// S3 or an S3-compatible `s3_endpoint`, through presigned requests from the web app.
+    #[default]
+    S3,
+    /// A server of your own. Each file is sent as the request body to `url` with its key,
+    /// `<user_id>/<video_id>/<type>/<filename>`, appended to the path, and the key, type and
+    /// filename in the `X-Cap-File-Key`, `X-Cap-File-Type` and `X-Cap-File-Name` headers.
+    Http {
+        url: String,
+        /// `PUT` or `POST`.
+        #[serde(default = "default_http_method")]
+        method: String,
+        /// Sent with every request, such as an `Authorization` header.
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+}
 
-        let file_name = Path::new(&file_path)
+fn default_http_method() -> String {
+    "PUT".to_string()
+}
+
+/// A recording file about to be uploaded, with the key it's stored under.
+struct UploadFile {
+    path: String,
+    name: String,
+    key: String,
+    kind: String,
+    mime_type: &'static str,
+    size: u64,
+}
+
+impl UploadFile {
+    async fn new(options: &RecordingOptions, path: &str, kind: &str) -> Result<Self, UploadError> {
+        let name = Path::new(path)
             .file_name()
             .and_then(|name| name.to_str())
-            .ok_or("Invalid file path")?
+            .ok_or(UploadError::File(format!("Invalid file path {}", path)))?
             .to_string();
+        let size = tokio::fs::metadata(path).await.map_err(|e| UploadError::File(format!("Failed to read {}: {}", path, e)))?.len();
 
-        let file_key = format!("{}/{}/{}/{}", options.user_id, options.video_id, file_type, file_name);
-
-        let server_url_base: &'static str = dotenv_codegen::dotenv!("NEXT_PUBLIC_URL");
-        let server_url = format!("{}/api/upload/signed", server_url_base);
-
-        // Create the request body for the Next.js handler
-        let body = serde_json::json!({
-            "userId": options.user_id,
-            "fileKey": file_key,
-            "awsBucket": options.aws_bucket,
-            "awsRegion": options.aws_region,
-        });
-
-        let client = reqwest::Client::new();
-        let server_response = client.post(server_url)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send request to Next.js handler: {}", e))?
-            .text()
-            .await
-            .map_err(|e| format!("Failed to read response from Next.js handler: {}", e))?;
-
-        println!("Server response: {}", server_response);
-
-
-        // Deserialize the server response
-        let presigned_post_data: JsonValue = serde_json::from_str(&server_response)
-            .map_err(|e| format!("Failed to deserialize server response: {}", e))?;
-
-        // Construct the multipart form for the file upload
-        let fields = presigned_post_data["presignedPostData"]["fields"].as_object()
-            .ok_or("Fields object is missing or not an object")?;
-        
-        let mut form = reqwest::multipart::Form::new();
-        
-        for (key, value) in fields.iter() {
-            let value_str = value.as_str()
-                .ok_or(format!("Value for key '{}' is not a string", key))?;
-            form = form.text(key.to_string(), value_str.to_owned());
-        }
-
-        println!("Uploading file: {}", file_path);
-        
-        let mime_type = if file_path.to_lowercase().ends_with(".aac") {
-            "audio/aac"
-        } else if file_path.to_lowercase().ends_with(".webm") { 
-            "audio/webm" 
+        let key = if kind == CONNECTION_TEST_KIND {
+            format!("{}/{}/{}", CONNECTION_TEST_KIND, options.user_id, name)
         } else {
-            "video/mp2t"
+            format!("{}/{}/{}/{}", options.user_id, options.video_id, kind, name)
         };
 
-        let file_bytes = tokio::fs::read(&file_path).await.map_err(|e| format!("Failed to read file: {}", e))?;
-        let file_part = reqwest::multipart::Part::bytes(file_bytes)
-            .file_name(file_name.clone())
-            .mime_str(mime_type)
-            .map_err(|e| format!("Error setting MIME type: {}", e))?;
+        Ok(UploadFile {
+            key,
+            mime_type: mime_type(path, kind),
+            path: path.to_string(),
+            name,
+            kind: kind.to_string(),
+            size,
+        })
+    }
+}
+
+/// Encrypts each file with AES-256-GCM before `inner` uploads it with `.enc` appended to its
+/// name. The manifest stays readable so it can tell that the rest of the recording is encrypted.
+struct EncryptingUploader {
+    inner: Box<dyn Uploader>,
+    key: [u8; 32],
+}
+
+impl Uploader for EncryptingUploader {
+    fn upload<'a>(&'a self, path: &'a str, kind: &'a str) -> BoxFuture<'a, Result<(), UploadError>> {
+        Box::pin(async move {
+            if kind == "manifest" {
+                return self.inner.upload(path, kind).await;
+            }
+
+            let encrypted_path = format!("{}.enc", path);
+            let (key, input_path, output_path) = (self.key, path.to_string(), encrypted_path.clone());
+            tokio::task::spawn_blocking(move || encrypt_file(&key, Path::new(&input_path), Path::new(&output_path)))
+                .await
+                .map_err(|e| UploadError::File(format!("Encryption task failed: {}", e)))?
+                .map_err(UploadError::File)?;
+
+            let result = self.inner.upload(&encrypted_path, kind).await;
+            if let Err(e) = std::fs::remove_file(&encrypted_path) {
+                warn!("Failed to remove encrypted file {}: {}", encrypted_path, e);
+            }
+            result
+        })
+    }
+}
+
+/// Uploads to S3 or an S3-compatible `s3_endpoint`, through presigned requests from the web app.
+struct S3Uploader {
+    options: RecordingOptions,
+    client: reqwest::Client,
+}
+
+impl Uploader for S3Uploader {
+    fn upload<'a>(&'a self, path: &'a str, kind: &'a str) -> BoxFuture<'a, Result<(), UploadError>> {
+        Box::pin(async move {
+            info!("Uploading {} from {}...", kind, path);
+            let file = UploadFile::new(&self.options, path, kind).await?;
+            upload_to_s3(&self.client, &self.options, &file).await.map_err(UploadError::Failed)
+        })
+    }
+}
+
+async fn upload_to_s3(client: &reqwest::Client, options: &RecordingOptions, file: &UploadFile) -> Result<(), String> {
+    let server_url_base: &'static str = dotenv_codegen::dotenv!("NEXT_PUBLIC_URL");
+
+    if file.size > MULTIPART_THRESHOLD {
+        upload_multipart(client, options, &file.key, &file.path, file.mime_type).await?;
+        info!("File uploaded successfully in parts");
+        return Ok(());
+    }
+
+    let server_url = format!("{}/api/upload/signed", server_url_base);
+
+    // Create the request body for the Next.js handler
+    let body = serde_json::json!({
+        "userId": options.user_id,
+        "fileKey": file.key,
+        "awsBucket": options.aws_bucket,
+        "awsRegion": options.aws_region,
+        "s3Endpoint": options.s3_endpoint,
+        "s3ForcePathStyle": options.s3_force_path_style,
+    });
+
+    let server_response = client.post(server_url)
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to send request to Next.js handler: {}", e))?
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read response from Next.js handler: {}", e))?;
+
+    debug!("Server response: {}", server_response);
+
+
+    // Deserialize the server response
+    let presigned_post_data: JsonValue = serde_json::from_str(&server_response)
+        .map_err(|e| format!("Failed to deserialize server response: {}", e))?;
+
+    // Construct the multipart form for the file upload
+    let fields = presigned_post_data["presignedPostData"]["fields"].as_object()
+        .ok_or("Fields object is missing or not an object")?;
+    
+    let mut form = reqwest::multipart::Form::new();
+    
+    for (key, value) in fields.iter() {
+        let value_str = value.as_str()
+            .ok_or(format!("Value for key '{}' is not a string", key))?;
+        form = form.text(key.to_string(), value_str.to_owned());
+    }
+
+    debug!("Uploading file: {}", file.path);
+
+    let file_bytes = tokio::fs::read(&file.path).await.map_err(|e| format!("Failed to read file: {}", e))?;
+    let file_part = reqwest::multipart::Part::bytes(file_bytes)
+        .file_name(file.name.clone())
+        .mime_str(file.mime_type)
+        .map_err(|e| format!("Error setting MIME type: {}", e))?;
+
+    form = form.part("file", file_part);
+
+    let post_url = match options.s3_endpoint.as_deref().filter(|endpoint| !endpoint.is_empty()) {
+        Some(endpoint) => s3_endpoint_url(endpoint, &options.aws_bucket, options.s3_force_path_style)?,
+        None => presigned_post_data["presignedPostData"]["url"].as_str()
+            .ok_or("URL is missing or not a string")?
+            .to_string(),
+    };
+
+    debug!("Uploading file to: {}", post_url);
+
+    let response = client.post(&post_url)
+        .multipart(form)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => {
+            info!("File uploaded successfully");
+            Ok(())
+        }
+        Ok(response) => {
+            let status = response.status();
+            let error_body = response.text().await.unwrap_or_else(|_| "<no response body>".to_string());
+            error!("Failed to upload file. Status: {}. Body: {}", status, error_body);
+            Err(format!("Failed to upload file. Status: {}. Body: {}", status, error_body))
+        }
+        Err(e) => Err(format!("Failed to send upload file request: {}", e)),
+    }
+}
 
-        form = form.part("file", file_part);
+/// Sends each file to a server of your own, see `UploadTarget::Http`.
+struct HttpUploader {
+    options: RecordingOptions,
+    url: reqwest::Url,
+    method: reqwest::Method,
+    headers: BTreeMap<String, String>,
+    client: reqwest::Client,
+}
 
-        let post_url = presigned_post_data["presignedPostData"]["url"].as_str()
-            .ok_or("URL is missing or not a string")?;
+impl Uploader for HttpUploader {
+    fn upload<'a>(&'a self, path: &'a str, kind: &'a str) -> BoxFuture<'a, Result<(), UploadError>> {
+        Box::pin(async move {
+            info!("Uploading {} from {}...", kind, path);
+            let file = UploadFile::new(&self.options, path, kind).await?;
 
-        println!("Uploading file to: {}", post_url);
+            // The base URL was checked to take a path when the uploader was created.
+            let mut url = self.url.clone();
+            if let Ok(mut segments) = url.path_segments_mut() {
+                segments.pop_if_empty().extend(file.key.split('/'));
+            }
 
-        let response = client.post(post_url)
-            .multipart(form)
-            .send()
-            .await;
+            debug!("Uploading file to: {}", url);
 
-        match response {
-            Ok(response) if response.status().is_success() => {
-                println!("File uploaded successfully");
+            // Streamed rather than read into memory, as segments from long recordings can be large.
+            let body = tokio::fs::File::open(&file.path).await.map_err(|e| UploadError::File(format!("Failed to open {}: {}", file.path, e)))?;
+            let mut request = self.client.request(self.method.clone(), url)
+                .header(reqwest::header::CONTENT_TYPE, file.mime_type)
+                .header(reqwest::header::CONTENT_LENGTH, file.size)
+                .header("X-Cap-File-Key", &file.key)
+                .header("X-Cap-File-Type", &file.kind)
+                .header("X-Cap-File-Name", &file.name)
+                .body(reqwest::Body::wrap_stream(ReaderStream::new(body)));
+            for (name, value) in &self.headers {
+                request = request.header(name, value);
             }
-            Ok(response) => {
+
+            let response = request.send().await.map_err(|e| UploadError::Failed(format!("Failed to send upload file request: {}", e)))?;
+            if !response.status().is_success() {
                 let status = response.status();
                 let error_body = response.text().await.unwrap_or_else(|_| "<no response body>".to_string());
-                eprintln!("Failed to upload file. Status: {}. Body: {}", status, error_body);
-                return Err(format!("Failed to upload file. Status: {}. Body: {}", status, error_body));
+                error!("Failed to upload file. Status: {}. Body: {}", status, error_body);
+                return Err(UploadError::Failed(format!("Failed to upload file. Status: {}. Body: {}", status, error_body)));
             }
-            Err(e) => {
-                return Err(format!("Failed to send upload file request: {}", e));
+
+            info!("File uploaded successfully");
+            Ok(())
+        })
+    }
+}
+
+fn mime_type(file_path: &str, file_type: &str) -> &'static str {
+    let file_path = file_path.to_lowercase();
+    if file_path.ends_with(".aac") {
+        "audio/aac"
+    } else if file_path.ends_with(".m4a") {
+        "audio/mp4"
+    } else if file_path.ends_with(".txt") {
+        "text/plain"
+    } else if file_path.ends_with(".json") {
+        "application/json"
+    } else if file_path.ends_with(".jpg") {
+        "image/jpeg"
+    } else if file_path.ends_with(".png") {
+        "image/png"
+    } else if file_path.ends_with(".webp") {
+        "image/webp"
+    } else if file_path.ends_with(".webm") {
+        if file_type == "audio" { "audio/webm" } else { "video/webm" }
+    } else if file_path.ends_with(".m4s") {
+        "video/iso.segment"
+    } else if file_path.ends_with(".mp4") {
+        "video/mp4"
+    } else if file_path.ends_with(".enc") {
+        "application/octet-stream"
+    } else {
+        "video/mp2t"
+    }
+}
+
+/// Uploads `file_path` as an S3 multipart upload, retrying each part on its own. The upload is
+/// aborted when a part fails for good, so S3 doesn't keep the parts around.
+async fn upload_multipart(
+    client: &reqwest::Client,
+    options: &RecordingOptions,
+    file_key: &str,
+    file_path: &str,
+    mime_type: &str,
+) -> Result<(), String> {
+    let server_url_base: &'static str = dotenv_codegen::dotenv!("NEXT_PUBLIC_URL");
+    let server_url = format!("{}/api/upload/multipart", server_url_base);
+    let request = |action: &str| serde_json::json!({
+        "action": action,
+        "userId": options.user_id,
+        "fileKey": file_key,
+        "awsBucket": options.aws_bucket,
+        "awsRegion": options.aws_region,
+        "s3Endpoint": options.s3_endpoint,
+        "s3ForcePathStyle": options.s3_force_path_style,
+    });
+
+    let mut initiate = request("initiate");
+    initiate["contentType"] = mime_type.into();
+    let response = multipart_request(client, &server_url, &initiate).await?;
+    let upload_id = response["uploadId"].as_str()
+        .ok_or("Upload ID is missing or not a string")?
+        .to_string();
+
+    debug!("Started multipart upload {} for {}", upload_id, file_key);
+
+    let parts = match upload_parts(client, &server_url, &request("presign-part"), &upload_id, file_path).await {
+        Ok(parts) => parts,
+        Err(e) => {
+            let mut abort = request("abort");
+            abort["uploadId"] = upload_id.clone().into();
+            if let Err(abort_error) = multipart_request(client, &server_url, &abort).await {
+                warn!("Failed to abort multipart upload {}: {}", upload_id, abort_error);
             }
+            return Err(e);
         }
+    };
+
+    let mut complete = request("complete");
+    complete["uploadId"] = upload_id.into();
+    complete["parts"] = parts.into();
+    multipart_request(client, &server_url, &complete).await?;
+
+    Ok(())
+}
 
-        println!("Removing file after upload: {}", file_path);
-        let remove_result = tokio::fs::remove_file(&file_path).await;
-        match &remove_result {
-            Ok(_) => println!("File removed successfully"),
-            Err(e) => println!("Failed to remove file after upload: {}", e),
+/// Uploads the file part by part and returns the `{ PartNumber, ETag }` list S3 needs to
+/// complete the upload.
+async fn upload_parts(
+    client: &reqwest::Client,
+    server_url: &str,
+    presign_request: &JsonValue,
+    upload_id: &str,
+    file_path: &str,
+) -> Result<Vec<JsonValue>, String> {
+    let mut file = tokio::fs::File::open(file_path).await.map_err(|e| format!("Failed to open file: {}", e))?;
+    let mut parts = Vec::new();
+
+    for part_number in 1u32.. {
+        let mut buffer = Vec::with_capacity(MULTIPART_PART_SIZE as usize);
+        (&mut file).take(MULTIPART_PART_SIZE).read_to_end(&mut buffer).await
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        if buffer.is_empty() {
+            break;
         }
-        remove_result.map_err(|e| format!("Failed to remove file after upload: {}", e))?;
 
-        Ok(file_key)
+        let mut presign = presign_request.clone();
+        presign["uploadId"] = upload_id.into();
+        presign["partNumber"] = part_number.into();
+
+        let etag = upload_part(client, server_url, &presign, part_number, Bytes::from(buffer)).await?;
+        parts.push(serde_json::json!({ "PartNumber": part_number, "ETag": etag }));
+    }
+
+    Ok(parts)
+}
+
+/// Uploads one part to a freshly presigned URL, with exponential backoff between attempts.
+async fn upload_part(
+    client: &reqwest::Client,
+    server_url: &str,
+    presign: &JsonValue,
+    part_number: u32,
+    bytes: Bytes,
+) -> Result<String, String> {
+    let mut attempt = 1;
+    loop {
+        let result = async {
+            let response = multipart_request(client, server_url, presign).await?;
+            let url = response["url"].as_str().ok_or("Part URL is missing or not a string")?;
+
+            let response = client.put(url)
+                .body(bytes.clone())
+                .send()
+                .await
+                .map_err(|e| format!("Failed to send part: {}", e))?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_body = response.text().await.unwrap_or_else(|_| "<no response body>".to_string());
+                return Err(format!("Status: {}. Body: {}", status, error_body));
+            }
+
+            response.headers().get("etag")
+                .and_then(|etag| etag.to_str().ok())
+                .map(|etag| etag.to_string())
+                .ok_or("Part response has no ETag".to_string())
+        }.await;
+
+        match result {
+            Ok(etag) => return Ok(etag),
+            Err(e) if attempt < PART_MAX_ATTEMPTS => {
+                let backoff = PART_INITIAL_BACKOFF * 2u32.pow(attempt - 1);
+                warn!("Upload of part {} failed (attempt {}): {}. Retrying in {:?}", part_number, attempt, e, backoff);
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(format!("Failed to upload part {} after {} attempts: {}", part_number, attempt, e)),
+        }
+    }
+}
+
+async fn multipart_request(client: &reqwest::Client, server_url: &str, body: &JsonValue) -> Result<JsonValue, String> {
+    let response = client.post(server_url)
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to send request to multipart upload handler: {}", e))?;
+
+    let status = response.status();
+    let text = response.text().await
+        .map_err(|e| format!("Failed to read response from multipart upload handler: {}", e))?;
+    if !status.is_success() {
+        return Err(format!("Multipart upload handler failed. Status: {}. Body: {}", status, text));
+    }
+
+    serde_json::from_str(&text).map_err(|e| format!("Failed to deserialize multipart upload response: {}", e))
+}
+
+/// Checks the upload configuration by uploading a small marker object through the same path as
+/// the segments, so misconfigured buckets or credentials show up before a recording starts.
+/// Returns the key of the uploaded marker. Uploads can't delete, so the marker stays in the bucket,
+/// under `connection-test/<user_id>/` rather than in the folder of a recording.
+#[tauri::command]
+pub async fn test_upload_connection(options: RecordingOptions) -> Result<String, String> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_millis();
+    let marker_path = std::env::temp_dir().join(format!("cap_connection_test_{}.txt", timestamp));
+    std::fs::write(&marker_path, b"cap upload connection test")
+        .map_err(|e| format!("Failed to write connection test file: {}", e))?;
+
+    let marker_path = marker_path.to_string_lossy().to_string();
+    let result = match create_uploader(&options) {
+        Ok(uploader) => uploader.upload(&marker_path, CONNECTION_TEST_KIND).await,
+        Err(e) => Err(e),
+    };
+
+    let _ = std::fs::remove_file(&marker_path);
+
+    result.map_err(|e| format!("Upload connection test failed: {}", e))?;
+    let file_name = Path::new(&marker_path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let suffix = if options.encryption_key.is_some() { ".enc" } else { "" };
+    Ok(format!("{}/{}/{}{}", CONNECTION_TEST_KIND, options.user_id, file_name, suffix))
+}
+
+/// Builds the bucket URL on a custom S3-compatible endpoint (MinIO, R2, ...), either path-style
+/// (`https://host/bucket`) or virtual-hosted (`https://bucket.host`).
+fn s3_endpoint_url(endpoint: &str, bucket: &str, force_path_style: bool) -> Result<String, String> {
+    let mut url = reqwest::Url::parse(endpoint).map_err(|e| format!("Invalid S3 endpoint '{}': {}", endpoint, e))?;
+
+    if force_path_style {
+        let path = format!("{}/{}", url.path().trim_end_matches('/'), bucket);
+        url.set_path(&path);
     } else {
-        return Err("No recording options provided".to_string());
+        let host = url.host_str().ok_or(format!("S3 endpoint '{}' has no host", endpoint))?;
+        let bucket_host = format!("{}.{}", bucket, host);
+        url.set_host(Some(&bucket_host)).map_err(|e| format!("Invalid S3 endpoint host '{}': {}", bucket_host, e))?;
     }
-}
\ No newline at end of file
+
+    Ok(url.to_string())
+}
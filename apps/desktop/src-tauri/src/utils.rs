@@ -3,11 +3,14 @@ use ffmpeg_sidecar::{
     paths::sidecar_dir,
 };
 use capture::{Capturer, Display};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::panic;
 use std::path::Path;
 use std::thread;
+use std::net::UdpSocket;
 use std::io::ErrorKind::WouldBlock;
+use serde::Serialize;
+use tracing::{debug, error};
 
 #[tauri::command]
 pub fn has_screen_capture_access() -> bool {
@@ -76,13 +79,13 @@ pub fn run_command(command: &str, args: Vec<&str>) -> Result<(String, String), S
     let output = Command::new(command)
         .args(args)
         .output()
-        .expect("Failed to execute command");
+        .map_err(|e| format!("Failed to run {}: {}", command, e))?;
 
     let stdout = String::from_utf8(output.stdout).unwrap_or_else(|_| "".to_string());
     let stderr = String::from_utf8(output.stderr).unwrap_or_else(|_| "".to_string());
 
-    println!("Command output: {}", stdout);
-    println!("Command error: {}", stderr);
+    debug!("Command output: {}", stdout);
+    debug!("Command error: {}", stderr);
 
     Ok((stdout, stderr))
 }
@@ -105,6 +108,23 @@ pub fn ffmpeg_path_as_str() -> Result<String, String> {
     }
 }
 
+/// Resolves the FFmpeg binary `ffmpeg_path_as_str` points at, the bundled one or else the one on
+/// the PATH, and checks it exists and can be executed.
+pub fn locate_ffmpeg() -> Result<String, String> {
+    let path = ffmpeg_path_as_str()?;
+    let resolved = which::which(&path).map_err(|e| {
+        if Path::new(&path).is_absolute() {
+            format!("FFmpeg at {} can't be executed: {}", path, e)
+        } else {
+            "FFmpeg is missing from the app bundle and wasn't found on the PATH, reinstall the app or install FFmpeg".to_string()
+        }
+    })?;
+
+    resolved.to_str()
+        .map(|s| s.to_owned())
+        .ok_or_else(|| "Failed to convert FFmpeg binary path to string".to_string())
+}
+
 pub fn create_named_pipe(path: &str) -> Result<(), nix::Error> {
     use nix::sys::stat;
     use nix::unistd;
@@ -115,4 +135,316 @@ pub fn create_named_pipe(path: &str) -> Result<(), nix::Error> {
 pub fn remove_named_pipe(path: &str) -> Result<(), std::io::Error> {
     std::fs::remove_file(path)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch.
+const NTP_UNIX_EPOCH_OFFSET_SECS: i64 = 2_208_988_800;
+
+/// How far the system clock is behind `server` in milliseconds (negative when it is ahead), from a
+/// single SNTP query. `server` is a host name, with an optional port that defaults to 123.
+pub fn ntp_clock_offset_ms(server: &str, timeout: Duration) -> Result<i64, String> {
+    let address = if server.contains(':') { server.to_string() } else { format!("{}:123", server) };
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("Failed to open a socket: {}", e))?;
+    socket.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    socket.connect(&address).map_err(|e| format!("Failed to reach NTP server {}: {}", address, e))?;
+
+    // Leap indicator 0, version 3, client mode.
+    let mut request = [0u8; 48];
+    request[0] = 0x1B;
+
+    let sent_at = unix_time_ms(SystemTime::now());
+    socket.send(&request).map_err(|e| format!("Failed to query NTP server {}: {}", address, e))?;
+    let mut response = [0u8; 48];
+    let received = socket.recv(&mut response).map_err(|e| format!("No response from NTP server {}: {}", address, e))?;
+    let received_at = unix_time_ms(SystemTime::now());
+
+    if received < response.len() {
+        return Err(format!("Short response from NTP server {}", address));
+    }
+
+    let server_received_at = ntp_timestamp_ms(&response[32..40]);
+    let server_sent_at = ntp_timestamp_ms(&response[40..48]);
+    Ok(((server_received_at - sent_at) + (server_sent_at - received_at)) / 2)
+}
+
+fn ntp_timestamp_ms(timestamp: &[u8]) -> i64 {
+    let seconds = u32::from_be_bytes([timestamp[0], timestamp[1], timestamp[2], timestamp[3]]) as i64;
+    let fraction = u32::from_be_bytes([timestamp[4], timestamp[5], timestamp[6], timestamp[7]]) as i64;
+    (seconds - NTP_UNIX_EPOCH_OFFSET_SECS) * 1000 + ((fraction * 1000) >> 32)
+}
+
+fn unix_time_ms(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_millis() as i64)
+}
+
+/// Returns the space available to unprivileged users on the filesystem containing `path`, in MB,
+/// or `None` when it can't be determined on this platform.
+pub fn available_disk_space_mb(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        nix::sys::statvfs::statvfs(path)
+            .ok()
+            .map(|stats| stats.blocks_available() as u64 * stats.fragment_size() as u64 / (1024 * 1024))
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        windows_disk::free_bytes_available(path).map(|bytes| bytes / (1024 * 1024))
+    }
+
+    #[cfg(not(any(unix, target_os = "windows")))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Returns whether the screen is locked, or `None` when it can't be determined on this platform.
+pub fn is_session_locked() -> Option<bool> {
+    #[cfg(target_os = "macos")]
+    {
+        Some(macos_session::screen_is_locked())
+    }
+
+    // The lock screen runs on the secure desktop, which the session can't open input on.
+    #[cfg(target_os = "windows")]
+    {
+        Some(!windows_session::input_desktop_available())
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "self".to_string());
+        let output = Command::new("loginctl")
+            .args(["show-session", &session, "--property=LockedHint"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        Some(String::from_utf8_lossy(&output.stdout).lines().any(|line| line == "LockedHint=yes"))
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+    {
+        None
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos_session {
+    use std::ffi::c_void;
+    use std::os::raw::c_char;
+
+    type CFTypeRef = *const c_void;
+
+    const CF_STRING_ENCODING_UTF8: u32 = 0x0800_0100;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGSessionCopyCurrentDictionary() -> CFTypeRef;
+    }
+
+    #[link(name = "CoreFoundation", kind = "framework")]
+    extern "C" {
+        fn CFStringCreateWithCString(allocator: CFTypeRef, string: *const c_char, encoding: u32) -> CFTypeRef;
+        fn CFDictionaryGetValue(dictionary: CFTypeRef, key: CFTypeRef) -> CFTypeRef;
+        fn CFBooleanGetValue(boolean: CFTypeRef) -> bool;
+        fn CFRelease(object: CFTypeRef);
+    }
+
+    /// Reads `CGSSessionScreenIsLocked` from the session dictionary, which only has the key while
+    /// the screen is locked.
+    pub fn screen_is_locked() -> bool {
+        unsafe {
+            let session = CGSessionCopyCurrentDictionary();
+            if session.is_null() {
+                return false;
+            }
+            let key = CFStringCreateWithCString(std::ptr::null(), b"CGSSessionScreenIsLocked\0".as_ptr() as *const c_char, CF_STRING_ENCODING_UTF8);
+            let value = if key.is_null() { std::ptr::null() } else { CFDictionaryGetValue(session, key) };
+            let locked = !value.is_null() && CFBooleanGetValue(value);
+            if !key.is_null() {
+                CFRelease(key);
+            }
+            CFRelease(session);
+            locked
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod windows_session {
+    use std::ffi::c_void;
+
+    // DESKTOP_SWITCHDESKTOP
+    const DESKTOP_SWITCH_DESKTOP: u32 = 0x0100;
+
+    #[link(name = "user32")]
+    extern "system" {
+        fn OpenInputDesktop(flags: u32, inherit: i32, desired_access: u32) -> *mut c_void;
+        fn SwitchDesktop(desktop: *mut c_void) -> i32;
+        fn CloseDesktop(desktop: *mut c_void) -> i32;
+    }
+
+    pub fn input_desktop_available() -> bool {
+        unsafe {
+            let desktop = OpenInputDesktop(0, 0, DESKTOP_SWITCH_DESKTOP);
+            if desktop.is_null() {
+                return false;
+            }
+            let available = SwitchDesktop(desktop) != 0;
+            CloseDesktop(desktop);
+            available
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod windows_disk {
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory_name: *const u16,
+            free_bytes_available: *mut u64,
+            total_number_of_bytes: *mut u64,
+            total_number_of_free_bytes: *mut u64,
+        ) -> i32;
+    }
+
+    /// Returns the bytes available to the calling user, which accounts for disk quotas.
+    pub fn free_bytes_available(path: &Path) -> Option<u64> {
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+        let mut free_bytes_available = 0u64;
+        let succeeded = unsafe {
+            GetDiskFreeSpaceExW(
+                wide.as_ptr(),
+                &mut free_bytes_available,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        (succeeded != 0).then_some(free_bytes_available)
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PermissionStatus {
+    pub screen: bool,
+    pub microphone: bool,
+    pub camera: bool,
+}
+
+/// Reports whether screen recording, microphone and camera access have been granted. Without them
+/// macOS hands out black frames and silent audio instead of failing, so this has to be checked up
+/// front. Other platforms don't gate these behind a prompt, so only device availability is checked.
+#[tauri::command]
+pub fn check_permissions() -> PermissionStatus {
+    #[cfg(target_os = "macos")]
+    {
+        PermissionStatus {
+            screen: macos_permissions::screen_capture_granted(),
+            microphone: macos_permissions::media_access_granted(macos_permissions::MediaType::Audio),
+            camera: macos_permissions::media_access_granted(macos_permissions::MediaType::Video),
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    {
+        PermissionStatus {
+            screen: has_screen_capture_access(),
+            microphone: cpal::default_host().default_input_device().is_some(),
+            camera: true,
+        }
+    }
+}
+
+/// Triggers the macOS screen recording and microphone permission prompts. Does nothing on other
+/// platforms.
+#[tauri::command]
+pub fn request_permissions() -> Result<(), String> {
+    #[cfg(target_os = "macos")]
+    {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        macos_permissions::request_screen_capture();
+
+        // There is no prompt API for the microphone without Objective-C blocks, but opening an input
+        // stream makes the system show it.
+        if let Some(device) = cpal::default_host().default_input_device() {
+            let config = device.default_input_config().map_err(|e| format!("Failed to get input config: {}", e))?;
+            let stream = device.build_input_stream_raw(
+                &config.config(),
+                config.sample_format(),
+                |_data, _info| {},
+                |e| error!("Permission prompt stream error: {}", e),
+                None,
+            ).map_err(|e| format!("Failed to open input stream: {}", e))?;
+            stream.play().map_err(|e| format!("Failed to start input stream: {}", e))?;
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+mod macos_permissions {
+    use std::ffi::c_void;
+    use std::os::raw::c_char;
+
+    type Id = *const c_void;
+
+    // AVAuthorizationStatusAuthorized
+    const AUTHORIZATION_STATUS_AUTHORIZED: isize = 3;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGPreflightScreenCaptureAccess() -> bool;
+        fn CGRequestScreenCaptureAccess() -> bool;
+    }
+
+    #[link(name = "AVFoundation", kind = "framework")]
+    extern "C" {
+        static AVMediaTypeAudio: Id;
+        static AVMediaTypeVideo: Id;
+    }
+
+    #[link(name = "objc")]
+    extern "C" {
+        fn objc_getClass(name: *const c_char) -> Id;
+        fn sel_registerName(name: *const c_char) -> Id;
+        fn objc_msgSend();
+    }
+
+    pub enum MediaType {
+        Audio,
+        Video,
+    }
+
+    pub fn screen_capture_granted() -> bool {
+        unsafe { CGPreflightScreenCaptureAccess() }
+    }
+
+    pub fn request_screen_capture() {
+        unsafe { CGRequestScreenCaptureAccess(); }
+    }
+
+    /// Calls `[AVCaptureDevice authorizationStatusForMediaType:]`.
+    pub fn media_access_granted(media_type: MediaType) -> bool {
+        unsafe {
+            let media_type = match media_type {
+                MediaType::Audio => AVMediaTypeAudio,
+                MediaType::Video => AVMediaTypeVideo,
+            };
+            let class = objc_getClass(b"AVCaptureDevice\0".as_ptr() as *const c_char);
+            if class.is_null() {
+                return false;
+            }
+            let selector = sel_registerName(b"authorizationStatusForMediaType:\0".as_ptr() as *const c_char);
+            let send: unsafe extern "C" fn(Id, Id, Id) -> isize = std::mem::transmute(objc_msgSend as unsafe extern "C" fn());
+            send(class, selector, media_type) == AUTHORIZATION_STATUS_AUTHORIZED
+        }
+    }
+}
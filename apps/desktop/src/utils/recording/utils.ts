@@ -8,12 +8,14 @@ export const enumerateAndStoreDevices = async () => {
       video: true,
     });
     const video = await navigator.mediaDevices.enumerateDevices();
-    const audio: string[] = await invoke("enumerate_audio_devices");
+    const audio: { index: number; name: string }[] = await invoke(
+      "enumerate_audio_devices"
+    );
     const videoDevices = video.filter((device) => device.kind === "videoinput");
     const audioDevices = audio.map((device) => {
       return {
-        id: device,
-        label: device,
+        id: device.name,
+        label: device.name,
         kind: "audioinput",
       };
     });
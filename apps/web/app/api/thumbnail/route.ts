@@ -1,6 +1,7 @@
 import { type NextRequest } from "next/server";
 import { S3Client, GetObjectCommand } from "@aws-sdk/client-s3";
 import { getSignedUrl } from "@aws-sdk/s3-request-presigner";
+import { findThumbnailKey } from "@/utils/video/thumbnail/helpers";
 
 export const revalidate = 3500;
 
@@ -43,7 +44,7 @@ export async function GET(request: NextRequest) {
   const fileKeys: FileKey[] = [
     {
       type: "screen",
-      key: `${userId}/${videoId}/screenshot/screen-capture.jpg`,
+      key: await findThumbnailKey({ s3Client, bucket, userId, videoId }),
     },
   ];
 
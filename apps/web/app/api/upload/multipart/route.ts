@@ -0,0 +1,128 @@
+import {
+  S3Client,
+  CreateMultipartUploadCommand,
+  UploadPartCommand,
+  CompleteMultipartUploadCommand,
+  AbortMultipartUploadCommand,
+} from "@aws-sdk/client-s3";
+import { getSignedUrl } from "@aws-sdk/s3-request-presigner";
+import { NextRequest } from "next/server";
+
+function jsonResponse(body: unknown, status = 200) {
+  return new Response(JSON.stringify(body), {
+    status,
+    headers: {
+      "Content-Type": "application/json",
+    },
+  });
+}
+
+// Steps of an S3 multipart upload for files too large to send in one
+// request: "initiate", then "presign-part" for every part (the client PUTs
+// the part to the returned URL), then "complete" or "abort".
+export async function POST(request: NextRequest) {
+  try {
+    const {
+      action,
+      userId,
+      fileKey,
+      awsBucket,
+      awsRegion,
+      contentType,
+      uploadId,
+      partNumber,
+      parts,
+      s3Endpoint,
+      s3ForcePathStyle,
+    } = await request.json();
+
+    if (!action || !userId || !fileKey || !awsBucket || !awsRegion) {
+      console.error(
+        "Missing required fields in /api/upload/multipart/route.ts"
+      );
+
+      return jsonResponse({ error: "Missing required fields" }, 400);
+    }
+
+    if (action !== "initiate" && !uploadId) {
+      return jsonResponse({ error: "Missing uploadId" }, 400);
+    }
+
+    const s3Client = new S3Client({
+      region: process.env.CAP_AWS_REGION || "",
+      credentials: {
+        accessKeyId: process.env.CAP_AWS_ACCESS_KEY || "",
+        secretAccessKey: process.env.CAP_AWS_SECRET_KEY || "",
+      },
+      ...(s3Endpoint ? { endpoint: s3Endpoint } : {}),
+      forcePathStyle: Boolean(s3ForcePathStyle),
+    });
+
+    switch (action) {
+      case "initiate": {
+        const { UploadId } = await s3Client.send(
+          new CreateMultipartUploadCommand({
+            Bucket: awsBucket,
+            Key: fileKey,
+            ContentType: contentType,
+            Metadata: {
+              userid: userId,
+            },
+          })
+        );
+
+        return jsonResponse({ uploadId: UploadId });
+      }
+      case "presign-part": {
+        if (!partNumber) {
+          return jsonResponse({ error: "Missing partNumber" }, 400);
+        }
+
+        const url = await getSignedUrl(
+          s3Client,
+          new UploadPartCommand({
+            Bucket: awsBucket,
+            Key: fileKey,
+            UploadId: uploadId,
+            PartNumber: partNumber,
+          }),
+          { expiresIn: 1800 }
+        );
+
+        return jsonResponse({ url });
+      }
+      case "complete": {
+        if (!Array.isArray(parts) || parts.length === 0) {
+          return jsonResponse({ error: "Missing parts" }, 400);
+        }
+
+        await s3Client.send(
+          new CompleteMultipartUploadCommand({
+            Bucket: awsBucket,
+            Key: fileKey,
+            UploadId: uploadId,
+            MultipartUpload: { Parts: parts },
+          })
+        );
+
+        return jsonResponse({ success: true });
+      }
+      case "abort": {
+        await s3Client.send(
+          new AbortMultipartUploadCommand({
+            Bucket: awsBucket,
+            Key: fileKey,
+            UploadId: uploadId,
+          })
+        );
+
+        return jsonResponse({ success: true });
+      }
+      default:
+        return jsonResponse({ error: `Unknown action: ${action}` }, 400);
+    }
+  } catch (error) {
+    console.error("Error handling multipart upload", error);
+    return jsonResponse({ error: "Error handling multipart upload" }, 500);
+  }
+}
@@ -2,14 +2,6 @@ import { S3Client } from "@aws-sdk/client-s3";
 import { createPresignedPost, PresignedPost } from "@aws-sdk/s3-presigned-post";
 import { NextRequest } from "next/server";
 
-const s3Client = new S3Client({
-  region: process.env.CAP_AWS_REGION || "",
-  credentials: {
-    accessKeyId: process.env.CAP_AWS_ACCESS_KEY || "",
-    secretAccessKey: process.env.CAP_AWS_SECRET_KEY || "",
-  },
-});
-
 export async function POST(request: NextRequest) {
   try {
     const {
@@ -22,6 +14,8 @@ export async function POST(request: NextRequest) {
       audioCodec,
       awsBucket,
       awsRegion,
+      s3Endpoint,
+      s3ForcePathStyle,
     } = await request.json();
 
     if (!userId || !fileKey || !awsBucket || !awsRegion) {
@@ -38,12 +32,34 @@ export async function POST(request: NextRequest) {
       );
     }
 
+    // Custom S3-compatible endpoints, such as MinIO or R2, usually need
+    // path-style addressing as they don't serve buckets from subdomains.
+    const s3Client = new S3Client({
+      region: process.env.CAP_AWS_REGION || "",
+      credentials: {
+        accessKeyId: process.env.CAP_AWS_ACCESS_KEY || "",
+        secretAccessKey: process.env.CAP_AWS_SECRET_KEY || "",
+      },
+      ...(s3Endpoint ? { endpoint: s3Endpoint } : {}),
+      forcePathStyle: Boolean(s3ForcePathStyle),
+    });
+
     const contentType = fileKey.endsWith(".aac")
       ? "audio/aac"
       : fileKey.endsWith(".webm")
       ? "audio/webm"
       : fileKey.endsWith(".mp4")
       ? "video/mp4"
+      : fileKey.endsWith(".json")
+      ? "application/json"
+      : fileKey.endsWith(".jpg")
+      ? "image/jpeg"
+      : fileKey.endsWith(".png")
+      ? "image/png"
+      : fileKey.endsWith(".webp")
+      ? "image/webp"
+      : fileKey.endsWith(".enc")
+      ? "application/octet-stream"
       : "video/mp2t";
 
     const Fields = {
@@ -5,6 +5,7 @@ import { ImageResponse } from "next/og";
 import { NextRequest } from "next/server";
 import { S3Client, GetObjectCommand } from "@aws-sdk/client-s3";
 import { getSignedUrl } from "@aws-sdk/s3-request-presigner";
+import { findThumbnailKey } from "@/utils/video/thumbnail/helpers";
 
 export const runtime = "edge";
 
@@ -59,7 +60,14 @@ export async function GET(req: NextRequest) {
   const fileKeys: FileKey[] = [
     {
       type: "screen",
-      key: `${video.ownerId}/${video.id}/screenshot/screen-capture.jpg`,
+      // ImageResponse can't decode WebP.
+      key: await findThumbnailKey({
+        s3Client,
+        bucket,
+        userId: video.ownerId,
+        videoId: video.id,
+        extensions: ["jpg", "png"],
+      }),
     },
   ];
 
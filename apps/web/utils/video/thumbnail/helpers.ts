@@ -0,0 +1,35 @@
+import { S3Client, HeadObjectCommand } from "@aws-sdk/client-s3";
+
+// The desktop app uploads the thumbnail in the format picked by its
+// screenshot_format option.
+export const THUMBNAIL_EXTENSIONS = ["jpg", "png", "webp"];
+
+export async function findThumbnailKey({
+  s3Client,
+  bucket,
+  userId,
+  videoId,
+  extensions = THUMBNAIL_EXTENSIONS,
+}: {
+  s3Client: S3Client;
+  bucket: string | undefined;
+  userId: string;
+  videoId: string;
+  extensions?: string[];
+}) {
+  const keys = extensions.map(
+    (extension) => `${userId}/${videoId}/screenshot/screen-capture.${extension}`
+  );
+
+  for (const key of keys) {
+    try {
+      await s3Client.send(new HeadObjectCommand({ Bucket: bucket, Key: key }));
+      return key;
+    } catch (error) {
+      continue;
+    }
+  }
+
+  // Nothing uploaded yet, so keep pointing at the default JPEG.
+  return keys[0];
+}